@@ -0,0 +1,20 @@
+use std::{env, path::PathBuf};
+
+use prost::Message;
+
+fn main() {
+    // Embedded migrations are only picked up again when this changes
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=proto");
+
+    // protox compiles the protobuf files so that building does not need protoc
+    let descriptors = protox::compile(["proto/nixdata.proto"], ["proto"]).unwrap();
+    let descriptorfile = PathBuf::from(env::var("OUT_DIR").unwrap()).join("nixdata.bin");
+    std::fs::write(&descriptorfile, descriptors.encode_to_vec()).unwrap();
+    tonic_build::configure()
+        .build_client(false)
+        .file_descriptor_set_path(&descriptorfile)
+        .skip_protoc_run()
+        .compile(&["proto/nixdata.proto"], &["proto"])
+        .unwrap();
+}
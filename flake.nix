@@ -19,7 +19,7 @@
         packages.nix-data-generator = naersk-lib.buildPackage {
           pname = "nix-data-generator";
           root = ./.;
-          nativeBuildInputs = with pkgs; [ makeWrapper ];
+          nativeBuildInputs = with pkgs; [ makeWrapper installShellFiles ];
           buildInputs = with pkgs; [
             openssl
             pkg-config
@@ -27,6 +27,10 @@
           ];
           postInstall = ''
             wrapProgram $out/bin/nix-data-generator --prefix PATH : '${pkgs.lib.makeBinPath [ pkgs.sqlite ]}'
+            installShellCompletion --cmd nix-data-generator \
+              --bash <($out/bin/nix-data-generator completions bash) \
+              --zsh <($out/bin/nix-data-generator completions zsh) \
+              --fish <($out/bin/nix-data-generator completions fish)
           '';
         };
 
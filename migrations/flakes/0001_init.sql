@@ -0,0 +1,16 @@
+-- Schema of flakes.db
+CREATE TABLE "flakes" (
+	"flake"	TEXT NOT NULL,
+	"url"	TEXT,
+	"attribute"	TEXT NOT NULL,
+	"name"	TEXT,
+	"pname"	TEXT,
+	"version"	TEXT,
+	"description"	TEXT,
+	PRIMARY KEY("flake", "attribute")
+);
+CREATE INDEX "flakepnames" ON "flakes" ("pname");
+
+CREATE TABLE "schema_version" (
+	"version"	INTEGER NOT NULL
+);
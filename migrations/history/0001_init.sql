@@ -0,0 +1,14 @@
+-- Schema of history.db, which keeps its rows across runs
+CREATE TABLE "versions" (
+	"channel"	TEXT NOT NULL,
+	"attribute"	TEXT NOT NULL,
+	"version"	TEXT NOT NULL,
+	"first_seen"	TEXT NOT NULL,
+	"last_seen"	TEXT NOT NULL,
+	PRIMARY KEY("channel", "attribute", "version")
+);
+CREATE INDEX "attributes" ON "versions" ("attribute");
+
+CREATE TABLE "schema_version" (
+	"version"	INTEGER NOT NULL
+);
@@ -0,0 +1,131 @@
+-- Schema of the MySQL mirror of nixpkgs.db
+CREATE TABLE `pkgs` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`system`	VARCHAR(255),
+	`pname`	VARCHAR(255),
+	`version`	VARCHAR(255),
+	`systems`	JSON,
+	`repo`	VARCHAR(255),
+	`in_cache`	INTEGER,
+	`outputname`	VARCHAR(255),
+	`outputs`	JSON,
+	PRIMARY KEY(`attribute`),
+	INDEX `pnames` (`pname`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `meta` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`broken`	INTEGER,
+	`insecure`	INTEGER,
+	`unsupported`	INTEGER,
+	`unfree`	INTEGER,
+	`description`	TEXT,
+	`longdescription`	MEDIUMTEXT,
+	`homepage`	TEXT,
+	`maintainers`	JSON,
+	`position`	TEXT,
+	`license`	JSON,
+	`platforms`	JSON,
+	`knownvulnerabilities`	JSON,
+	`mainprogram`	VARCHAR(255),
+	`badplatforms`	JSON,
+	`hydraplatforms`	JSON,
+	`sourceprovenance`	JSON,
+	`changelog`	TEXT,
+	`homepages`	JSON,
+	`position_file`	TEXT,
+	`position_line`	INTEGER,
+	`position_url`	TEXT,
+	PRIMARY KEY(`attribute`),
+	FULLTEXT INDEX `descriptions` (`description`, `longdescription`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `generation_info` (
+	`channel`	TEXT,
+	`version`	TEXT,
+	`variant`	TEXT,
+	`revision`	TEXT,
+	`generated_at`	TEXT NOT NULL,
+	`generator_version`	TEXT NOT NULL,
+	`package_count`	INTEGER NOT NULL
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `vulnerabilities` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`cve`	VARCHAR(64) NOT NULL,
+	`severity`	VARCHAR(32),
+	`url`	TEXT,
+	PRIMARY KEY(`attribute`, `cve`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `upstream` (
+	`pname`	VARCHAR(255) NOT NULL,
+	`project`	VARCHAR(255),
+	`version`	VARCHAR(255),
+	PRIMARY KEY(`pname`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `licenses` (
+	`name`	VARCHAR(255) NOT NULL,
+	`spdxid`	VARCHAR(255),
+	`fullname`	TEXT,
+	`free`	INTEGER,
+	`url`	TEXT,
+	PRIMARY KEY(`name`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `pkglicenses` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`license`	VARCHAR(255) NOT NULL,
+	PRIMARY KEY(`attribute`, `license`),
+	INDEX `pkglicensenames` (`license`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `aliases` (
+	`alias`	VARCHAR(512) NOT NULL,
+	`attribute`	VARCHAR(512) NOT NULL,
+	PRIMARY KEY(`alias`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `teams` (
+	`name`	VARCHAR(255) NOT NULL,
+	`scope`	TEXT,
+	`githubteams`	JSON,
+	PRIMARY KEY(`name`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `teammembers` (
+	`team`	VARCHAR(255) NOT NULL,
+	`github`	VARCHAR(255),
+	`name`	VARCHAR(255),
+	`email`	VARCHAR(255),
+	`matrix`	VARCHAR(255),
+	INDEX `teammemberteams` (`team`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `pkgteams` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`team`	VARCHAR(255) NOT NULL,
+	PRIMARY KEY(`attribute`, `team`),
+	INDEX `pkgteamnames` (`team`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `paths` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`output`	VARCHAR(255) NOT NULL,
+	`path`	TEXT NOT NULL,
+	`hash`	VARCHAR(64) NOT NULL,
+	PRIMARY KEY(`attribute`, `output`),
+	INDEX `hashes` (`hash`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `programs` (
+	`binary`	VARCHAR(191) NOT NULL,
+	`attribute`	VARCHAR(512) NOT NULL,
+	`system`	VARCHAR(64) NOT NULL,
+	PRIMARY KEY(`binary`, `attribute`, `system`)
+) DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE `schema_version` (
+	`version`	INTEGER NOT NULL
+) DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,14 @@
+-- Maintainers of each package as rows, with the members of its teams flattened in
+ALTER TABLE `teammembers` ADD COLUMN `githubid` BIGINT;
+
+CREATE TABLE `pkgmaintainers` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`name`	VARCHAR(255),
+	`github`	VARCHAR(255),
+	`githubid`	BIGINT,
+	`email`	VARCHAR(255),
+	`matrix`	VARCHAR(255),
+	`team`	VARCHAR(255),
+	INDEX `pkgmaintainerattributes` (`attribute`),
+	INDEX `pkgmaintainergithubs` (`github`)
+) DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,6 @@
+-- Packages that could not be parsed, which no other table has
+CREATE TABLE `errors` (
+	`attribute`	VARCHAR(512) NOT NULL,
+	`error`	TEXT NOT NULL,
+	PRIMARY KEY(`attribute`)
+) DEFAULT CHARSET = utf8mb4;
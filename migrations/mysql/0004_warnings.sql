@@ -0,0 +1,9 @@
+-- Package fields whose shape the other tables don't hold, samples is a JSON list of packages
+-- with their value
+CREATE TABLE `warnings` (
+	`field`	VARCHAR(64) NOT NULL,
+	`kind`	VARCHAR(64) NOT NULL,
+	`count`	INTEGER NOT NULL,
+	`samples`	TEXT NOT NULL,
+	PRIMARY KEY(`field`, `kind`)
+) DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,139 @@
+-- Schema of nixpkgs.db
+CREATE TABLE "pkgs" (
+	"attribute"	TEXT NOT NULL UNIQUE,
+	"system"	TEXT,
+	"pname"	TEXT,
+	"version"	TEXT,
+	"systems"	JSON,
+	"repo"	TEXT,
+	"in_cache"	INTEGER,
+	"outputname"	TEXT,
+	"outputs"	JSON,
+	PRIMARY KEY("attribute")
+);
+
+CREATE TABLE "meta" (
+	"attribute"	TEXT NOT NULL UNIQUE,
+	"broken"	INTEGER,
+	"insecure"	INTEGER,
+	"unsupported"	INTEGER,
+	"unfree"	INTEGER,
+	"description"	TEXT,
+	"longdescription"	TEXT,
+	"homepage"	TEXT,
+	"maintainers"	JSON,
+	"position"	TEXT,
+	"license"	JSON,
+	"platforms"	JSON,
+	"knownvulnerabilities"	JSON,
+	"mainprogram"	TEXT,
+	"badplatforms"	JSON,
+	"hydraplatforms"	JSON,
+	"sourceprovenance"	JSON,
+	"changelog"	TEXT,
+	"homepages"	JSON,
+	"position_file"	TEXT,
+	"position_line"	INTEGER,
+	"position_url"	TEXT,
+	FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute"),
+	PRIMARY KEY("attribute")
+);
+
+CREATE TABLE "channel" (
+	"name"	TEXT,
+	"version"	TEXT,
+	"variant"	TEXT,
+	"revision"	TEXT
+);
+
+CREATE UNIQUE INDEX "attributes" ON "pkgs" ("attribute");
+CREATE UNIQUE INDEX "metaattributes" ON "meta" ("attribute");
+CREATE INDEX "pnames" ON "pkgs" ("pname");
+
+CREATE TABLE "vulnerabilities" (
+	"attribute"	TEXT NOT NULL,
+	"cve"	TEXT NOT NULL,
+	"severity"	TEXT,
+	"url"	TEXT,
+	FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute"),
+	PRIMARY KEY("attribute", "cve")
+);
+CREATE INDEX "vulnattributes" ON "vulnerabilities" ("attribute");
+
+CREATE TABLE "upstream" (
+	"pname"	TEXT NOT NULL UNIQUE,
+	"project"	TEXT,
+	"version"	TEXT,
+	PRIMARY KEY("pname")
+);
+
+CREATE TABLE "licenses" (
+	"name"	TEXT NOT NULL UNIQUE,
+	"spdxid"	TEXT,
+	"fullname"	TEXT,
+	"free"	INTEGER,
+	"url"	TEXT,
+	PRIMARY KEY("name")
+);
+
+CREATE TABLE "pkglicenses" (
+	"attribute"	TEXT NOT NULL,
+	"license"	TEXT NOT NULL,
+	FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute"),
+	FOREIGN KEY("license") REFERENCES "licenses"("name"),
+	PRIMARY KEY("attribute", "license")
+);
+CREATE INDEX "pkglicensenames" ON "pkglicenses" ("license");
+
+CREATE TABLE "aliases" (
+	"alias"	TEXT NOT NULL UNIQUE,
+	"attribute"	TEXT NOT NULL,
+	PRIMARY KEY("alias")
+);
+
+CREATE TABLE "teams" (
+	"name"	TEXT NOT NULL UNIQUE,
+	"scope"	TEXT,
+	"githubteams"	JSON,
+	PRIMARY KEY("name")
+);
+
+CREATE TABLE "teammembers" (
+	"team"	TEXT NOT NULL,
+	"github"	TEXT,
+	"name"	TEXT,
+	"email"	TEXT,
+	"matrix"	TEXT,
+	FOREIGN KEY("team") REFERENCES "teams"("name")
+);
+
+CREATE TABLE "pkgteams" (
+	"attribute"	TEXT NOT NULL,
+	"team"	TEXT NOT NULL,
+	FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute"),
+	FOREIGN KEY("team") REFERENCES "teams"("name"),
+	PRIMARY KEY("attribute", "team")
+);
+CREATE INDEX "pkgteamnames" ON "pkgteams" ("team");
+
+CREATE TABLE "paths" (
+	"attribute"	TEXT NOT NULL,
+	"output"	TEXT NOT NULL,
+	"path"	TEXT NOT NULL,
+	"hash"	TEXT NOT NULL,
+	FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute"),
+	PRIMARY KEY("attribute", "output")
+);
+CREATE INDEX "hashes" ON "paths" ("hash");
+
+CREATE TABLE "programs" (
+	"binary"	TEXT NOT NULL,
+	"attribute"	TEXT NOT NULL,
+	"system"	TEXT NOT NULL,
+	PRIMARY KEY("binary", "attribute", "system")
+);
+CREATE INDEX "programbinaries" ON "programs" ("binary");
+
+CREATE TABLE "schema_version" (
+	"version"	INTEGER NOT NULL
+);
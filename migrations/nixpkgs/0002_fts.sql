@@ -0,0 +1,8 @@
+-- Full-text search over package names and descriptions, filled after pkgs and meta
+CREATE VIRTUAL TABLE "pkgs_fts" USING fts5(
+	"attribute",
+	"pname",
+	"description",
+	"longdescription",
+	prefix = '2 3'
+);
@@ -0,0 +1,12 @@
+-- Replaces the channel table with provenance of the whole generation
+CREATE TABLE "generation_info" (
+	"channel"	TEXT,
+	"version"	TEXT,
+	"variant"	TEXT,
+	"revision"	TEXT,
+	"generated_at"	TEXT NOT NULL,
+	"generator_version"	TEXT NOT NULL,
+	"package_count"	INTEGER NOT NULL
+);
+
+DROP TABLE "channel";
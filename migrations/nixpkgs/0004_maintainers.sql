@@ -0,0 +1,15 @@
+-- Maintainers of each package as rows, with the members of its teams flattened in
+ALTER TABLE "teammembers" ADD COLUMN "githubid" INTEGER;
+
+CREATE TABLE "pkgmaintainers" (
+	"attribute"	TEXT NOT NULL,
+	"name"	TEXT,
+	"github"	TEXT,
+	"githubid"	INTEGER,
+	"email"	TEXT,
+	"matrix"	TEXT,
+	"team"	TEXT,
+	FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute")
+);
+CREATE INDEX "pkgmaintainerattributes" ON "pkgmaintainers" ("attribute");
+CREATE INDEX "pkgmaintainergithubs" ON "pkgmaintainers" ("github");
@@ -0,0 +1,6 @@
+-- Packages that could not be parsed, which no other table has
+CREATE TABLE "errors" (
+	"attribute"	TEXT NOT NULL UNIQUE,
+	"error"	TEXT NOT NULL,
+	PRIMARY KEY("attribute")
+);
@@ -0,0 +1,9 @@
+-- Package fields whose shape the other tables don't hold, samples is a JSON list of packages
+-- with their value
+CREATE TABLE "warnings" (
+	"field"	TEXT NOT NULL,
+	"kind"	TEXT NOT NULL,
+	"count"	INTEGER NOT NULL,
+	"samples"	TEXT NOT NULL,
+	PRIMARY KEY("field","kind")
+);
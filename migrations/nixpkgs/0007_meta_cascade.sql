@@ -0,0 +1,32 @@
+-- Deleting a package deletes its metadata, SQLite can only change a foreign key by recreating
+-- the table
+CREATE TABLE "meta_cascade" (
+	"attribute"	TEXT NOT NULL UNIQUE,
+	"broken"	INTEGER,
+	"insecure"	INTEGER,
+	"unsupported"	INTEGER,
+	"unfree"	INTEGER,
+	"description"	TEXT,
+	"longdescription"	TEXT,
+	"homepage"	TEXT,
+	"maintainers"	JSON,
+	"position"	TEXT,
+	"license"	JSON,
+	"platforms"	JSON,
+	"knownvulnerabilities"	JSON,
+	"mainprogram"	TEXT,
+	"badplatforms"	JSON,
+	"hydraplatforms"	JSON,
+	"sourceprovenance"	JSON,
+	"changelog"	TEXT,
+	"homepages"	JSON,
+	"position_file"	TEXT,
+	"position_line"	INTEGER,
+	"position_url"	TEXT,
+	FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute") ON DELETE CASCADE,
+	PRIMARY KEY("attribute")
+);
+INSERT INTO "meta_cascade" SELECT * FROM "meta";
+DROP TABLE "meta";
+ALTER TABLE "meta_cascade" RENAME TO "meta";
+CREATE UNIQUE INDEX "metaattributes" ON "meta" ("attribute");
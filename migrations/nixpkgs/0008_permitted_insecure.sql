@@ -0,0 +1,3 @@
+-- Name of an insecure package as permittedInsecurePackages lists it, its advisories are in
+-- knownvulnerabilities
+ALTER TABLE "meta" ADD COLUMN "permitted_insecure" TEXT;
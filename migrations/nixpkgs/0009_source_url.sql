@@ -0,0 +1,2 @@
+-- Named for what it links to, the package source on GitHub at its position
+ALTER TABLE "meta" RENAME COLUMN "position_url" TO "source_url";
@@ -0,0 +1,16 @@
+-- Schema of nixosoptions.db and darwinoptions.db
+CREATE TABLE "options" (
+	"attribute"	TEXT NOT NULL UNIQUE,
+	"description"	TEXT,
+	"type"	TEXT,
+	"default"	TEXT,
+	"example"	TEXT,
+	"declarations"	JSON,
+	"readonly"	INTEGER,
+	PRIMARY KEY("attribute")
+);
+CREATE UNIQUE INDEX "attributes" ON "options" ("attribute");
+
+CREATE TABLE "schema_version" (
+	"version"	INTEGER NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Schema of nixpkgs_versions.db
+CREATE TABLE "pkgs" (
+	"attribute"	TEXT NOT NULL UNIQUE,
+	"pname"	TEXT,
+	"version"	TEXT,
+	PRIMARY KEY("attribute")
+);
+CREATE UNIQUE INDEX "attributes" ON "pkgs" ("attribute");
+CREATE INDEX "pnames" ON "pkgs" ("attribute");
+
+CREATE TABLE "schema_version" (
+	"version"	INTEGER NOT NULL
+);
@@ -0,0 +1,366 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{channel, models::NixosPkg};
+
+/// NVD CVE API
+const NVD_URL: &str = "https://services.nvd.nist.gov/rest/json/cves/2.0";
+
+/// Largest page the NVD API hands out
+const NVD_PAGE_SIZE: usize = 2000;
+
+/// How long the downloaded advisories are reused before fetching them again
+const NVD_CACHE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A CVE reduced to what is needed to match it against packages
+#[derive(Debug, Serialize, Deserialize)]
+struct Advisory {
+    id: String,
+    severity: Option<String>,
+    matches: Vec<CpeMatch>,
+}
+
+/// A vulnerable product version or version range
+#[derive(Debug, Serialize, Deserialize)]
+struct CpeMatch {
+    product: String,
+    /// Exact version, `None` if the range below applies
+    version: Option<String>,
+    startincluding: Option<String>,
+    startexcluding: Option<String>,
+    endincluding: Option<String>,
+    endexcluding: Option<String>,
+}
+
+/// A CVE matched to a package
+pub struct Vulnerability<'a> {
+    pub attribute: &'a str,
+    pub cve: String,
+    pub severity: Option<String>,
+}
+
+impl Vulnerability<'_> {
+    pub fn url(&self) -> String {
+        format!("https://nvd.nist.gov/vuln/detail/{}", self.cve)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NvdPage {
+    total_results: usize,
+    vulnerabilities: Vec<NvdVulnerability>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NvdVulnerability {
+    cve: NvdCve,
+}
+
+#[derive(Debug, Deserialize)]
+struct NvdCve {
+    id: String,
+    metrics: Option<Value>,
+    #[serde(default)]
+    configurations: Vec<NvdConfiguration>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NvdConfiguration {
+    #[serde(default)]
+    nodes: Vec<NvdNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NvdNode {
+    #[serde(default)]
+    cpe_match: Vec<NvdCpeMatch>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NvdCpeMatch {
+    vulnerable: bool,
+    criteria: String,
+    version_start_including: Option<String>,
+    version_start_excluding: Option<String>,
+    version_end_including: Option<String>,
+    version_end_excluding: Option<String>,
+}
+
+impl NvdCve {
+    /// Highest-version CVSS severity available
+    fn severity(&self) -> Option<String> {
+        let metrics = self.metrics.as_ref()?;
+        [
+            "cvssMetricV40",
+            "cvssMetricV31",
+            "cvssMetricV30",
+            "cvssMetricV2",
+        ]
+        .iter()
+        .find_map(|version| {
+            let metric = metrics.get(version)?.get(0)?;
+            metric
+                .get("cvssData")
+                .and_then(|x| x.get("baseSeverity"))
+                .or_else(|| metric.get("baseSeverity"))
+                .and_then(|x| x.as_str())
+                .map(|x| x.to_string())
+        })
+    }
+
+    fn advisory(self) -> Option<Advisory> {
+        let severity = self.severity();
+        let matches = self
+            .configurations
+            .into_iter()
+            .flat_map(|x| x.nodes)
+            .flat_map(|x| x.cpe_match)
+            .filter(|x| x.vulnerable)
+            .filter_map(|x| {
+                // cpe:2.3:<part>:<vendor>:<product>:<version>:...
+                let mut fields = x.criteria.split(':').skip(4);
+                let product = fields.next()?.to_lowercase();
+                let version = fields.next()?;
+                Some(CpeMatch {
+                    product,
+                    version: match version {
+                        "*" | "-" => None,
+                        x => Some(x.to_string()),
+                    },
+                    startincluding: x.version_start_including,
+                    startexcluding: x.version_start_excluding,
+                    endincluding: x.version_end_including,
+                    endexcluding: x.version_end_excluding,
+                })
+            })
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            None
+        } else {
+            Some(Advisory {
+                id: self.id,
+                severity,
+                matches,
+            })
+        }
+    }
+}
+
+impl CpeMatch {
+    fn matches(&self, version: &str) -> bool {
+        if let Some(x) = &self.version {
+            return compareversions(version, x) == Ordering::Equal;
+        }
+        let bounded = self.startincluding.is_some()
+            || self.startexcluding.is_some()
+            || self.endincluding.is_some()
+            || self.endexcluding.is_some();
+        bounded
+            && self
+                .startincluding
+                .as_ref()
+                .is_none_or(|x| compareversions(version, x) != Ordering::Less)
+            && self
+                .startexcluding
+                .as_ref()
+                .is_none_or(|x| compareversions(version, x) == Ordering::Greater)
+            && self
+                .endincluding
+                .as_ref()
+                .is_none_or(|x| compareversions(version, x) != Ordering::Greater)
+            && self
+                .endexcluding
+                .as_ref()
+                .is_none_or(|x| compareversions(version, x) == Ordering::Less)
+    }
+}
+
+/// Compares versions component by component like `builtins.compareVersions`
+pub(crate) fn compareversions(a: &str, b: &str) -> Ordering {
+    /// Splits into runs of digits and runs of other characters, dropping `.` and `-`
+    fn components(x: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let mut rest = x;
+        while let Some(c) = rest.chars().next() {
+            if c == '.' || c == '-' {
+                rest = &rest[1..];
+                continue;
+            }
+            let digit = c.is_ascii_digit();
+            let end = rest
+                .find(|x: char| x == '.' || x == '-' || x.is_ascii_digit() != digit)
+                .unwrap_or(rest.len());
+            parts.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        parts
+    }
+
+    /// Whether component `x` sorts before `y`, a missing component being `""`
+    fn less(x: &str, y: &str) -> bool {
+        match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x < y,
+            (_, Ok(_)) if x.is_empty() => true,
+            // "pre" sorts before everything, even a missing component
+            _ if x == "pre" && y != "pre" => true,
+            _ if y == "pre" => false,
+            // Strings sort before numbers, 2.3a before 2.3.1
+            (_, Ok(_)) => true,
+            (Ok(_), _) => false,
+            _ => x < y,
+        }
+    }
+
+    let (a, b) = (components(a), components(b));
+    for i in 0..a.len().max(b.len()) {
+        let (x, y) = (
+            a.get(i).copied().unwrap_or(""),
+            b.get(i).copied().unwrap_or(""),
+        );
+        if less(x, y) {
+            return Ordering::Less;
+        }
+        if less(y, x) {
+            return Ordering::Greater;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Downloads every CVE from the NVD, keeping only those with CPE configurations
+async fn download(apikey: Option<&str>, proxy: Option<&str>) -> Result<Vec<Advisory>> {
+    let client = channel::clientbuilder(proxy)?
+        .timeout(Duration::from_secs(300))
+        .build()?;
+    // The NVD allows 5 requests per 30 seconds without an API key and 50 with one
+    let delay = Duration::from_millis(if apikey.is_some() { 700 } else { 6500 });
+    let mut advisories = Vec::new();
+    let mut start = 0;
+    loop {
+        debug!("Downloading CVEs {} to {}", start, start + NVD_PAGE_SIZE);
+        let mut req = client.get(NVD_URL).query(&[
+            ("startIndex", start.to_string()),
+            ("resultsPerPage", NVD_PAGE_SIZE.to_string()),
+        ]);
+        if let Some(key) = apikey {
+            req = req.header("apiKey", key);
+        }
+        let resp = req.send().await?.error_for_status()?;
+        let page: NvdPage = serde_json::from_slice(&resp.bytes().await?)?;
+        let count = page.vulnerabilities.len();
+        advisories.extend(
+            page.vulnerabilities
+                .into_iter()
+                .filter_map(|x| x.cve.advisory()),
+        );
+        start += count;
+        if count == 0 || start >= page.total_results {
+            break;
+        }
+        tokio::time::sleep(delay).await;
+    }
+    Ok(advisories)
+}
+
+/// Loads the advisories cached in `sourcedir`, refreshing them from the NVD when stale
+async fn advisories(
+    sourcedir: &str,
+    apikey: Option<&str>,
+    proxy: Option<&str>,
+) -> Result<Vec<Advisory>> {
+    let cachefile = format!("{}/nvd.json", sourcedir);
+    let fresh = fs::metadata(&cachefile)
+        .and_then(|x| x.modified())
+        .ok()
+        .and_then(|x| SystemTime::now().duration_since(x).ok())
+        .is_some_and(|x| x < NVD_CACHE_AGE);
+    if fresh && Path::new(&cachefile).exists() {
+        debug!("Using cached advisories from {}", cachefile);
+        return Ok(serde_json::from_reader(BufReader::new(File::open(
+            &cachefile,
+        )?))?);
+    }
+
+    info!("Downloading advisories from the NVD, this takes a while");
+    let advisories = download(apikey, proxy).await?;
+    serde_json::to_writer(BufWriter::new(File::create(&cachefile)?), &advisories)?;
+    Ok(advisories)
+}
+
+/// Matches the pname and version of `packages` against the CPE products of known CVEs
+pub async fn vulnerabilities<'a>(
+    sourcedir: &str,
+    apikey: Option<&str>,
+    packages: &[(&'a String, &'a NixosPkg)],
+    proxy: Option<&str>,
+) -> Result<Vec<Vulnerability<'a>>> {
+    let advisories = advisories(sourcedir, apikey, proxy).await?;
+    let mut byproduct: HashMap<&str, Vec<(&Advisory, &CpeMatch)>> = HashMap::new();
+    for advisory in &advisories {
+        for cpe in &advisory.matches {
+            byproduct
+                .entry(cpe.product.as_str())
+                .or_default()
+                .push((advisory, cpe));
+        }
+    }
+
+    let mut found = Vec::new();
+    for (pkg, data) in packages {
+        // CPE products use underscores where pnames use dashes
+        let product = data.pname.to_lowercase().replace('-', "_");
+        let Some(candidates) = byproduct.get(product.as_str()) else {
+            continue;
+        };
+        let mut ids = candidates
+            .iter()
+            .filter(|(_, cpe)| cpe.matches(&data.version))
+            .map(|(advisory, _)| *advisory)
+            .collect::<Vec<_>>();
+        ids.sort_by(|a, b| a.id.cmp(&b.id));
+        ids.dedup_by(|a, b| a.id == b.id);
+        for advisory in ids {
+            found.push(Vulnerability {
+                attribute: pkg.as_str(),
+                cve: advisory.id.to_string(),
+                severity: advisory.severity.clone(),
+            });
+        }
+    }
+    info!("Found {} vulnerabilities", found.len());
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::compareversions;
+
+    #[test]
+    fn prerelease_before_release() {
+        assert_eq!(compareversions("2.3pre1", "2.3"), Ordering::Less);
+        assert_eq!(compareversions("2.3", "2.3pre1"), Ordering::Greater);
+    }
+
+    #[test]
+    fn missing_component_before_number() {
+        assert_eq!(compareversions("1.0", "1.0.1"), Ordering::Less);
+        assert_eq!(compareversions("1.0.1", "1.0"), Ordering::Greater);
+        assert_eq!(compareversions("1.0", "1.0"), Ordering::Equal);
+    }
+}
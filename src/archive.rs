@@ -0,0 +1,41 @@
+use std::{fs, path::Path, time::SystemTime};
+
+use anyhow::Result;
+use log::debug;
+
+/// Directory next to the databases the previous ones are archived in
+const ARCHIVE_DIR: &str = "archive";
+
+/// Copies `dbfile`, built from `version`, to `archive/<stem>-<version>.db` next to it and removes
+/// all but the newest `keep` archives of `stem`
+pub fn archive(dbfile: &str, stem: &str, version: &str, keep: usize) -> Result<()> {
+    let dir = Path::new(dbfile)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(ARCHIVE_DIR);
+    fs::create_dir_all(&dir)?;
+    let archived = dir.join(format!("{}-{}.db", stem, version));
+    debug!("Archiving {} to {}", dbfile, archived.display());
+    // Copied rather than moved, the database is updated in place when it can be
+    fs::copy(dbfile, &archived)?;
+
+    let prefix = format!("{}-", stem);
+    let mut archives = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with(&prefix) && name.ends_with(".db") {
+            let modified = entry
+                .metadata()?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            archives.push((modified, entry.path()));
+        }
+    }
+    archives.sort_by_key(|x| std::cmp::Reverse(x.0));
+    for (_, path) in archives.into_iter().skip(keep) {
+        debug!("Removing archived {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
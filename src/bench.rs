@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+
+use crate::{
+    builddb, channel, daemon, progress::Progress, source, stream::PackageStream, BuildConfig,
+    Duplicates, Source,
+};
+
+/// Time spent in each phase of a build, in the order they ran
+#[derive(Default)]
+pub struct Timings(Vec<(&'static str, Duration)>);
+
+impl Timings {
+    /// Records `phase` as having run since `start`, returning when it ended
+    pub fn record(&mut self, phase: &'static str, start: Instant) -> Instant {
+        let now = Instant::now();
+        self.0.push((phase, now - start));
+        daemon::alive();
+        now
+    }
+
+    fn extend(&mut self, other: Timings) {
+        self.0.extend(other.0);
+    }
+}
+
+/// Builds `nixpkgs.db` into `<sourcedir>/bench` from `packages`, or else from the packages of
+/// the latest release of `channel` kept in `sourcedir` between runs, and prints how long each
+/// phase took
+pub async fn bench(
+    mirrors: &channel::Mirrors,
+    channel: Option<&str>,
+    packages: Option<&str>,
+    sourcedir: &str,
+    batchsize: usize,
+) -> Result<()> {
+    let mut timings = Timings::default();
+    let (name, file) = match (packages, channel) {
+        (Some(file), _) => (file.to_string(), file.to_string()),
+        (None, Some(channel)) => {
+            let file = format!("{}/packages.json.br", sourcedir);
+            if Path::new(&file).exists() {
+                info!("Using cached {}", file);
+            } else {
+                let release = mirrors
+                    .latestrelease(channel)
+                    .await?
+                    .ok_or_else(|| anyhow!("Could not find latest nixpkgs version"))?;
+                info!("Downloading packages.json.br of {}", release.name);
+                let start = Instant::now();
+                release
+                    .downloadto(mirrors, "packages.json.br", &file, None, Progress::Hidden)
+                    .await
+                    .context("Failed to download latest packages.json")?;
+                timings.record("download", start);
+            }
+            (channel.to_string(), file)
+        }
+        (None, None) => return Err(anyhow!("Either a channel or a packages file is needed")),
+    };
+
+    // Read on its own instead of streamed into the inserts so both can be timed
+    let start = Instant::now();
+    let mut stream = PackageStream::parse(source::open(&file)?);
+    let mut parsed = HashMap::new();
+    while let Some((pkg, data)) = stream.next().await {
+        parsed.insert(pkg, data);
+    }
+    stream.finish().await.context("Failed to parse packages")?;
+    timings.record("parse", start);
+    let count = parsed.len();
+
+    let outdir = format!("{}/bench", sourcedir);
+    if Path::new(&outdir).exists() {
+        fs::remove_dir_all(&outdir)?;
+    }
+    fs::create_dir_all(&outdir)?;
+    let source = Source {
+        name,
+        version: String::new(),
+        revision: None,
+        path: None,
+    };
+    let config = BuildConfig {
+        systems: Vec::new(),
+        filtersystems: false,
+        splitbysystem: false,
+        nur: None,
+        cache: None,
+        advisories: false,
+        nvdapikey: None,
+        repology: false,
+        proxy: None,
+        aliases: None,
+        programs: false,
+        mysql: None,
+        duckdb: false,
+        exports: Vec::new(),
+        compress: None,
+        changelog: None,
+        addedremoved: false,
+        history: None,
+        feed: None,
+        publish: Vec::new(),
+        webhooks: Vec::new(),
+        chat: Default::default(),
+        batchsize,
+        progress: Progress::Hidden,
+        dbname: "nixpkgs.db".to_string(),
+        force: true,
+        check: false,
+        strict: None,
+        duplicates: Duplicates::FirstWins,
+        keep: None,
+        events: Default::default(),
+    };
+    timings.extend(
+        builddb(
+            &outdir,
+            &outdir,
+            &source,
+            PackageStream::from_map(parsed),
+            &config,
+            None,
+        )
+        .await?,
+    );
+
+    let total = timings.0.iter().map(|x| x.1).sum::<Duration>();
+    println!("{} packages, batch size {}", count, batchsize);
+    for (phase, duration) in timings.0 {
+        println!("{:<10} {:>9.3}s", phase, duration.as_secs_f64());
+    }
+    println!("{:<10} {:>9.3}s", "total", total.as_secs_f64());
+    Ok(())
+}
@@ -0,0 +1,337 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::Url;
+use tokio::sync::mpsc;
+
+use crate::{
+    chat::{Chat, Matrix},
+    eval::Evaluator,
+    events::Event,
+    publish::Target,
+    source::PackageSource,
+    ChangelogFormat, Compression, Duplicates, Export, Format, GenerateConfig, GenerationReport,
+};
+
+/// Sets up a generation for library users, starting from the defaults of the generate subcommand.
+/// Settings added later get a method of their own, so code using it keeps compiling.
+pub struct GeneratorBuilder {
+    config: GenerateConfig,
+}
+
+impl GeneratorBuilder {
+    /// Generates into the source directory `src`, which holds the version markers and downloads
+    pub fn new(src: &str) -> Self {
+        GeneratorBuilder {
+            config: GenerateConfig::new(src),
+        }
+    }
+
+    /// Builds `channel`, each channel is built into its own subdirectory when there are several
+    pub fn channel(mut self, channel: &str) -> Self {
+        self.config.channels.push(channel.to_string());
+        self
+    }
+
+    /// Also merges every channel into one nixpkgs_combined.db with a channel column
+    pub fn combined(mut self, combined: bool) -> Self {
+        self.config.combined = combined;
+        self
+    }
+
+    /// Builds the nixpkgs git revision `rev`, from the release of the only channel that has it or
+    /// else by evaluating it from GitHub. Generating fails when a flake, a nixpkgs path or a
+    /// source is set as well.
+    pub fn rev(mut self, rev: &str) -> Self {
+        self.config.rev = Some(rev.to_string());
+        self
+    }
+
+    /// Evaluates the nixpkgs flake reference `flake` instead of a channel
+    pub fn flake(mut self, flake: &str) -> Self {
+        self.config.flake = Some(flake.to_string());
+        self
+    }
+
+    /// Evaluates the local nixpkgs checkout `path` instead of a channel
+    pub fn nixpkgspath(mut self, path: &str) -> Self {
+        self.config.nixpkgspath = Some(path.to_string());
+        self
+    }
+
+    /// Builds `source` instead of a channel
+    pub fn source(mut self, source: impl PackageSource + 'static) -> Self {
+        self.config.source = Some(Box::new(source));
+        self
+    }
+
+    /// Evaluates revisions, flakes and checkouts with `evaluator`, nix-env by default
+    pub fn evaluator(mut self, evaluator: Evaluator) -> Self {
+        self.config.evaluator = evaluator;
+        self
+    }
+
+    /// Records the availability of packages on `system`, the first supported one becomes their
+    /// system
+    pub fn system(mut self, system: &str) -> Self {
+        self.config.systems.push(system.to_string());
+        self
+    }
+
+    /// Only keeps packages available on at least one of the systems
+    pub fn filtersystems(mut self, filter: bool) -> Self {
+        self.config.filtersystems = filter;
+        self
+    }
+
+    /// Also writes a database per system with only its packages
+    pub fn splitbysystem(mut self, split: bool) -> Self {
+        self.config.splitbysystem = split;
+        self
+    }
+
+    /// Writes the databases to `output` rather than to the source directory
+    pub fn output(mut self, output: &str) -> Self {
+        self.config.output = Some(output.to_string());
+        self
+    }
+
+    /// Names the package database `dbname`, nixpkgs.db by default
+    pub fn dbname(mut self, dbname: &str) -> Self {
+        self.config.dbname = dbname.to_string();
+        self
+    }
+
+    /// Also writes the package database as `format`, nixpkgs.db is always kept
+    pub fn format(mut self, format: Format) -> Self {
+        if !self.config.formats.contains(&format) {
+            self.config.formats.push(format);
+        }
+        self
+    }
+
+    /// Also exports the package tables to `export`
+    pub fn export(mut self, export: Export) -> Self {
+        self.config.exports.push(export);
+        self
+    }
+
+    /// Also writes a compressed copy and checksums of each database
+    pub fn compress(mut self, compression: Compression) -> Self {
+        self.config.compress = Some(compression);
+        self
+    }
+
+    /// Writes what changed since the previous package database as `format` when it is rebuilt
+    pub fn changelog(mut self, format: ChangelogFormat) -> Self {
+        self.config.changelog = Some(format);
+        self
+    }
+
+    /// Writes the packages added and removed since the previous package database to added.json
+    /// and removed.json when it is rebuilt
+    pub fn addedremoved(mut self, addedremoved: bool) -> Self {
+        self.config.addedremoved = addedremoved;
+        self
+    }
+
+    /// Records when each package version was first and last built in history.db of the output
+    /// directory
+    pub fn history(mut self, history: bool) -> Self {
+        self.config.history = history;
+        self
+    }
+
+    /// Writes an Atom feed of the version bumps recorded in history.db next to each rebuilt
+    /// package database, only of the packages of `watchlist` unless it is empty. Needs `history`.
+    pub fn feed(mut self, watchlist: &[&str]) -> Self {
+        self.config.feed = true;
+        self.config.feedpackages = watchlist.iter().map(|x| x.to_string()).collect();
+        self
+    }
+
+    /// Also indexes the Nix User Repository
+    pub fn nur(mut self, nur: bool) -> Self {
+        self.config.nur = nur;
+        self
+    }
+
+    /// Records which packages the binary cache at `url` has
+    pub fn cache(mut self, url: &str) -> Self {
+        self.config.cache = Some(url.to_string());
+        self
+    }
+
+    /// Matches packages against the CVEs of the NVD, faster with an `apikey`
+    pub fn advisories(mut self, apikey: Option<&str>) -> Self {
+        self.config.advisories = true;
+        self.config.nvdapikey = apikey.map(|x| x.to_string());
+        self
+    }
+
+    /// Records the newest upstream version of each package from Repology
+    pub fn repology(mut self, repology: bool) -> Self {
+        self.config.repology = repology;
+        self
+    }
+
+    /// Imports the command-not-found programs of nixos-* channels
+    pub fn programs(mut self, programs: bool) -> Self {
+        self.config.programs = programs;
+        self
+    }
+
+    /// Records the aliases of the JSON `file` instead of evaluating aliases.nix
+    pub fn aliases(mut self, file: &str) -> Self {
+        self.config.aliases = Some(file.to_string());
+        self
+    }
+
+    /// Also mirrors the package database into the MySQL database at `url`
+    pub fn mysql(mut self, url: &str) -> Self {
+        self.config.mysql = Some(url.to_string());
+        self
+    }
+
+    /// Uploads rebuilt databases to `target`
+    pub fn publish(mut self, target: Target) -> Self {
+        self.config.publish.push(target);
+        self
+    }
+
+    /// Announces rebuilt databases to the webhook `url`
+    pub fn webhook(mut self, url: Url) -> Self {
+        self.config.webhooks.push(url);
+        self
+    }
+
+    /// Posts updated and failed databases through the Discord webhook `url`
+    pub fn discord(mut self, url: Url) -> Self {
+        self.config.chat = Chat {
+            discord: Some(url),
+            ..self.config.chat
+        };
+        self
+    }
+
+    /// Posts updated and failed databases to a Matrix room
+    pub fn matrix(mut self, matrix: Matrix) -> Self {
+        self.config.chat = Chat {
+            matrix: Some(matrix),
+            ..self.config.chat
+        };
+        self
+    }
+
+    /// Rebuilds the databases even when they are up to date
+    pub fn force(mut self, force: bool) -> Self {
+        self.config.force = force;
+        self
+    }
+
+    /// Archives the package database being replaced, keeping the newest `keep` archives
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.config.keep = Some(keep);
+        self
+    }
+
+    /// Only finds out which databases would be rebuilt
+    pub fn check(mut self, check: bool) -> Self {
+        self.config.check = check;
+        self
+    }
+
+    /// Fails when more than `rate`, a share such as 0.01, of the packages can't be parsed rather
+    /// than leaving them out
+    pub fn strict(mut self, rate: f64) -> Self {
+        self.config.strict = Some(rate);
+        self
+    }
+
+    /// Sets which package is kept of those whose attributes collide
+    pub fn duplicates(mut self, policy: Duplicates) -> Self {
+        self.config.duplicates = policy;
+        self
+    }
+
+    /// Also generates the NixOS options database of each NixOS channel
+    pub fn options(mut self, options: bool) -> Self {
+        self.config.options = options;
+        self
+    }
+
+    /// Also generates a nix-darwin options database
+    pub fn darwin(mut self, darwin: bool) -> Self {
+        self.config.darwin = darwin;
+        self
+    }
+
+    /// Resolves and downloads channels from `url` instead of channels.nixos.org
+    pub fn channelurl(mut self, url: &str) -> Self {
+        self.config.channelurls[0] = url.to_string();
+        self
+    }
+
+    /// Falls back to the channel mirror `url`, after the channel server and earlier mirrors
+    pub fn mirror(mut self, url: &str) -> Self {
+        self.config.channelurls.push(url.to_string());
+        self
+    }
+
+    /// Makes every request through the proxy `url`
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.config.proxy = Some(url.to_string());
+        self
+    }
+
+    /// Gives a request to a channel server up after `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Retries a failed request to a channel server `retries` times
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.config.retries = retries;
+        self
+    }
+
+    /// Accepts downloads whose SHA-256 the release page doesn't list, only warning about them
+    pub fn noverify(mut self, noverify: bool) -> Self {
+        self.config.noverify = noverify;
+        self
+    }
+
+    /// Inserts `batchsize` rows per transaction
+    pub fn batchsize(mut self, batchsize: usize) -> Self {
+        self.config.batchsize = batchsize.max(1);
+        self
+    }
+
+    /// Shows progress on stderr even when it is not a terminal
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.config.progress = progress;
+        self
+    }
+
+    /// Calls `listener` with the phases, progress and warnings of the generation
+    pub fn listen(mut self, listener: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.config.events.listen(listener);
+        self
+    }
+
+    /// The phases, progress and warnings of the generation as they happen
+    pub fn stream(&mut self) -> mpsc::UnboundedReceiver<Event> {
+        self.config.events.stream()
+    }
+
+    /// The configuration to pass to `generate`
+    pub fn build(self) -> GenerateConfig {
+        self.config
+    }
+
+    /// Generates the databases, see `generate`
+    pub async fn generate(self) -> Result<GenerationReport> {
+        crate::generate(self.config).await
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+use reqwest::{Client, StatusCode};
+
+use crate::{channel, storehash};
+
+/// Default binary cache
+pub const CACHE_URL: &str = "https://cache.nixos.org";
+
+/// Number of narinfo requests in flight at once
+const WORKERS: usize = 32;
+
+/// Checks which store `paths` have a narinfo in the binary cache at `url`.
+/// Paths the cache couldn't be asked about are left out of the result.
+pub async fn incache<'a>(
+    url: &str,
+    paths: impl Iterator<Item = &'a str>,
+    proxy: Option<&str>,
+) -> Result<HashMap<&'a str, bool>> {
+    let client = channel::clientbuilder(proxy)?.build()?;
+    let paths = paths.collect::<Vec<_>>();
+    debug!("Checking {} store paths", paths.len());
+
+    let mut checks = stream::iter(paths)
+        .map(|path| {
+            let client = &client;
+            async move { (path, narinfo(client, url, path).await) }
+        })
+        .buffer_unordered(WORKERS);
+    let mut cached = HashMap::new();
+    while let Some((path, found)) = checks.next().await {
+        match found {
+            Ok(found) => {
+                cached.insert(path, found);
+            }
+            Err(e) => warn!("Failed to check {}: {}", path, e),
+        }
+    }
+    Ok(cached)
+}
+
+/// Whether the cache has a narinfo for `path`
+async fn narinfo(client: &Client, url: &str, path: &str) -> Result<bool> {
+    let hash = storehash(path).ok_or_else(|| anyhow!("Not a store path"))?;
+    let resp = client
+        .head(format!("{}/{}.narinfo", url.trim_end_matches('/'), hash))
+        .send()
+        .await?;
+    match resp.status() {
+        StatusCode::OK => Ok(true),
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => Ok(false),
+        status => Err(anyhow!("Unexpected status {}", status)),
+    }
+}
@@ -0,0 +1,135 @@
+use std::{cmp::Ordering, fs, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    advisories::compareversions,
+    diff::{Diff, PackageVersion, VersionChange},
+    ChangelogFormat,
+};
+
+/// What changed between two builds of the package database of a source
+#[derive(Serialize)]
+struct Changelog<'a> {
+    source: &'a str,
+    /// Version the previous database was built from, when known
+    from: Option<&'a str>,
+    to: &'a str,
+    upgraded: Vec<&'a VersionChange>,
+    downgraded: Vec<&'a VersionChange>,
+    added: &'a [PackageVersion],
+    removed: &'a [PackageVersion],
+}
+
+/// Writes the changes of `diff` to `file` as `format`, going from version `from` of `source` to
+/// `to`
+pub fn write(
+    file: &str,
+    format: ChangelogFormat,
+    source: &str,
+    (from, to): (Option<&str>, &str),
+    diff: &Diff,
+) -> Result<()> {
+    let (downgraded, upgraded) = diff.changed.iter().partition(|x| {
+        compareversions(
+            x.new.as_deref().unwrap_or_default(),
+            x.old.as_deref().unwrap_or_default(),
+        ) == Ordering::Less
+    });
+    let changelog = Changelog {
+        source,
+        from,
+        to,
+        upgraded,
+        downgraded,
+        added: &diff.added,
+        removed: &diff.removed,
+    };
+    let contents = match format {
+        ChangelogFormat::Markdown => changelog.markdown(),
+        ChangelogFormat::Json => serde_json::to_string_pretty(&changelog)?,
+    };
+    fs::write(file, contents)?;
+    Ok(())
+}
+
+/// Removes the changelog `file` of an earlier build, which no longer describes the database
+pub fn remove(file: &str) -> Result<()> {
+    if Path::new(file).exists() {
+        fs::remove_file(file)?;
+    }
+    Ok(())
+}
+
+/// Writes the packages added and removed by `diff` to added.json and removed.json in `outdir`,
+/// removing those of an earlier build without a previous database to compare with
+pub fn addedremoved(outdir: &str, diff: Option<&Diff>) -> Result<()> {
+    for (name, packages) in [
+        ("added.json", diff.map(|x| &x.added)),
+        ("removed.json", diff.map(|x| &x.removed)),
+    ] {
+        let file = format!("{}/{}", outdir, name);
+        match packages {
+            Some(packages) => fs::write(file, serde_json::to_string_pretty(packages)?)?,
+            None => remove(&file)?,
+        }
+    }
+    Ok(())
+}
+
+/// Escapes `value` for a markdown table cell
+fn cell(value: Option<&str>) -> String {
+    value.unwrap_or("-").replace('|', "\\|")
+}
+
+impl Changelog<'_> {
+    fn markdown(&self) -> String {
+        let mut out = format!("# {} {}\n\n", self.source, self.to);
+        out.push_str(&format!(
+            "{} upgraded, {} downgraded, {} added, {} removed since {}\n",
+            self.upgraded.len(),
+            self.downgraded.len(),
+            self.added.len(),
+            self.removed.len(),
+            self.from.unwrap_or("the previous build")
+        ));
+        for (title, changes) in [
+            ("Upgraded", &self.upgraded),
+            ("Downgraded", &self.downgraded),
+        ] {
+            if changes.is_empty() {
+                continue;
+            }
+            out.push_str(&format!(
+                "\n## {}\n\n| Package | Old | New |\n| --- | --- | --- |\n",
+                title
+            ));
+            for x in changes {
+                out.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    cell(Some(&x.attribute)),
+                    cell(x.old.as_deref()),
+                    cell(x.new.as_deref())
+                ));
+            }
+        }
+        for (title, packages) in [("Added", self.added), ("Removed", self.removed)] {
+            if packages.is_empty() {
+                continue;
+            }
+            out.push_str(&format!(
+                "\n## {}\n\n| Package | Version |\n| --- | --- |\n",
+                title
+            ));
+            for x in packages {
+                out.push_str(&format!(
+                    "| {} | {} |\n",
+                    cell(Some(&x.attribute)),
+                    cell(x.version.as_deref())
+                ));
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, File},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    io::{self, BufReader, BufWriter, Read, SeekFrom},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use brotli_decompressor::Decompressor;
+use futures::TryStreamExt;
+use indicatif::ProgressBar;
+use log::{debug, error, warn};
+use reqwest::{
+    header::{
+        HeaderMap, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+        LAST_MODIFIED, RANGE,
+    },
+    Client, ClientBuilder, NoProxy, Proxy, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+use crate::{compress, daemon, metrics, progress::Progress};
+
+/// Systems the darwin channels are built for
+pub const DARWIN_SYSTEMS: [&str; 2] = ["aarch64-darwin", "x86_64-darwin"];
+
+/// Default channel server
+pub const CHANNEL_URL: &str = "https://channels.nixos.org";
+
+/// Server the channel releases are published on
+const RELEASES_URL: &str = "https://releases.nixos.org";
+
+/// S3 bucket behind `RELEASES_URL`, which unlike the website can be listed
+const RELEASES_BUCKET: &str = "https://nix-releases.s3.amazonaws.com";
+
+/// Longest wait between two attempts at a request
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A resolved channel release
+pub struct Release {
+    /// Url the release files are served from
+    pub url: String,
+    /// Release name, e.g. `nixos-23.11.1234.abcdef`
+    pub name: String,
+}
+
+impl Release {
+    /// Downloads `file` from this release
+    pub async fn download(&self, client: &Client, file: &str) -> Result<Response> {
+        let url = format!("{}/{}", self.url, file);
+        debug!("Downloading {}", url);
+        Ok(client.get(url).send().await?)
+    }
+
+    /// Downloads `file` from this release to `dest` as it is stored, resuming it up to the
+    /// `retries` of `mirrors` times when the connection drops, and checks it against the SHA-256
+    /// on the release page. Returns the response headers, or `None` if the server reports the file
+    /// unchanged since `validators` were recorded.
+    pub async fn downloadto(
+        &self,
+        mirrors: &Mirrors,
+        file: &str,
+        dest: &str,
+        validators: Option<&Validators>,
+        progress: Progress,
+    ) -> Result<Option<HeaderMap>> {
+        let (client, retries) = (&mirrors.client, mirrors.retries);
+        let url = format!("{}/{}", self.url, file);
+        debug!("Downloading {} to {}", url, dest);
+        let partial = format!("{}.partial", dest);
+        let mut out = tokio::fs::File::create(&partial).await?;
+        let mut headers = None;
+        let mut attempt = 0;
+        let bar = progress.bytes(&format!("Downloading {}", file), None);
+        loop {
+            match resume(
+                client,
+                &url,
+                &mut out,
+                &mut headers,
+                validators,
+                (progress, &bar),
+            )
+            .await
+            {
+                Ok(true) => break,
+                Ok(false) => {
+                    bar.finish_and_clear();
+                    fs::remove_file(&partial)?;
+                    return Ok(None);
+                }
+                Err(e) if attempt < retries && retryable(&e) => {
+                    attempt += 1;
+                    let delay = backoff(attempt);
+                    warn!(
+                        "Resuming download of {} in {:.1}s after: {}",
+                        file,
+                        delay.as_secs_f64(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    fs::remove_file(&partial)?;
+                    return Err(e);
+                }
+            }
+        }
+
+        bar.finish();
+        let headers = headers.ok_or_else(|| anyhow!("No response for {}", url))?;
+        let len = out.metadata().await?.len();
+        drop(out);
+        if let Some(expected) = contentlength(&headers) {
+            if len != expected {
+                fs::remove_file(&partial)?;
+                return Err(anyhow!(
+                    "Downloaded {} of the {} bytes of {}",
+                    len,
+                    expected,
+                    file
+                ));
+            }
+        }
+        if let Err(e) = self.verify(client, file, &partial, mirrors.verify).await {
+            fs::remove_file(&partial)?;
+            return Err(e);
+        }
+        fs::rename(&partial, dest)?;
+        Ok(Some(headers))
+    }
+
+    /// Checks the downloaded `file` at `path` against the SHA-256 listed for it on the release
+    /// page. A page that can't be read or doesn't list it fails the download when `required`, and
+    /// is only warned about otherwise.
+    async fn verify(&self, client: &Client, file: &str, path: &str, required: bool) -> Result<()> {
+        let problem = match self.sha256(client, file).await {
+            Ok(Some(hash)) => return self.compare(file, path, &hash),
+            Ok(None) => format!("No SHA-256 published for {} of {}", file, self.name),
+            Err(e) => format!("Could not read the SHA-256 of {}: {}", file, e),
+        };
+        if required {
+            // Logged here as well since callers only report that the download failed
+            error!("{}, pass --no-verify to accept it", problem);
+            return Err(anyhow!(problem));
+        }
+        warn!("{}", problem);
+        Ok(())
+    }
+
+    /// Checks the downloaded `file` at `path` against the `expected` SHA-256
+    fn compare(&self, file: &str, path: &str, expected: &str) -> Result<()> {
+        let actual = compress::sha256(path)?;
+        if actual != expected {
+            // Logged here as well since callers only report that the download failed
+            error!(
+                "SHA-256 mismatch for {} of {}: expected {}, downloaded {}",
+                file, self.name, expected, actual
+            );
+            return Err(anyhow!("SHA-256 mismatch for {}", file));
+        }
+        debug!("Verified SHA-256 of {}", file);
+        Ok(())
+    }
+
+    /// SHA-256 of `file` as listed on the release page, if it is
+    pub async fn sha256(&self, client: &Client, file: &str) -> Result<Option<String>> {
+        let page = self
+            .download(client, "")
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(listedhash(&page, file))
+    }
+
+    /// Full nixpkgs git revision of this release
+    pub async fn revision(&self, client: &Client) -> Result<String> {
+        let rev = self
+            .download(client, "git-revision")
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(rev.trim().to_string())
+    }
+}
+
+/// Starts an HTTP client going through `proxy`, or else through the proxies set by `HTTP_PROXY`,
+/// `HTTPS_PROXY` and `NO_PROXY`. Hosts in `NO_PROXY` bypass `proxy` as well.
+pub fn clientbuilder(proxy: Option<&str>) -> Result<ClientBuilder> {
+    let builder = Client::builder();
+    Ok(match proxy {
+        Some(url) => builder.proxy(
+            Proxy::all(url)
+                .with_context(|| format!("Invalid proxy {}", url))?
+                .no_proxy(NoProxy::from_env()),
+        ),
+        None => builder,
+    })
+}
+
+/// HTTP validators of a downloaded file, sent along to skip downloading it again when unchanged
+#[derive(Serialize, Deserialize)]
+pub struct Validators {
+    /// Channel the file was downloaded for
+    pub channel: String,
+    etag: Option<String>,
+    lastmodified: Option<String>,
+}
+
+impl Validators {
+    /// Validators of a response with `headers`, downloaded for `channel`
+    pub fn new(channel: &str, headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.to_string())
+        };
+        Validators {
+            channel: channel.to_string(),
+            etag: header(ETAG),
+            lastmodified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// Validators recorded in `file`, if there are any
+    pub fn load(file: &str) -> Option<Self> {
+        let validators: Self =
+            serde_json::from_reader(BufReader::new(File::open(file).ok()?)).ok()?;
+        (validators.etag.is_some() || validators.lastmodified.is_some()).then_some(validators)
+    }
+
+    /// Records these validators in `file`, unless the server sent none
+    pub fn save(&self, file: &str) -> Result<()> {
+        if self.etag.is_some() || self.lastmodified.is_some() {
+            serde_json::to_writer(BufWriter::new(File::create(file)?), self)?;
+        }
+        Ok(())
+    }
+}
+
+/// Body of `resp`, read as it arrives
+pub fn body(resp: Response) -> impl AsyncRead + Send + Unpin {
+    StreamReader::new(resp.bytes_stream().map_err(io::Error::other))
+}
+
+/// Reads the brotli compressed `file`, showing how much of it was read
+pub fn decompress(file: &str, progress: Progress) -> Result<impl Read + Send + 'static> {
+    let file = File::open(file)?;
+    let bar = progress.bytes("Parsing", Some(file.metadata()?.len()));
+    Ok(BufReader::new(Decompressor::new(bar.wrap_read(file), 4096)))
+}
+
+/// Continues downloading `url` into `out` from where it ends, recording the headers of the
+/// response that started it in `headers`. Returns false if the server reports the file unchanged
+/// since `validators` were recorded.
+async fn resume(
+    client: &Client,
+    url: &str,
+    out: &mut tokio::fs::File,
+    headers: &mut Option<HeaderMap>,
+    validators: Option<&Validators>,
+    (progress, bar): (Progress, &ProgressBar),
+) -> Result<bool> {
+    let offset = out.metadata().await?.len();
+    let mut req = client.get(url);
+    match headers {
+        Some(headers) if offset > 0 => {
+            req = req.header(RANGE, format!("bytes={}-", offset));
+            // A changed file is sent whole instead of the rest of the old one
+            if let Some(validator) = headers.get(ETAG).or(headers.get(LAST_MODIFIED)) {
+                req = req.header(IF_RANGE, validator);
+            }
+        }
+        _ => {
+            if let Some(etag) = validators.and_then(|x| x.etag.as_ref()) {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(lastmodified) = validators.and_then(|x| x.lastmodified.as_ref()) {
+                req = req.header(IF_MODIFIED_SINCE, lastmodified);
+            }
+        }
+    }
+
+    let mut resp = req.send().await?;
+    if resp.status() == StatusCode::NOT_MODIFIED && headers.is_none() {
+        return Ok(false);
+    }
+    resp = resp.error_for_status()?;
+    if resp.status() == StatusCode::PARTIAL_CONTENT {
+        // Content-Range is `bytes <start>-<end>/<length>`
+        let start = resp
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("bytes "))
+            .and_then(|x| x.split('-').next())
+            .and_then(|x| x.parse::<u64>().ok());
+        if start != Some(offset) {
+            return Err(anyhow!(
+                "Server resumed {} at {:?}, not {}",
+                url,
+                start,
+                offset
+            ));
+        }
+    } else {
+        // Sent whole, either the range was ignored or the file changed
+        out.set_len(0).await?;
+        out.seek(SeekFrom::Start(0)).await?;
+        *headers = Some(resp.headers().clone());
+        if let Some(len) = contentlength(resp.headers()) {
+            progress.setlength(bar, len);
+        }
+        bar.set_position(0);
+    }
+    while let Some(chunk) = resp.chunk().await? {
+        out.write_all(&chunk).await?;
+        bar.inc(chunk.len() as u64);
+        metrics::downloaded(chunk.len() as u64);
+        daemon::alive();
+    }
+    out.flush().await?;
+    Ok(true)
+}
+
+/// Length of the whole file a response with `headers` sent
+fn contentlength(headers: &HeaderMap) -> Option<u64> {
+    headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// Whether a request that failed with `e` may succeed when made again
+fn retryable(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .is_some_and(|x| x.status().is_none_or(|x| x.is_server_error()))
+}
+
+/// How long to wait before the `attempt`th retry: doubling from a second up to `MAX_BACKOFF`,
+/// less a random part of up to half so failed clients don't all come back at once
+fn backoff(attempt: u32) -> Duration {
+    let delay = Duration::from_secs(1 << attempt.saturating_sub(1).min(6)).min(MAX_BACKOFF);
+    let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay.mul_f64(1.0 - jitter / 2.0)
+}
+
+/// Makes the request `f` until it succeeds, fails for a reason retrying won't fix or was retried
+/// `retries` times
+pub async fn retry<T, F, Fut>(what: &str, retries: u32, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Err(e) if attempt < retries && retryable(&e) => {
+                attempt += 1;
+                let delay = backoff(attempt);
+                warn!(
+                    "Retrying {} in {:.1}s after: {}",
+                    what,
+                    delay.as_secs_f64(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Channel servers, tried in order until one serves the requested channel
+#[derive(Clone)]
+pub struct Mirrors {
+    pub client: Client,
+    /// Times a failed request is made again
+    pub retries: u32,
+    /// Fail downloads whose SHA-256 the release page doesn't list, instead of warning
+    pub verify: bool,
+    urls: Vec<String>,
+}
+
+impl Mirrors {
+    pub fn new(client: Client, urls: Vec<String>, retries: u32) -> Self {
+        Mirrors {
+            client,
+            retries,
+            verify: true,
+            urls: urls
+                .into_iter()
+                .map(|x| x.trim_end_matches('/').to_string())
+                .collect(),
+        }
+    }
+
+    /// Tries `urls` in order through `proxy`, giving each request up after `timeout`
+    pub fn connect(
+        urls: Vec<String>,
+        proxy: Option<&str>,
+        timeout: Duration,
+        retries: u32,
+    ) -> Result<Self> {
+        Ok(Mirrors::new(
+            clientbuilder(proxy)?.timeout(timeout).build()?,
+            urls,
+            retries,
+        ))
+    }
+
+    /// Resolves the latest release of `channel` on the first mirror that serves it,
+    /// or from the published releases if none do
+    pub async fn latestrelease(&self, channel: &str) -> Result<Option<Release>> {
+        for mirror in &self.urls {
+            let url = format!("{}/{}", mirror, channel);
+            match retry(&url, self.retries, || latestrelease(&self.client, &url)).await {
+                Ok(Some(release)) => return Ok(Some(release)),
+                Ok(None) => debug!("{} not found on {}", channel, mirror),
+                Err(e) => warn!("Failed to resolve {} on {}: {}", channel, mirror, e),
+            }
+        }
+        warn!(
+            "{} could not be resolved on any mirror, falling back to {}",
+            channel, RELEASES_URL
+        );
+        newestrelease(&self.client, channel, self.retries).await
+    }
+
+    /// Finds the release of `channel` built from nixpkgs revision `rev`
+    pub async fn findrelease(&self, channel: &str, rev: &str) -> Result<Option<Release>> {
+        // Release names end in the abbreviated revision, e.g. `nixos-23.11.1234.abcdef`
+        Ok(releases(&self.client, channel, self.retries)
+            .await?
+            .into_iter()
+            .find(|x| {
+                x.name
+                    .rsplit_once('.')
+                    .is_some_and(|(_, shortrev)| shortrev.len() >= 7 && rev.starts_with(shortrev))
+            }))
+    }
+}
+
+/// Follows the channel redirect at `url` and returns the release it points to.
+/// Mirrors that serve the channel directory directly are identified by its `git-revision`.
+async fn latestrelease(client: &Client, url: &str) -> Result<Option<Release>> {
+    let resp = client.get(url).send().await?;
+    if resp.status().is_server_error() {
+        // Passed on to be retried instead of taken for a channel the mirror doesn't serve
+        resp.error_for_status_ref()?;
+    }
+    if resp.status().is_success() {
+        let releaseurl = resp.url().as_str().trim_end_matches('/').to_string();
+        let name = resp
+            .url()
+            .path_segments()
+            .context("No path segments found")?
+            .rfind(|x| !x.is_empty())
+            .context("Last element not found")?
+            .to_string();
+        let mut release = Release {
+            url: releaseurl,
+            name,
+        };
+        if url
+            .trim_end_matches('/')
+            .ends_with(&format!("/{}", release.name))
+        {
+            release.name = format!("{}.{}", release.name, release.revision(client).await?);
+        }
+        Ok(Some(release))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Directory the releases of `channel` are published in on `RELEASES_URL`
+fn releasedir(channel: &str) -> String {
+    if let Some(ver) = channel.strip_prefix("nixos-") {
+        format!("nixos/{}", ver)
+    } else if channel == "nixpkgs-unstable" {
+        "nixpkgs".to_string()
+    } else {
+        format!(
+            "nixpkgs/{}",
+            channel.strip_prefix("nixpkgs-").unwrap_or(channel)
+        )
+    }
+}
+
+/// Finds the SHA-256 in the row of the file table of a release page linking to `file`
+fn listedhash(page: &str, file: &str) -> Option<String> {
+    let link = format!(">{}<", file);
+    page.split("<tr")
+        .find(|row| row.contains(&link))?
+        .split(|c: char| !c.is_ascii_hexdigit())
+        .find(|x| x.len() == 64)
+        .map(|x| x.to_ascii_lowercase())
+}
+
+/// Returns the text of every `<tag>` element in `xml`
+fn xmltags<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    xml.split(&open)
+        .skip(1)
+        .filter_map(|x| x.split(&close).next())
+        .collect()
+}
+
+/// Lists every published release of `channel`
+async fn releases(client: &Client, channel: &str, retries: u32) -> Result<Vec<Release>> {
+    let prefix = format!("{}/", releasedir(channel));
+    let mut releases = Vec::new();
+    let mut marker = String::new();
+    loop {
+        let listing = retry(RELEASES_BUCKET, retries, || async {
+            Ok(client
+                .get(RELEASES_BUCKET)
+                .query(&[("delimiter", "/"), ("prefix", &prefix), ("marker", &marker)])
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?)
+        })
+        .await?;
+        let dirs = xmltags(&listing, "Prefix");
+        for dir in &dirs {
+            let dir = dir.trim_end_matches('/');
+            let name = dir.rsplit('/').next().unwrap_or(dir);
+            // Skips the listing prefix itself and nested channel directories
+            if name.starts_with("nixos-") || name.starts_with("nixpkgs-") {
+                releases.push(Release {
+                    url: format!("{}/{}", RELEASES_URL, dir),
+                    name: name.to_string(),
+                });
+            }
+        }
+        if xmltags(&listing, "IsTruncated").first() != Some(&"true") {
+            return Ok(releases);
+        }
+        marker = match xmltags(&listing, "NextMarker").first().or(dirs.last()) {
+            Some(next) => next.to_string(),
+            None => return Ok(releases),
+        };
+    }
+}
+
+/// Finds the newest published release of `channel` by its release counter,
+/// e.g. `1234` in `nixos-23.11.1234.abcdef` or `123456` in `nixos-24.05pre123456.abcdef`
+async fn newestrelease(client: &Client, channel: &str, retries: u32) -> Result<Option<Release>> {
+    Ok(releases(client, channel, retries)
+        .await?
+        .into_iter()
+        .max_by_key(|x| {
+            x.name
+                .rsplit('.')
+                .nth(1)
+                .and_then(|x| x.rsplit(|c: char| !c.is_ascii_digit()).next())
+                .and_then(|x| x.parse::<u64>().ok())
+                .unwrap_or_default()
+        }))
+}
+
+/// Strips the channel prefix from a release name, e.g.
+/// `nixos-23.11.1234.abcdef` or `nixpkgs-darwin-23.11.1234.abcdef` becomes `23.11.1234.abcdef`
+pub fn releaseversion(release: &str) -> &str {
+    ["nixos-", "nixpkgs-darwin-", "nixpkgs-"]
+        .iter()
+        .find_map(|prefix| release.strip_prefix(prefix))
+        .unwrap_or(release)
+}
+
+/// Whether `channel` is a darwin channel such as `nixpkgs-23.11-darwin`
+pub fn isdarwin(channel: &str) -> bool {
+    channel.starts_with("nixpkgs-") && channel.ends_with("-darwin")
+}
+
+/// Which build of nixpkgs `channel` tracks, `small` channels advance faster with fewer packages built
+pub fn variant(channel: &str) -> &'static str {
+    if channel.ends_with("-small") {
+        "small"
+    } else if isdarwin(channel) {
+        "darwin"
+    } else {
+        "standard"
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use log::{debug, error};
+use reqwest::{Client, Url};
+use sqlx::SqlitePool;
+
+use crate::channel::{self, Mirrors};
+
+/// Chats to tell about updated and failed databases
+#[derive(Clone, Default)]
+pub struct Chat {
+    /// Discord webhook to post to
+    pub discord: Option<Url>,
+    pub matrix: Option<Matrix>,
+}
+
+/// A Matrix room posted to as the account of `token`
+#[derive(Clone)]
+pub struct Matrix {
+    pub homeserver: Url,
+    pub room: String,
+    pub token: String,
+}
+
+impl Chat {
+    fn enabled(&self) -> bool {
+        self.discord.is_some() || self.matrix.is_some()
+    }
+
+    /// Attributes in the package database `dbfile` before it is rebuilt, to count the new ones
+    /// after. None without chats to tell or a database to compare with.
+    pub async fn previous(&self, dbfile: &str) -> HashSet<String> {
+        if !self.enabled() || !Path::new(dbfile).exists() {
+            return HashSet::new();
+        }
+        let attributes = async {
+            let pool = SqlitePool::connect(&format!("sqlite://{}?mode=ro", dbfile)).await?;
+            let attributes: Vec<(String,)> = sqlx::query_as(r#"SELECT "attribute" FROM "pkgs""#)
+                .fetch_all(&pool)
+                .await?;
+            pool.close().await;
+            anyhow::Ok(attributes.into_iter().map(|x| x.0).collect())
+        };
+        attributes.await.unwrap_or_else(|e| {
+            debug!("Failed to read the packages of {}: {}", dbfile, e);
+            HashSet::new()
+        })
+    }
+
+    /// Tells that `dbfile` was rebuilt from `source`, with how many of its packages are not in
+    /// `previous`
+    pub async fn updated(
+        &self,
+        mirrors: &Mirrors,
+        source: &str,
+        dbfile: &str,
+        previous: &HashSet<String>,
+    ) -> Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let pool = SqlitePool::connect(&format!("sqlite://{}?mode=ro", dbfile)).await?;
+        let (version,): (String,) = sqlx::query_as(r#"SELECT "version" FROM "generation_info""#)
+            .fetch_one(&pool)
+            .await?;
+        let attributes: Vec<(String,)> = sqlx::query_as(r#"SELECT "attribute" FROM "pkgs""#)
+            .fetch_all(&pool)
+            .await?;
+        pool.close().await;
+        let new = attributes
+            .iter()
+            .filter(|x| !previous.contains(&x.0))
+            .count();
+        self.send(
+            mirrors,
+            &format!(
+                "{} db updated to {}, {} packages, {} new",
+                source,
+                version,
+                thousands(attributes.len()),
+                thousands(new)
+            ),
+        )
+        .await
+    }
+
+    /// Tells that the databases of `source` failed with `e`, logging rather than returning
+    /// failures to tell as the run fails already
+    pub async fn failed(&self, mirrors: &Mirrors, source: &str, e: &anyhow::Error) {
+        if let Err(e) = self
+            .send(mirrors, &format!("{} db failed to update: {}", source, e))
+            .await
+        {
+            error!("{}", e);
+        }
+    }
+
+    /// Posts `message` to every chat, logging each one that fails
+    async fn send(&self, mirrors: &Mirrors, message: &str) -> Result<()> {
+        let mut failed = false;
+        if let Some(url) = &self.discord {
+            if let Err(e) = discord(&mirrors.client, mirrors.retries, url, message).await {
+                error!("Failed to post to Discord: {}", e);
+                failed = true;
+            }
+        }
+        if let Some(matrix) = &self.matrix {
+            if let Err(e) = matrix.send(&mirrors.client, mirrors.retries, message).await {
+                error!("Failed to post to {}: {}", matrix.room, e);
+                failed = true;
+            }
+        }
+        if failed {
+            return Err(anyhow!("Not every chat could be told"));
+        }
+        Ok(())
+    }
+}
+
+impl Matrix {
+    /// Sends `message` to the room as a notice, which bots send so as not to trigger other bots
+    async fn send(&self, client: &Client, retries: u32, message: &str) -> Result<()> {
+        // Identifies the message to the homeserver, so retrying it doesn't send it twice
+        let txnid = format!(
+            "{}-{}",
+            env!("CARGO_PKG_NAME"),
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+        );
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("{} can't be a homeserver", self.homeserver))?
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room,
+                "send",
+                "m.room.message",
+                &txnid,
+            ]);
+        let body =
+            serde_json::to_vec(&serde_json::json!({ "msgtype": "m.notice", "body": message }))?;
+        channel::retry("posting to Matrix", retries, || async {
+            client
+                .put(url.clone())
+                .bearer_auth(&self.token)
+                .header("content-type", "application/json")
+                .body(body.clone())
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Posts `message` through the Discord webhook `url`
+async fn discord(client: &Client, retries: u32, url: &Url, message: &str) -> Result<()> {
+    let body = serde_json::to_vec(&serde_json::json!({ "content": message }))?;
+    channel::retry("posting to Discord", retries, || async {
+        client
+            .post(url.clone())
+            .header("content-type", "application/json")
+            .body(body.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    })
+    .await
+}
+
+/// `n` with commas between its thousands, e.g. 98,432
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
@@ -0,0 +1,126 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use log::debug;
+use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+
+use crate::schema;
+
+/// Database holding every channel built in one run, next to their subdirectories
+pub const COMBINED_DB: &str = "nixpkgs_combined.db";
+
+/// Merges the `nixpkgs.db` of each `(channel, dbfile)` into a new database at `dbfile`.
+/// Its tables are those of `nixpkgs.db` with a leading `channel` column that is part of their
+/// primary key. The full-text search index is left out.
+pub async fn combine(dbfile: &str, channels: &[(String, String)]) -> Result<()> {
+    let Some((_, first)) = channels.first() else {
+        return Ok(());
+    };
+    debug!("Combining {} channels into {}", channels.len(), dbfile);
+    if Path::new(dbfile).exists() {
+        fs::remove_file(dbfile)?;
+    }
+    let db = format!("sqlite://{}", dbfile);
+    Sqlite::create_database(&db).await?;
+    let pool = SqlitePool::connect(&db).await?;
+
+    // Every channel was generated by this run, so they all share the first one's schema
+    let source = SqlitePool::connect(&format!("sqlite://{}", first)).await?;
+    let mut tables = Vec::new();
+    for (table, columns) in schema::tables(&source).await? {
+        if table == "schema_version" {
+            continue;
+        }
+        let keys: Vec<(String,)> = sqlx::query_as(
+            r#"SELECT "name" FROM pragma_table_info(?) WHERE "pk" > 0 ORDER BY "pk""#,
+        )
+        .bind(&table)
+        .fetch_all(&source)
+        .await?;
+        // generation_info already records its channel
+        let haschannel = columns.iter().any(|x| x.name == "channel");
+
+        let mut definitions = Vec::new();
+        if !haschannel {
+            definitions.push(r#""channel" TEXT NOT NULL"#.to_string());
+        }
+        definitions.extend(columns.iter().map(|x| {
+            format!(
+                r#""{}" {}"#,
+                x.name,
+                if x.integer { "INTEGER" } else { "TEXT" }
+            )
+        }));
+        if !keys.is_empty() {
+            definitions.push(format!(
+                r#"PRIMARY KEY("channel", {})"#,
+                keys.iter()
+                    .map(|x| format!(r#""{}""#, x.0))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        sqlx::query(&format!(
+            r#"CREATE TABLE "{}" ({})"#,
+            table,
+            definitions.join(", ")
+        ))
+        .execute(&pool)
+        .await?;
+        let columns = columns.into_iter().map(|x| x.name).collect::<Vec<_>>();
+        tables.push((table, columns, haschannel));
+    }
+    source.close().await;
+
+    // ATTACH only applies to the connection it runs on
+    let mut conn = pool.acquire().await?;
+    for (channel, channeldb) in channels {
+        debug!("Adding {} from {}", channel, channeldb);
+        sqlx::query("ATTACH DATABASE ? AS src")
+            .bind(channeldb)
+            .execute(&mut *conn)
+            .await?;
+        for (table, columns, haschannel) in &tables {
+            let columns = columns
+                .iter()
+                .map(|x| format!(r#""{}""#, x))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if *haschannel {
+                sqlx::query(&format!(
+                    r#"INSERT INTO main."{0}" ({1}) SELECT {1} FROM src."{0}""#,
+                    table, columns
+                ))
+                .execute(&mut *conn)
+                .await?;
+            } else {
+                sqlx::query(&format!(
+                    r#"INSERT INTO main."{0}" ("channel", {1}) SELECT ?, {1} FROM src."{0}""#,
+                    table, columns
+                ))
+                .bind(channel)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        sqlx::query("DETACH DATABASE src")
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    // Each channel's rows follow the layout of its nixpkgs.db
+    sqlx::query(r#"CREATE TABLE "schema_version" ("version" INTEGER NOT NULL)"#)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(r#"INSERT INTO "schema_version" ("version") VALUES (?)"#)
+        .bind(schema::version(&schema::NIXPKGS))
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(&format!(
+        "PRAGMA application_id = {}",
+        schema::APPLICATION_ID
+    ))
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use log::debug;
+use sha2::{Digest, Sha256};
+
+/// zstd level of published files, they are compressed once and downloaded many times
+const ZSTD_LEVEL: i32 = 19;
+
+/// Writes `<file>.zst` next to `file`, along with `sha256sum` style checksums of both
+pub fn zstd(file: &str) -> Result<()> {
+    let zstfile = format!("{}.zst", file);
+    debug!("Compressing {} to {}", file, zstfile);
+    let mut writer = BufWriter::new(File::create(&zstfile)?);
+    zstd::stream::copy_encode(BufReader::new(File::open(file)?), &mut writer, ZSTD_LEVEL)?;
+    writer.flush()?;
+
+    checksum(file)?;
+    checksum(&zstfile)?;
+    Ok(())
+}
+
+/// Writes the SHA-256 of `file` to `<file>.sha256`, checkable with `sha256sum -c`
+fn checksum(file: &str) -> Result<()> {
+    let hash = sha256(file)?;
+    let name = Path::new(file)
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or(file);
+    fs::write(format!("{}.sha256", file), format!("{}  {}\n", hash, name))?;
+    Ok(())
+}
+
+/// Hex SHA-256 of `file`
+pub fn sha256(file: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(File::open(file)?), &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,69 @@
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use toml::{Table, Value};
+
+/// File read by a bare --config
+pub const CONFIG_FILE: &str = "nix-data-generator.toml";
+
+/// Reads the configuration `file` into the arguments of one `generate` run per `[[channel]]`.
+///
+/// Every key is the name of a long flag. Top level keys apply to each channel, which may
+/// override them. `true` passes a flag, `false` leaves it out and arrays repeat it, so
+///
+/// ```toml
+/// src = "/var/lib/nix-data"
+/// export = ["csv"]
+///
+/// [[channel]]
+/// ver = "nixos-unstable"
+/// options = true
+/// ```
+///
+/// runs `generate --src=/var/lib/nix-data --export=csv --ver=nixos-unstable --options`.
+pub fn load(file: &str) -> Result<Vec<Vec<String>>> {
+    let mut config: Table = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read {}", file))?
+        .parse()
+        .with_context(|| format!("Failed to parse {}", file))?;
+    let channels = match config.remove("channel") {
+        Some(Value::Array(channels)) => channels,
+        Some(_) => return Err(anyhow!("{}: channel must be an array of tables", file)),
+        None => return Err(anyhow!("{} has no [[channel]]", file)),
+    };
+
+    channels
+        .into_iter()
+        .map(|channel| {
+            let Value::Table(channel) = channel else {
+                return Err(anyhow!("{}: channel must be an array of tables", file));
+            };
+            let mut keys = config.clone();
+            keys.extend(channel);
+            let mut args = vec![env!("CARGO_PKG_NAME").to_string(), "generate".to_string()];
+            for (key, value) in keys {
+                match value {
+                    Value::Array(values) => {
+                        for value in values {
+                            args.push(flag(file, &key, value)?);
+                        }
+                    }
+                    Value::Boolean(false) => (),
+                    value => args.push(flag(file, &key, value)?),
+                }
+            }
+            Ok(args)
+        })
+        .collect()
+}
+
+/// The flag `key` is passed as with `value`
+fn flag(file: &str, key: &str, value: Value) -> Result<String> {
+    Ok(match value {
+        Value::Boolean(true) => format!("--{}", key),
+        Value::String(x) => format!("--{}={}", key, x),
+        Value::Integer(x) => format!("--{}={}", key, x),
+        Value::Float(x) => format!("--{}={}", key, x),
+        _ => return Err(anyhow!("{}: unsupported value for {}", file, key)),
+    })
+}
@@ -0,0 +1,42 @@
+use anyhow::{anyhow, Result};
+use log::debug;
+use sqlx::{Row, SqlitePool};
+
+use crate::schema;
+
+/// Writes the package tables of the generated SQLite database `dbfile` as CSV files with a header
+/// row into `outdir`. NULL is written as an empty field.
+pub async fn export(dbfile: &str, outdir: &str) -> Result<()> {
+    let sqlite = SqlitePool::connect(&format!("sqlite://{}", dbfile)).await?;
+    let tables = schema::tables(&sqlite).await?;
+    for table in schema::PACKAGE_TABLES {
+        let columns = &tables
+            .iter()
+            .find(|x| x.0 == table)
+            .ok_or_else(|| anyhow!("{} has no {} table", dbfile, table))?
+            .1;
+        let file = format!("{}/{}.csv", outdir, table);
+        debug!("Exporting {} to {}", table, file);
+
+        let mut wtr = csv::Writer::from_path(&file)?;
+        wtr.write_record(columns.iter().map(|x| &x.name))?;
+        let select = format!(
+            r#"SELECT {} FROM "{}" ORDER BY rowid"#,
+            columns
+                .iter()
+                .map(|x| format!(r#"CAST("{}" AS TEXT)"#, x.name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            table
+        );
+        let rows = sqlx::query(&select).fetch_all(&sqlite).await?;
+        for row in &rows {
+            wtr.write_record(
+                (0..columns.len()).map(|i| row.get::<Option<String>, _>(i).unwrap_or_default()),
+            )?;
+        }
+        wtr.flush()?;
+        debug!("Exported {} rows of {}", rows.len(), table);
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use croner::Cron;
+use log::{debug, error, info};
+use sd_notify::NotifyState;
+
+use crate::metrics;
+
+/// When the running generation last made progress, `None` between the runs of --watch
+static LASTACTIVE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Parses an interval such as `90s`, `30m`, `6h` or `1d`, in seconds without a unit
+pub fn interval(s: &str) -> Result<Duration, String> {
+    let (count, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let count: u64 = count
+        .parse()
+        .map_err(|_| format!("{} is not an interval such as 30m or 6h", s))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit {}, expected s, m, h or d", unit)),
+    };
+    if count == 0 {
+        return Err("The interval must not be 0".to_string());
+    }
+    let seconds = count
+        .checked_mul(seconds)
+        .ok_or_else(|| format!("{} is too long an interval", s))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Parses a cron expression with five fields, minute, hour, day of month, month and day of week
+pub fn schedule(s: &str) -> Result<Cron, String> {
+    Cron::new(s).parse().map_err(|e| e.to_string())
+}
+
+/// When --watch runs again
+#[derive(Clone)]
+pub enum Rerun {
+    /// This long after the previous run finished
+    After(Duration),
+    /// At the next time a cron expression matches in local time
+    Schedule(Box<Cron>),
+}
+
+impl Rerun {
+    /// How long to wait from now and until when, for the log
+    fn wait(&self) -> Result<(Duration, String)> {
+        match self {
+            Rerun::After(interval) => Ok((*interval, format!("in {}s", interval.as_secs()))),
+            Rerun::Schedule(cron) => {
+                let now = Local::now();
+                let next = cron
+                    .find_next_occurrence(&now, false)
+                    .map_err(|e| anyhow!("{} never matches again: {}", cron, e))?;
+                Ok((
+                    (next - now).to_std().unwrap_or_default(),
+                    format!("at {}", next.format("%F %T %Z")),
+                ))
+            }
+        }
+    }
+}
+
+impl PartialEq for Rerun {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Rerun::After(x), Rerun::After(y)) => x == y,
+            (Rerun::Schedule(x), Rerun::Schedule(y)) => x.as_str() == y.as_str(),
+            _ => false,
+        }
+    }
+}
+
+/// Runs `run` now and then again as `rerun` says, until the process is stopped, serving the
+/// metrics of the runs on `metrics`. Failed runs are logged instead of stopping it.
+pub async fn watch<F, Fut>(rerun: Rerun, metrics: Option<SocketAddr>, mut run: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    if let Some(listen) = metrics {
+        metrics::spawn(listen)?;
+    }
+    notify(&[NotifyState::Ready]);
+    loop {
+        alive();
+        let outcome = match run().await {
+            Ok(true) => "Databases updated".to_string(),
+            Ok(false) => "Databases up to date".to_string(),
+            Err(e) => {
+                error!("{}", e);
+                format!("Failed: {}", e)
+            }
+        };
+        *LASTACTIVE.lock().unwrap() = None;
+        let (wait, next) = rerun.wait()?;
+        info!("{}, checking again {}", outcome, next);
+        status(&format!("{}, checking again {}", outcome, next));
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Sends `state` to systemd when running as a service of `Type=notify`
+fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(state) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Shows `status` as what the service is doing in `systemctl status`
+pub fn status(status: &str) {
+    notify(&[NotifyState::Status(status)]);
+}
+
+/// Records that the running generation made progress
+pub fn alive() {
+    *LASTACTIVE.lock().unwrap() = Some(Instant::now());
+}
+
+/// Pings the systemd watchdog, when the service has `WatchdogSec=` set, as long as the process is
+/// idle or made progress within the watchdog timeout. A generation stuck for longer, e.g. on a
+/// hung download, gets the service restarted.
+pub fn watchdog() {
+    let Some(timeout) = sd_notify::watchdog_enabled() else {
+        return;
+    };
+    debug!("Pinging the systemd watchdog every {:?}", timeout / 2);
+    alive();
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(timeout / 2);
+        loop {
+            ticks.tick().await;
+            if LASTACTIVE
+                .lock()
+                .unwrap()
+                .is_none_or(|x| x.elapsed() < timeout)
+            {
+                notify(&[NotifyState::Watchdog]);
+            }
+        }
+    });
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::query::Database;
+
+/// Differences between the packages of two package databases
+#[derive(Serialize)]
+pub struct Diff {
+    /// Packages only in the new database
+    pub added: Vec<PackageVersion>,
+    /// Packages only in the old database
+    pub removed: Vec<PackageVersion>,
+    /// Packages in both whose version changed
+    pub changed: Vec<VersionChange>,
+}
+
+/// A package added or removed
+#[derive(Serialize)]
+pub struct PackageVersion {
+    pub attribute: String,
+    pub version: Option<String>,
+}
+
+/// A package whose version changed
+#[derive(Serialize)]
+pub struct VersionChange {
+    pub attribute: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Versions of every package of `db`, by attribute
+async fn versions(db: &Database) -> Result<BTreeMap<String, Option<String>>> {
+    let rows: Vec<(String, Option<String>)> =
+        sqlx::query_as(r#"SELECT "attribute", "version" FROM "pkgs""#)
+            .fetch_all(&db.pool)
+            .await?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Compares the packages of the package databases `old` and `new`
+pub async fn diff(old: &str, new: &str) -> Result<Diff> {
+    let old = versions(&Database::open(old).await?).await?;
+    let mut new = versions(&Database::open(new).await?).await?;
+    let mut diff = Diff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+    };
+    for (attribute, version) in old {
+        match new.remove(&attribute) {
+            None => diff.removed.push(PackageVersion { attribute, version }),
+            Some(x) if x != version => diff.changed.push(VersionChange {
+                attribute,
+                old: version,
+                new: x,
+            }),
+            Some(_) => (),
+        }
+    }
+    diff.added = new
+        .into_iter()
+        .map(|(attribute, version)| PackageVersion { attribute, version })
+        .collect();
+    Ok(diff)
+}
+
+impl Diff {
+    /// Whether the databases hold the same package versions
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Prints a line per package, `+` when added, `-` when removed and `~` when its version
+    /// changed, then the totals
+    pub fn print(&self) {
+        let width = self
+            .added
+            .iter()
+            .chain(&self.removed)
+            .map(|x| x.attribute.len())
+            .chain(self.changed.iter().map(|x| x.attribute.len()))
+            .max()
+            .unwrap_or(0);
+        let version = |x: &Option<String>| x.clone().unwrap_or_else(|| "-".to_string());
+        for pkg in &self.added {
+            println!("+ {:<width$}  {}", pkg.attribute, version(&pkg.version));
+        }
+        for pkg in &self.removed {
+            println!("- {:<width$}  {}", pkg.attribute, version(&pkg.version));
+        }
+        for pkg in &self.changed {
+            println!(
+                "~ {:<width$}  {} -> {}",
+                pkg.attribute,
+                version(&pkg.old),
+                version(&pkg.new)
+            );
+        }
+        println!(
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        );
+    }
+}
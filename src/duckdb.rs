@@ -0,0 +1,88 @@
+use std::{fs, path::Path};
+
+use ::duckdb::{
+    appender_params_from_iter,
+    types::Value::{self, BigInt, Null, Text},
+    Connection,
+};
+use anyhow::Result;
+use log::debug;
+use sqlx::{Row, SqlitePool};
+
+use crate::schema;
+
+/// Rows read from SQLite at a time
+const BATCH_SIZE: usize = 10000;
+
+/// Copies the tables of the generated SQLite database `dbfile` into a new DuckDB database at
+/// `duckfile`. The full-text search index has no DuckDB counterpart and is left out.
+pub async fn export(dbfile: &str, duckfile: &str) -> Result<()> {
+    debug!("Exporting {} to {}", dbfile, duckfile);
+    if Path::new(duckfile).exists() {
+        fs::remove_file(duckfile)?;
+    }
+    let sqlite = SqlitePool::connect(&format!("sqlite://{}", dbfile)).await?;
+    let duck = Connection::open(duckfile)?;
+
+    for (table, columns) in schema::tables(&sqlite).await? {
+        duck.execute_batch(&format!(
+            r#"CREATE TABLE "{}" ({})"#,
+            table,
+            columns
+                .iter()
+                .map(|x| format!(
+                    r#""{}" {}"#,
+                    x.name,
+                    if x.integer { "BIGINT" } else { "VARCHAR" }
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))?;
+
+        // SQLite columns may hold any type, so read them as what they are declared as
+        let select = format!(
+            r#"SELECT {} FROM "{}" ORDER BY rowid LIMIT ? OFFSET ?"#,
+            columns
+                .iter()
+                .map(|x| format!(
+                    r#"CAST("{}" AS {})"#,
+                    x.name,
+                    if x.integer { "INTEGER" } else { "TEXT" }
+                ))
+                .collect::<Vec<_>>()
+                .join(", "),
+            table
+        );
+        let mut appender = duck.appender(&table)?;
+        let mut offset = 0;
+        loop {
+            let rows = sqlx::query(&select)
+                .bind(BATCH_SIZE as i64)
+                .bind(offset as i64)
+                .fetch_all(&sqlite)
+                .await?;
+            if rows.is_empty() {
+                break;
+            }
+            offset += rows.len();
+
+            for row in rows {
+                let values = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| {
+                        if x.integer {
+                            row.get::<Option<i64>, _>(i).map_or(Null, BigInt)
+                        } else {
+                            row.get::<Option<String>, _>(i).map_or(Null, Text)
+                        }
+                    })
+                    .collect::<Vec<Value>>();
+                appender.append_row(appender_params_from_iter(values))?;
+            }
+        }
+        appender.flush()?;
+        debug!("Exported {} rows of {}", offset, table);
+    }
+    Ok(())
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{
+    models::{Meta, NixosPkg},
+    stream::{self, PackageStream, ParseError},
+};
+
+/// Flake of the Nix User Repository
+pub const NUR_FLAKE: &str = "github:nix-community/NUR";
+
+/// Evaluates NUR against `<nixpkgs>` as its README suggests, marking each repo for `nix-env` to recurse into
+const NUR_EXPR: &str = r#"
+{ nurpath }:
+let
+  pkgs = import <nixpkgs> { config.allowUnfree = true; };
+  nur = import nurpath { nurpkgs = pkgs; inherit pkgs; };
+in
+builtins.mapAttrs (_: repo: repo // { recurseForDerivations = true; }) nur.repos
+"#;
+
+/// Resolves the aliases in `aliases.nix` to the attribute names they point to by handing it
+/// package names in place of packages. Aliases that throw or are not plain renames are dropped.
+const ALIASES_EXPR: &str = r#"
+{ nixpkgs }:
+let
+  lib = import (nixpkgs + "/lib");
+  pkgs = import nixpkgs { config.allowAliases = false; };
+  names = builtins.mapAttrs (name: _: name) pkgs;
+  aliases = import (nixpkgs + "/pkgs/top-level/aliases.nix") lib names names;
+  target = value:
+    let result = builtins.tryEval (if builtins.isString value then value else null);
+    in if result.success then result.value else null;
+in
+lib.filterAttrs (_: value: value != null) (builtins.mapAttrs (_: target) aliases)
+"#;
+
+/// A locked checkout of the Nix User Repository
+#[derive(Clone)]
+pub struct Nur {
+    pub path: String,
+    pub rev: String,
+}
+
+/// How a nixpkgs source tree is turned into a package list
+#[derive(Clone)]
+pub enum Evaluator {
+    /// `nix-env -qa`, matching what Hydra publishes as `packages.json`
+    NixEnv,
+    /// `nix-eval-jobs`, recursing into package sets that `packages.json` omits
+    EvalJobs { workers: usize, maxmemory: usize },
+}
+
+impl Evaluator {
+    /// Evaluates the packages of `path` on a blocking thread, waiting on nix and parsing its
+    /// output would stall the runtime for minutes
+    pub(crate) fn evaluate(&self, path: &str) -> PackageStream {
+        let (evaluator, path) = (self.clone(), path.to_string());
+        PackageStream::spawn(move |tx| match evaluator {
+            Evaluator::NixEnv => nixenv(&path, &tx),
+            Evaluator::EvalJobs { workers, maxmemory } => evaljobs(&path, workers, maxmemory, &tx),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EvalJob {
+    attr: String,
+    name: Option<String>,
+    system: Option<String>,
+    meta: Option<Meta>,
+    outputs: Option<HashMap<String, String>>,
+    error: Option<String>,
+}
+
+/// Locks `flakeref` and returns the store path of its source together with its revision
+pub async fn flakesource(flakeref: &str) -> Result<(String, String)> {
+    debug!("Locking flake {}", flakeref);
+    let output = tokio::process::Command::new("nix")
+        .arg("flake")
+        .arg("metadata")
+        .arg("--json")
+        .arg(flakeref)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to get flake metadata for {}: {}",
+            flakeref,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let metadata: Value = serde_json::from_slice(&output.stdout)?;
+    let path = metadata
+        .get("path")
+        .and_then(|x| x.as_str())
+        .context("Flake metadata has no store path")?;
+    let rev = metadata
+        .get("revision")
+        .and_then(|x| x.as_str())
+        .context("Flake metadata has no revision, is the flake dirty?")?;
+    Ok((path.to_string(), rev.to_string()))
+}
+
+/// Returns the git revision of the checkout at `path`, or `None` if it is not a clean git tree
+pub fn localrevision(path: &str) -> Option<String> {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("status")
+        .arg("--porcelain")
+        .output()
+        .ok()?;
+    if !status.status.success() || !status.stdout.is_empty() {
+        return None;
+    }
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("rev-parse")
+        .arg("HEAD")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+/// Splits a derivation name into pname and version the same way `builtins.parseDrvName` does
+fn parsedrvname(name: &str) -> (String, String) {
+    let mut chars = name.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '-' {
+            if let Some((_, next)) = chars.peek() {
+                if !next.is_alphabetic() {
+                    return (name[..i].to_string(), name[i + 1..].to_string());
+                }
+            }
+        }
+    }
+    (name.to_string(), String::new())
+}
+
+/// Evaluates every package in the nixpkgs tree at `path` with `nix-eval-jobs`, sending each to `tx`
+fn evaljobs(
+    path: &str,
+    workers: usize,
+    maxmemory: usize,
+    tx: &mpsc::Sender<(String, NixosPkg)>,
+) -> Result<Vec<ParseError>> {
+    debug!("Evaluating packages in {} with nix-eval-jobs", path);
+    let mut cmd = Command::new("nix-eval-jobs")
+        .arg("--meta")
+        .arg("--force-recurse")
+        .arg("--workers")
+        .arg(workers.to_string())
+        .arg("--max-memory-size")
+        .arg(maxmemory.to_string())
+        .arg("--expr")
+        .arg(format!(
+            "import {0} {{ config = import {0}/pkgs/top-level/packages-config.nix; }}",
+            path
+        ))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = cmd
+        .stdout
+        .take()
+        .context("Failed to capture nix-eval-jobs output")?;
+
+    for line in BufReader::new(stdout).lines() {
+        let job: EvalJob = serde_json::from_str(&line?)?;
+        if let Some(error) = job.error {
+            warn!("Failed to evaluate {}: {}", job.attr, error);
+            continue;
+        }
+        let (Some(name), Some(system)) = (job.name, job.system) else {
+            debug!("Skipping {}, not a derivation", job.attr);
+            continue;
+        };
+        let (pname, version) = parsedrvname(&name);
+        let package = NixosPkg {
+            name: Some(name.clone()),
+            pname,
+            version,
+            system,
+            meta: job.meta.unwrap_or_default(),
+            outputs: job
+                .outputs
+                .map(|x| x.into_iter().map(|(k, v)| (k, Some(v))).collect()),
+            outputname: None,
+        };
+        tx.blocking_send((job.attr, package))
+            .map_err(|_| anyhow!("Package receiver closed"))?;
+    }
+    let status = cmd.wait()?;
+    if !status.success() {
+        return Err(anyhow!("nix-eval-jobs exited with {}", status));
+    }
+    Ok(Vec::new())
+}
+
+/// Evaluates the nixpkgs tree at `path` the same way Hydra builds `packages.json`
+fn nixenv(path: &str, tx: &mpsc::Sender<(String, NixosPkg)>) -> Result<Vec<ParseError>> {
+    debug!("Evaluating packages in {}", path);
+    nixenvquery(
+        path,
+        &[
+            "--arg",
+            "config",
+            &format!("import {}/pkgs/top-level/packages-config.nix", path),
+        ],
+        tx,
+    )
+}
+
+/// Runs `nix-env -qa --json --meta --out-path` on `file` with the extra `args`, sending each
+/// package to `tx` as it is parsed and returning those that don't parse
+fn nixenvquery(
+    file: &str,
+    args: &[&str],
+    tx: &mpsc::Sender<(String, NixosPkg)>,
+) -> Result<Vec<ParseError>> {
+    let mut cmd = Command::new("nix-env")
+        .arg("-f")
+        .arg(file)
+        .arg("-qa")
+        .arg("--json")
+        .arg("--meta")
+        .arg("--out-path")
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = cmd
+        .stdout
+        .take()
+        .context("Failed to capture nix-env output")?;
+    let errors = stream::packages(BufReader::new(stdout), tx);
+    let status = cmd.wait()?;
+    if !status.success() {
+        return Err(anyhow!("nix-env exited with {}", status));
+    }
+    errors.context("Failed to parse nix-env output")
+}
+
+/// Evaluates every package of every NUR repository on a blocking thread like
+/// [`Evaluator::evaluate`], keyed by their `<repo>.<attr>` path below `nur.repos`
+pub(crate) fn nurpackages(nur: &Nur) -> PackageStream {
+    let nur = nur.clone();
+    PackageStream::spawn(move |tx| evalnur(&nur, &tx))
+}
+
+fn evalnur(nur: &Nur, tx: &mpsc::Sender<(String, NixosPkg)>) -> Result<Vec<ParseError>> {
+    debug!("Evaluating NUR packages in {}", nur.path);
+    // nix-env only reads its expression from a file, a private one so concurrent runs can't
+    // replace it
+    let mut exprfile = tempfile::Builder::new()
+        .prefix("nix-data-generator-nur")
+        .suffix(".nix")
+        .tempfile()?;
+    exprfile.write_all(NUR_EXPR.as_bytes())?;
+    nixenvquery(
+        &exprfile.path().to_string_lossy(),
+        &[
+            "--argstr",
+            "nurpath",
+            &nur.path,
+            "--option",
+            "allow-import-from-derivation",
+            "false",
+        ],
+        tx,
+    )
+}
+
+/// Evaluates `pkgs/top-level/aliases.nix` of the nixpkgs tree at `path` into alias -> attribute
+pub async fn aliases(path: &str) -> Result<HashMap<String, String>> {
+    debug!("Evaluating aliases in {}", path);
+    let output = tokio::process::Command::new("nix-instantiate")
+        .arg("--eval")
+        .arg("--strict")
+        .arg("--json")
+        .arg("--expr")
+        .arg(ALIASES_EXPR)
+        .arg("--argstr")
+        .arg("nixpkgs")
+        .arg(fs::canonicalize(path)?)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to evaluate aliases: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
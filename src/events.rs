@@ -0,0 +1,95 @@
+use std::{fmt, sync::Arc};
+
+use log::warn;
+use tokio::sync::mpsc;
+
+use crate::{daemon, summary::Summary};
+
+/// Something that happened while generating
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// A phase started, e.g. "Downloading packages.json.br of nixos-24.05.1234.abcdef"
+    Phase(String),
+    /// Packages inserted into the package database being built so far, sent after each batch
+    Packages(u64),
+    /// Something is wrong with the packages being built, e.g. some could not be parsed, logged as
+    /// a warning as well
+    Warning(String),
+    /// What check mode found for a package database
+    Checked(Check),
+    /// The run finished, also when some database failed
+    Summary(Summary),
+}
+
+/// Whether the package database of a source is up to date, found in check mode
+#[derive(Clone, Debug)]
+pub struct Check {
+    /// Channel, flake reference or nixpkgs path
+    pub source: String,
+    /// Version it would be built from, `None` for a tree without one
+    pub version: Option<String>,
+    /// Version the package database was built from
+    pub built: Option<String>,
+    pub uptodate: bool,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = self.version.as_deref().unwrap_or("its working tree");
+        match &self.built {
+            _ if self.uptodate => write!(f, "{}: {} is up to date", self.source, version),
+            Some(built) if built == version => {
+                write!(f, "{}: {} would be rebuilt", self.source, version)
+            }
+            Some(built) => write!(
+                f,
+                "{}: {} would be updated to {}",
+                self.source, built, version
+            ),
+            None => write!(f, "{}: {} would be built", self.source, version),
+        }
+    }
+}
+
+type Listener = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// Listeners to the events of a generation
+#[derive(Clone, Default)]
+pub struct Events {
+    listeners: Vec<Listener>,
+}
+
+impl Events {
+    /// Calls `listener` with every event, from whichever thread it happens on
+    pub fn listen(&mut self, listener: impl Fn(&Event) + Send + Sync + 'static) {
+        self.listeners.push(Arc::new(listener));
+    }
+
+    /// Every event from now on
+    pub fn stream(&mut self) -> mpsc::UnboundedReceiver<Event> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.listen(move |x| {
+            // Nothing to do once the receiver is dropped
+            let _ = tx.send(x.clone());
+        });
+        rx
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        for listener in &self.listeners {
+            listener(&event);
+        }
+    }
+
+    /// Starts `phase`, telling systemd about it as well
+    pub(crate) fn phase(&self, phase: &str) {
+        daemon::status(phase);
+        self.emit(Event::Phase(phase.to_string()));
+    }
+
+    /// Logs the warning `message` and sends it to the listeners
+    pub(crate) fn warning(&self, message: String) {
+        warn!("{}", message);
+        self.emit(Event::Warning(message));
+    }
+}
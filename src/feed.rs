@@ -0,0 +1,130 @@
+use std::fs;
+
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use sqlx::{FromRow, SqlitePool};
+
+use crate::schema;
+
+/// File name of the feed written next to a rebuilt package database
+pub const FEED_FILE: &str = "updates.atom";
+
+/// Most updates a feed lists
+const FEED_ENTRIES: i64 = 100;
+
+/// A package version that replaced an earlier one in a channel
+#[derive(FromRow)]
+pub struct Update {
+    pub channel: String,
+    pub attribute: String,
+    pub version: String,
+    pub previous: String,
+    /// When the version was first built, RFC 3339
+    pub first_seen: String,
+}
+
+/// Newest version bumps recorded in the history database `pool`, only of `channel` and of the
+/// packages of `watchlist` unless they are `None` and empty
+pub async fn updates(
+    pool: &SqlitePool,
+    channel: Option<&str>,
+    watchlist: &[String],
+) -> Result<Vec<Update>> {
+    let watchlist = (!watchlist.is_empty())
+        .then(|| serde_json::to_string(watchlist))
+        .transpose()?;
+    Ok(sqlx::query_as(
+        r#"
+        SELECT "n"."channel", "n"."attribute", "n"."version", "n"."first_seen", (
+            SELECT "o"."version" FROM "versions" AS "o"
+            WHERE "o"."channel" = "n"."channel" AND "o"."attribute" = "n"."attribute"
+            AND "o"."first_seen" < "n"."first_seen"
+            ORDER BY "o"."first_seen" DESC LIMIT 1
+        ) AS "previous"
+        FROM "versions" AS "n"
+        WHERE "previous" IS NOT NULL
+        AND (?1 IS NULL OR "n"."channel" = ?1)
+        AND (?2 IS NULL OR "n"."attribute" IN (SELECT "value" FROM json_each(?2)))
+        ORDER BY "n"."first_seen" DESC, "n"."attribute"
+        LIMIT ?3
+        "#,
+    )
+    .bind(channel)
+    .bind(watchlist)
+    .bind(FEED_ENTRIES)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Escapes `value` for XML text and attributes
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `updates` as an Atom feed of the updates of `channel`, or of every channel
+pub fn atom(channel: Option<&str>, updates: &[Update]) -> String {
+    let (id, title) = match channel {
+        Some(channel) => (channel, format!("Package updates of {}", channel)),
+        None => ("all", "Package updates".to_string()),
+    };
+    let updated = updates
+        .first()
+        .map(|x| x.first_seen.clone())
+        .unwrap_or_else(|| Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+    let mut out = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n  \
+        <id>tag:nix-data-generator,2024:{}</id>\n  \
+        <title>{}</title>\n  \
+        <updated>{}</updated>\n  \
+        <generator>{}</generator>\n",
+        escape(id),
+        escape(&title),
+        updated,
+        env!("CARGO_PKG_NAME")
+    );
+    for update in updates {
+        let summary = format!(
+            "{} {} to {} in {}",
+            update.attribute, update.previous, update.version, update.channel
+        );
+        out.push_str(&format!(
+            "  <entry>\n    \
+            <id>tag:nix-data-generator,2024:{}/{}/{}</id>\n    \
+            <title>{} {}</title>\n    \
+            <updated>{}</updated>\n    \
+            <author><name>{}</name></author>\n    \
+            <summary>{}</summary>\n  \
+            </entry>\n",
+            escape(&update.channel),
+            escape(&update.attribute),
+            escape(&update.version),
+            escape(&update.attribute),
+            escape(&update.version),
+            update.first_seen,
+            escape(&update.channel),
+            escape(&summary)
+        ));
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+/// Writes the feed of the updates of `channel` recorded in `historyfile` to `file`, only of the
+/// packages of `watchlist` unless it is empty
+pub async fn write(
+    historyfile: &str,
+    channel: &str,
+    watchlist: &[String],
+    file: &str,
+) -> Result<()> {
+    let pool = schema::keepdb(historyfile, &schema::HISTORY).await?;
+    let updates = updates(&pool, Some(channel), watchlist).await;
+    pool.close().await;
+    fs::write(file, atom(Some(channel), &updates?))?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::env;
+
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use reqwest::{Body, Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+
+/// GitHub REST API, unless `GITHUB_API_URL` points at GitHub Enterprise
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// A GitHub repository to publish releases to, with the token of `GITHUB_TOKEN`
+pub struct Repository {
+    /// `owner/repo`
+    name: String,
+    api: String,
+    token: String,
+}
+
+/// A release as returned by the API
+#[derive(Deserialize)]
+pub struct Release {
+    id: u64,
+    /// URL template assets are uploaded to, e.g. `.../assets{?name,label}`
+    upload_url: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    id: u64,
+    name: String,
+}
+
+impl Repository {
+    /// The repository `name` with the token of the environment
+    pub fn fromenv(name: &str) -> Result<Self> {
+        Ok(Repository {
+            name: name.to_string(),
+            api: env::var("GITHUB_API_URL")
+                .ok()
+                .filter(|x| !x.is_empty())
+                .unwrap_or_else(|| GITHUB_API_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            token: env::var("GITHUB_TOKEN")
+                .ok()
+                .filter(|x| !x.is_empty())
+                .ok_or_else(|| anyhow!("GITHUB_TOKEN is needed to publish to GitHub"))?,
+        })
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+            .bearer_auth(&self.token)
+            .header("accept", "application/vnd.github+json")
+            .header("x-github-api-version", "2022-11-28")
+    }
+
+    /// The release tagged `tag`, created along with the tag when there is none yet
+    pub async fn release(&self, client: &Client, tag: &str) -> Result<Release> {
+        let response = self
+            .authorize(client.get(format!(
+                "{}/repos/{}/releases/tags/{}",
+                self.api, self.name, tag
+            )))
+            .send()
+            .await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            return Ok(serde_json::from_slice(
+                &response.error_for_status()?.bytes().await?,
+            )?);
+        }
+        info!("Creating release {} of {}", tag, self.name);
+        let response = self
+            .authorize(client.post(format!("{}/repos/{}/releases", self.api, self.name)))
+            .header("content-type", "application/json")
+            .body(serde_json::to_vec(
+                &serde_json::json!({ "tag_name": tag, "name": tag }),
+            )?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    /// Attaches `file` to `release` as `name`, replacing the asset of that name it already has
+    pub async fn upload(
+        &self,
+        client: &Client,
+        release: &Release,
+        name: &str,
+        file: &str,
+    ) -> Result<()> {
+        // Assets can't be overwritten, only deleted and uploaded again
+        if let Some(asset) = release.assets.iter().find(|x| x.name == name) {
+            debug!("Deleting the previous {} of release {}", name, release.id);
+            self.authorize(client.delete(format!(
+                "{}/repos/{}/releases/assets/{}",
+                self.api, self.name, asset.id
+            )))
+            .send()
+            .await?
+            .error_for_status()?;
+        }
+        debug!("Uploading {} to release {}", file, release.id);
+        let url = release
+            .upload_url
+            .split('{')
+            .next()
+            .unwrap_or(&release.upload_url);
+        self.authorize(client.post(url))
+            .query(&[("name", name)])
+            .header("content-length", tokio::fs::metadata(file).await?.len())
+            .header("content-type", "application/octet-stream")
+            .body(Body::wrap_stream(ReaderStream::new(
+                tokio::fs::File::open(file).await?,
+            )))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
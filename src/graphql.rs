@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use async_graphql::{
+    http::GraphiQLSource, Context, EmptyMutation, EmptySubscription, Object, Result, Schema,
+    SimpleObject,
+};
+use axum::{
+    extract::State,
+    response::{Html, IntoResponse},
+    Json,
+};
+use sqlx::FromRow;
+use tokio::sync::OnceCell;
+
+use crate::{
+    query,
+    serve::{self, Databases, Search},
+};
+
+/// Deepest a query may nest
+const MAX_DEPTH: usize = 10;
+
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// The GraphQL schema over the databases of `dbs`
+pub fn schema(dbs: Arc<Databases>) -> ApiSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(dbs)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// Answers a GraphQL request
+pub async fn graphql(
+    State(schema): State<ApiSchema>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(schema.execute(request).await)
+}
+
+/// GraphiQL to try queries in the browser
+pub async fn graphiql() -> impl IntoResponse {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+pub struct QueryRoot;
+
+#[Object(name = "Query")]
+impl QueryRoot {
+    /// Packages matching `search` in their names or descriptions, best matches first, or every
+    /// package by attribute
+    async fn packages(
+        &self,
+        ctx: &Context<'_>,
+        search: Option<String>,
+        #[graphql(default = 1)] page: u32,
+        #[graphql(default_with = "serve::PER_PAGE")] per_page: u32,
+    ) -> Result<PackagePage> {
+        let dbs = ctx.data::<Arc<Databases>>()?;
+        let page = dbs
+            .packages(&Search {
+                search,
+                page,
+                per_page,
+            })
+            .await?;
+        Ok(PackagePage {
+            total: page.total,
+            page: page.page,
+            per_page: page.per_page,
+            items: page.items.into_iter().map(Package::new).collect(),
+        })
+    }
+
+    /// The package with the attribute `attribute`
+    async fn package(&self, ctx: &Context<'_>, attribute: String) -> Result<Option<Package>> {
+        let dbs = ctx.data::<Arc<Databases>>()?;
+        Ok(dbs
+            .packages
+            .get_package(&attribute)
+            .await?
+            .map(|details| Package {
+                summary: query::PackageSummary {
+                    attribute: details.attribute.clone(),
+                    pname: details.pname.clone(),
+                    version: details.version.clone(),
+                    description: details.description.clone(),
+                },
+                details: OnceCell::new_with(Some(Some(details))),
+            }))
+    }
+}
+
+/// One page of packages
+#[derive(SimpleObject)]
+struct PackagePage {
+    /// Packages on every page
+    total: i64,
+    page: u32,
+    per_page: u32,
+    items: Vec<Package>,
+}
+
+/// A package, its metadata is only read when asked for
+struct Package {
+    summary: query::PackageSummary,
+    details: OnceCell<Option<query::Package>>,
+}
+
+impl Package {
+    fn new(summary: query::PackageSummary) -> Self {
+        Package {
+            summary,
+            details: OnceCell::new(),
+        }
+    }
+
+    async fn details(&self, ctx: &Context<'_>) -> Result<Option<&query::Package>> {
+        let dbs = ctx.data::<Arc<Databases>>()?;
+        Ok(self
+            .details
+            .get_or_try_init(|| dbs.packages.get_package(&self.summary.attribute))
+            .await?
+            .as_ref())
+    }
+}
+
+#[Object]
+impl Package {
+    async fn attribute(&self) -> &str {
+        &self.summary.attribute
+    }
+
+    async fn pname(&self) -> Option<&str> {
+        self.summary.pname.as_deref()
+    }
+
+    async fn version(&self) -> Option<&str> {
+        self.summary.version.as_deref()
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.summary.description.as_deref()
+    }
+
+    /// System the package was evaluated for
+    async fn system(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        Ok(self.details(ctx).await?.and_then(|x| x.system.clone()))
+    }
+
+    /// Outputs of the package
+    async fn outputs(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        Ok(serve::jsonlist(
+            self.details(ctx).await?.and_then(|x| x.outputs.as_deref()),
+        ))
+    }
+
+    async fn meta(&self, ctx: &Context<'_>) -> Result<Option<Meta>> {
+        Ok(self.details(ctx).await?.map(|x| Meta {
+            broken: x.broken,
+            insecure: x.insecure,
+            unsupported: x.unsupported,
+            unfree: x.unfree,
+            long_description: x.longdescription.clone(),
+            homepage: x.homepage.clone(),
+            main_program: x.mainprogram.clone(),
+            changelog: x.changelog.clone(),
+            position: x.position.clone(),
+            source_url: x.source_url.clone(),
+            platforms: serve::jsonlist(x.platforms.as_deref()),
+            known_vulnerabilities: serve::jsonlist(x.knownvulnerabilities.as_deref()),
+            permitted_insecure: x.permitted_insecure.clone(),
+        }))
+    }
+
+    /// Maintainers listed one by one, then the members of its teams
+    async fn maintainers(&self, ctx: &Context<'_>) -> Result<Vec<Maintainer>> {
+        let dbs = ctx.data::<Arc<Databases>>()?;
+        Ok(dbs
+            .packages
+            .maintainers(&self.summary.attribute)
+            .await?
+            .into_iter()
+            .map(|x| Maintainer {
+                github: x.github,
+                github_id: x.githubid,
+                name: x.name,
+                email: x.email,
+                matrix: x.matrix,
+                team: x.team,
+            })
+            .collect())
+    }
+
+    async fn licenses(&self, ctx: &Context<'_>) -> Result<Vec<License>> {
+        let dbs = ctx.data::<Arc<Databases>>()?;
+        Ok(sqlx::query_as(
+            r#"SELECT "licenses"."name", "spdxid", "fullname", "free", "url"
+            FROM "pkglicenses"
+            JOIN "licenses" ON "licenses"."name" = "pkglicenses"."license"
+            WHERE "pkglicenses"."attribute" = ?
+            ORDER BY "licenses"."name""#,
+        )
+        .bind(&self.summary.attribute)
+        .fetch_all(&dbs.packages.pool)
+        .await?)
+    }
+}
+
+/// Metadata of a package from its `meta` attribute
+#[derive(SimpleObject)]
+struct Meta {
+    broken: Option<bool>,
+    insecure: Option<bool>,
+    unsupported: Option<bool>,
+    unfree: Option<bool>,
+    long_description: Option<String>,
+    homepage: Option<String>,
+    main_program: Option<String>,
+    changelog: Option<String>,
+    /// File and line the package is defined at
+    position: Option<String>,
+    /// The source at `position` on GitHub
+    source_url: Option<String>,
+    platforms: Vec<String>,
+    known_vulnerabilities: Vec<String>,
+    /// Name to add to permittedInsecurePackages when insecure
+    permitted_insecure: Option<String>,
+}
+
+#[derive(SimpleObject)]
+struct Maintainer {
+    github: Option<String>,
+    github_id: Option<i64>,
+    name: Option<String>,
+    email: Option<String>,
+    matrix: Option<String>,
+    /// Team the maintainer is a member of, when not listed directly
+    team: Option<String>,
+}
+
+#[derive(SimpleObject, FromRow)]
+struct License {
+    /// Short name of the license in nixpkgs
+    name: String,
+    #[sqlx(rename = "spdxid")]
+    spdx_id: Option<String>,
+    #[sqlx(rename = "fullname")]
+    full_name: Option<String>,
+    free: Option<bool>,
+    url: Option<String>,
+}
@@ -0,0 +1,125 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use log::{error, info};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::serve::{self, Databases, Search};
+
+/// Types and service generated from `proto/nixdata.proto`
+pub mod proto {
+    tonic::include_proto!("nixdata");
+}
+
+use proto::packages_server::{Packages, PackagesServer};
+
+/// Serves the `nixdata.Packages` service over `dbs` on `listen`
+pub async fn serve(dbs: Arc<Databases>, listen: SocketAddr) -> Result<()> {
+    info!("Serving gRPC on {}", listen);
+    Server::builder()
+        .add_service(PackagesServer::new(PackagesService { dbs }))
+        .serve(listen)
+        .await?;
+    Ok(())
+}
+
+struct PackagesService {
+    dbs: Arc<Databases>,
+}
+
+/// Logs a failed lookup and answers it as an internal error
+fn internal(e: anyhow::Error) -> Status {
+    error!("{}", e);
+    Status::internal(e.to_string())
+}
+
+#[tonic::async_trait]
+impl Packages for PackagesService {
+    async fn get_package(
+        &self,
+        request: Request<proto::GetPackageRequest>,
+    ) -> Result<Response<proto::Package>, Status> {
+        let attribute = request.into_inner().attribute;
+        let package = self
+            .dbs
+            .packages
+            .get_package(&attribute)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| Status::not_found(format!("No package {}", attribute)))?;
+        Ok(Response::new(proto::Package {
+            outputs: serve::jsonlist(package.outputs.as_deref()),
+            platforms: serve::jsonlist(package.platforms.as_deref()),
+            known_vulnerabilities: serve::jsonlist(package.knownvulnerabilities.as_deref()),
+            attribute: package.attribute,
+            pname: package.pname,
+            version: package.version,
+            system: package.system,
+            description: package.description,
+            long_description: package.longdescription,
+            homepage: package.homepage,
+            broken: package.broken,
+            insecure: package.insecure,
+            unsupported: package.unsupported,
+            unfree: package.unfree,
+            main_program: package.mainprogram,
+            source_url: package.source_url,
+            permitted_insecure: package.permitted_insecure,
+        }))
+    }
+
+    async fn search_packages(
+        &self,
+        request: Request<proto::SearchPackagesRequest>,
+    ) -> Result<Response<proto::SearchPackagesResponse>, Status> {
+        let request = request.into_inner();
+        let page = self
+            .dbs
+            .packages(&Search {
+                search: Some(request.search),
+                page: request.page,
+                per_page: match request.per_page {
+                    0 => serve::PER_PAGE,
+                    x => x,
+                },
+            })
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::SearchPackagesResponse {
+            total: page.total,
+            page: page.page,
+            per_page: page.per_page,
+            packages: page
+                .items
+                .into_iter()
+                .map(|x| proto::PackageSummary {
+                    attribute: x.attribute,
+                    pname: x.pname,
+                    version: x.version,
+                    description: x.description,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_versions(
+        &self,
+        request: Request<proto::GetVersionsRequest>,
+    ) -> Result<Response<proto::GetVersionsResponse>, Status> {
+        let versions = self
+            .dbs
+            .packages
+            .versions_of(&request.into_inner().pname)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::GetVersionsResponse {
+            versions: versions
+                .into_iter()
+                .map(|x| proto::PackageVersion {
+                    attribute: x.attribute,
+                    version: x.version,
+                })
+                .collect(),
+        }))
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use log::debug;
+
+use crate::schema;
+
+/// File name of the history database, shared by every channel generated into a directory
+pub const HISTORY_DB: &str = "history.db";
+
+/// Records the package versions of the package database `dbfile` of `channel` in the history
+/// database `historyfile`, as first seen now when new and as last seen now otherwise
+pub async fn record(historyfile: &str, channel: &str, dbfile: &str) -> Result<()> {
+    let pool = schema::keepdb(historyfile, &schema::HISTORY).await?;
+    // ATTACH only applies to the connection it runs on
+    let mut conn = pool.acquire().await?;
+    sqlx::query(r#"ATTACH DATABASE ? AS "built""#)
+        .bind(dbfile)
+        .execute(&mut *conn)
+        .await?;
+    let recorded = sqlx::query(
+        r#"
+        INSERT INTO "versions" ("channel", "attribute", "version", "first_seen", "last_seen")
+        SELECT ?, "attribute", "version", strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+        FROM "built"."pkgs" WHERE "version" IS NOT NULL
+        ON CONFLICT ("channel", "attribute", "version") DO UPDATE SET "last_seen" = "excluded"."last_seen"
+        "#,
+    )
+    .bind(channel)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    sqlx::query(r#"DETACH DATABASE "built""#)
+        .execute(&mut *conn)
+        .await?;
+    drop(conn);
+    pool.close().await;
+    debug!("Recorded {} package versions of {}", recorded, channel);
+    Ok(())
+}
@@ -0,0 +1,2006 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::{debug, error, info};
+use rayon::prelude::*;
+use serde_json::Value;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use tokio::sync::mpsc;
+use unicode_normalization::UnicodeNormalization;
+
+pub use builder::GeneratorBuilder;
+use models::{License, NixosPkg, Team};
+
+mod advisories;
+mod archive;
+pub mod bench;
+mod builder;
+pub mod cache;
+mod changelog;
+pub mod channel;
+pub mod chat;
+mod combined;
+mod compress;
+pub mod config;
+mod csvexport;
+pub mod daemon;
+pub mod diff;
+#[cfg(feature = "duckdb")]
+mod duckdb;
+pub mod eval;
+pub mod events;
+mod feed;
+mod github;
+mod graphql;
+mod grpc;
+mod history;
+mod metrics;
+/// Packages as laid out in `packages.json`, public with the models feature
+#[cfg(feature = "models")]
+pub mod models;
+#[cfg(not(feature = "models"))]
+mod models;
+mod msgpack;
+mod mysql;
+pub mod options;
+mod parquet;
+mod programs;
+pub mod progress;
+pub mod publish;
+pub mod query;
+pub mod registry;
+mod repology;
+mod s3;
+mod schema;
+pub mod serve;
+mod sftp;
+pub mod source;
+mod split;
+mod stream;
+pub mod summary;
+pub mod verify;
+mod warnings;
+mod webhook;
+
+/// Nixpkgs repository that package positions link into
+const NIXPKGS_URL: &str = "https://github.com/NixOS/nixpkgs";
+
+/// Formats the package database can be written in
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// nixpkgs.db
+    Sqlite,
+    /// nixpkgs.duckdb, needs the duckdb feature
+    Duckdb,
+}
+
+/// Files the package tables can be exported to
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Export {
+    /// pkgs.parquet and meta.parquet
+    Parquet,
+    /// nixpkgs.msgpack
+    Msgpack,
+    /// pkgs.csv and meta.csv
+    Csv,
+}
+
+/// Compression of the published copies of the databases
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// <name>.db.zst
+    Zstd,
+}
+
+/// Format of the changelog written next to a rebuilt package database
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChangelogFormat {
+    /// <name>.changelog.md
+    Markdown,
+    /// <name>.changelog.json
+    Json,
+}
+
+/// Which package is kept of those whose attributes collide, exactly or, when mirrored into MySQL
+/// which compares them regardless of case, only by case
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Duplicates {
+    /// Keep the package read first
+    FirstWins,
+    /// Keep the package read last
+    LastWins,
+    /// Fail the build
+    Error,
+}
+
+impl Export {
+    /// Files the export of the package database named after `stem` writes
+    fn files(self, stem: &str) -> Vec<String> {
+        match self {
+            Export::Parquet => vec!["pkgs.parquet".to_string(), "meta.parquet".to_string()],
+            Export::Msgpack => vec![format!("{}.msgpack", stem)],
+            Export::Csv => vec!["pkgs.csv".to_string(), "meta.csv".to_string()],
+        }
+    }
+}
+
+/// Settings shared by every database built in one run
+#[derive(Clone)]
+struct BuildConfig {
+    /// Systems to record availability for, the first supported one is used as the package system
+    systems: Vec<String>,
+    /// Drop packages that are not available on any of `systems`
+    filtersystems: bool,
+    /// Write a database per system in `systems`
+    splitbysystem: bool,
+    /// Also index the Nix User Repository at this checkout
+    nur: Option<eval::Nur>,
+    /// Binary cache to check output paths against
+    cache: Option<String>,
+    /// Match packages against the CVEs published by the NVD
+    advisories: bool,
+    /// NVD API key, raises the NVD rate limit
+    nvdapikey: Option<String>,
+    /// Look up upstream versions on Repology
+    repology: bool,
+    /// Proxy to reach Repology, the NVD and the binary cache through
+    proxy: Option<String>,
+    /// JSON file of aliases to record instead of evaluating aliases.nix
+    aliases: Option<String>,
+    /// Import the channel's programs.sqlite
+    programs: bool,
+    /// MySQL database to mirror nixpkgs.db into
+    mysql: Option<String>,
+    /// Also write nixpkgs.duckdb
+    duckdb: bool,
+    /// Files to export the package tables to
+    exports: Vec<Export>,
+    /// Compression of the database copies to publish
+    compress: Option<Compression>,
+    /// Changes to the package database to write out when it is rebuilt
+    changelog: Option<ChangelogFormat>,
+    /// Write added.json and removed.json when the package database is rebuilt
+    addedremoved: bool,
+    /// History database to record the package versions of each rebuilt database in
+    history: Option<String>,
+    /// Packages to write the feed of updates of from the history database, every one when empty
+    feed: Option<Vec<String>>,
+    /// Where to upload rebuilt databases to
+    publish: Vec<publish::Target>,
+    /// URLs to announce rebuilt databases to
+    webhooks: Vec<reqwest::Url>,
+    /// Chats to tell about updated and failed databases
+    chat: chat::Chat,
+    /// Rows inserted per transaction
+    batchsize: usize,
+    /// How progress is shown
+    progress: progress::Progress,
+    /// File name of the package database
+    dbname: String,
+    /// Rebuild even when the databases are up to date
+    force: bool,
+    /// Only print what would be rebuilt
+    check: bool,
+    /// Fail when more than this share of the packages can't be parsed, instead of leaving them out
+    strict: Option<f64>,
+    /// Which package of colliding attributes is kept
+    duplicates: Duplicates,
+    /// Archives of replaced package databases to keep
+    keep: Option<usize>,
+    /// Listeners to phases and progress
+    events: events::Events,
+}
+
+impl BuildConfig {
+    /// `dbname` without its extension, the other databases and the version markers are named
+    /// after it
+    fn stem(&self) -> &str {
+        Path::new(&self.dbname)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap_or(&self.dbname)
+    }
+
+    /// Files written next to the package database besides the versions database
+    fn outputs(&self) -> Vec<String> {
+        let mut outputs = Vec::new();
+        if self.duckdb {
+            outputs.push(format!("{}.duckdb", self.stem()));
+        }
+        outputs.extend(self.exports.iter().flat_map(|x| x.files(self.stem())));
+        if self.splitbysystem {
+            outputs.extend(self.systems.iter().map(|x| split::dbname(self.stem(), x)));
+        }
+        if self.compress == Some(Compression::Zstd) {
+            outputs.extend(self.databases().iter().map(|x| format!("{}.zst", x)));
+        }
+        outputs
+    }
+
+    /// Databases written next to the package database, including itself
+    fn databases(&self) -> Vec<String> {
+        let mut databases = vec![self.dbname.clone(), self.versionsdb()];
+        if self.splitbysystem {
+            databases.extend(self.systems.iter().map(|x| split::dbname(self.stem(), x)));
+        }
+        databases
+    }
+
+    /// File name of the versions database
+    fn versionsdb(&self) -> String {
+        format!("{}_versions.db", self.stem())
+    }
+
+    /// This config for building `channel`, whose packages.json is always evaluated on
+    /// x86_64-linux, so darwin channels default to darwin systems
+    fn forchannel(&self, channel: &str) -> BuildConfig {
+        let mut config = self.clone();
+        if channel::isdarwin(channel) && config.systems.is_empty() {
+            config.systems = channel::DARWIN_SYSTEMS
+                .iter()
+                .map(|x| x.to_string())
+                .collect();
+        }
+        config
+    }
+
+    /// What packages whose attributes collide share, the attribute lowercased when mirrored into
+    /// MySQL which compares them regardless of case
+    fn attributekey(&self, attribute: &str) -> String {
+        match self.mysql {
+            Some(_) => attribute.to_lowercase(),
+            None => attribute.to_string(),
+        }
+    }
+
+    /// File name of the changelog written as `format`
+    fn changelogfile(&self, format: ChangelogFormat) -> String {
+        match format {
+            ChangelogFormat::Markdown => format!("{}.changelog.md", self.stem()),
+            ChangelogFormat::Json => format!("{}.changelog.json", self.stem()),
+        }
+    }
+
+    /// File name of the marker in the source directory ending in `extension`, `ver` for the
+    /// version built, `etag` for the validators of its `packages.json.br` and `nur` for the NUR
+    /// revision indexed
+    fn marker(&self, extension: &str) -> String {
+        format!("{}.{}", self.stem(), extension)
+    }
+}
+
+/// What a database was generated from
+struct Source {
+    /// Channel name, flake reference or nixpkgs path
+    name: String,
+    /// Resolved release version or revision
+    version: String,
+    /// Nixpkgs git revision, when known
+    revision: Option<String>,
+    /// Nixpkgs source tree, when evaluated locally, absolute like the package positions in it
+    path: Option<String>,
+}
+
+/// Splits a `meta.position` into the file relative to the nixpkgs root and its line, the root
+/// being the store path or the source `tree` it was evaluated from
+fn splitposition<'a>(position: &'a str, tree: Option<&str>) -> (&'a str, Option<u32>) {
+    let (file, line) = match position.rsplit_once(':') {
+        Some((file, line)) if line.parse::<u32>().is_ok() => (file, line.parse().ok()),
+        _ => (position, None),
+    };
+    // Evaluated trees report absolute paths
+    let file = tree
+        .and_then(|x| file.strip_prefix(x.trim_end_matches('/')))
+        .and_then(|x| x.strip_prefix('/'))
+        .or_else(|| {
+            file.strip_prefix("/nix/store/")
+                .and_then(|x| x.split_once('/'))
+                .map(|x| x.1)
+        })
+        .unwrap_or(file);
+    (file, line)
+}
+
+/// Normalizes a package description so it compares and searches the same in every channel: NFC,
+/// runs of control characters other than newlines as one space and no trailing whitespace on any
+/// line
+fn normalize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut control = false;
+    for c in text.replace("\r\n", "\n").nfc() {
+        if c.is_control() && c != '\n' {
+            if !control {
+                out.push(' ');
+            }
+            control = true;
+        } else {
+            out.push(c);
+            control = false;
+        }
+    }
+    out.lines()
+        .map(|x| x.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Hash part of a store path
+fn storehash(path: &str) -> Option<&str> {
+    path.strip_prefix("/nix/store/")
+        .and_then(|x| x.split('-').next())
+}
+
+/// What the generate subcommand builds and how, `GenerateConfig::new` has the defaults of its
+/// flags. Settings are added to it over time, `GeneratorBuilder` sets them without naming every
+/// one.
+#[non_exhaustive]
+pub struct GenerateConfig {
+    /// Channels to build, each into its own subdirectory when there are several
+    pub channels: Vec<String>,
+    /// Also merge every channel into one nixpkgs_combined.db with a channel column
+    pub combined: bool,
+    /// Exact nixpkgs git revision to build, looked up in the releases of the only channel or
+    /// evaluated from GitHub
+    pub rev: Option<String>,
+    /// Nixpkgs flake reference to evaluate instead of a channel
+    pub flake: Option<String>,
+    /// Local nixpkgs checkout to evaluate instead of a channel
+    pub nixpkgspath: Option<String>,
+    /// How `rev`, `flake` and `nixpkgspath` are evaluated
+    pub evaluator: eval::Evaluator,
+    /// Package source of your own to build instead of a channel
+    pub source: Option<Box<dyn source::PackageSource>>,
+    /// Also index the Nix User Repository, evaluated against <nixpkgs>
+    pub nur: bool,
+    /// Binary cache to check output paths against
+    pub cache: Option<String>,
+    /// Match packages against the CVEs published by the NVD
+    pub advisories: bool,
+    /// NVD API key, raises the NVD rate limit
+    pub nvdapikey: Option<String>,
+    /// Look up upstream versions on Repology
+    pub repology: bool,
+    /// Import the programs.sqlite of nixos-* channels
+    pub programs: bool,
+    /// JSON file of aliases to record instead of evaluating aliases.nix
+    pub aliases: Option<String>,
+    /// MySQL database to mirror the package database into
+    pub mysql: Option<String>,
+    /// Formats to write the package database in, nixpkgs.db is always kept
+    pub formats: Vec<Format>,
+    /// Files to export the package tables to
+    pub exports: Vec<Export>,
+    /// Compression of the database copies to publish
+    pub compress: Option<Compression>,
+    /// Write what changed since the previous package database when it is rebuilt
+    pub changelog: Option<ChangelogFormat>,
+    /// Write the packages added and removed since the previous package database to added.json
+    /// and removed.json next to it when it is rebuilt
+    pub addedremoved: bool,
+    /// Record when each package version was first and last built in history.db of `output`
+    pub history: bool,
+    /// Write an Atom feed of the version bumps recorded in history.db next to each rebuilt
+    /// package database
+    pub feed: bool,
+    /// Packages to list in the feed, every one when empty
+    pub feedpackages: Vec<String>,
+    /// Where to upload rebuilt databases to
+    pub publish: Vec<publish::Target>,
+    /// URLs to announce rebuilt databases to
+    pub webhooks: Vec<reqwest::Url>,
+    /// Chats to tell about updated and failed databases
+    pub chat: chat::Chat,
+    /// Systems to record availability for
+    pub systems: Vec<String>,
+    /// Drop packages that are not available on any of `systems`
+    pub filtersystems: bool,
+    /// Write a database per system in `systems`
+    pub splitbysystem: bool,
+    /// Source directory, holding the version markers and downloads
+    pub src: String,
+    /// Directory to write the databases to, `src` when `None`
+    pub output: Option<String>,
+    /// File name of the package database
+    pub dbname: String,
+    /// Rebuild even when the databases are up to date
+    pub force: bool,
+    /// Archives of replaced package databases to keep
+    pub keep: Option<usize>,
+    /// Only print what would be rebuilt
+    pub check: bool,
+    /// Fail when more than this share of the packages can't be parsed, they are left out otherwise
+    pub strict: Option<f64>,
+    /// Which package of colliding attributes is kept, the first read by default
+    pub duplicates: Duplicates,
+    /// Also generate the NixOS options database of each NixOS channel
+    pub options: bool,
+    /// Also generate a nix-darwin options database
+    pub darwin: bool,
+    /// Channel servers to try in order
+    pub channelurls: Vec<String>,
+    /// Proxy for every request, instead of the HTTP_PROXY and HTTPS_PROXY environment variables
+    pub proxy: Option<String>,
+    /// Time before a request to a channel server is given up on
+    pub timeout: Duration,
+    /// Times a failed request to a channel server is retried
+    pub retries: u32,
+    /// Accept downloads whose SHA-256 the release page doesn't list, only warning about them
+    pub noverify: bool,
+    /// Rows inserted per transaction
+    pub batchsize: usize,
+    /// Show progress even when stderr is not a terminal
+    pub progress: bool,
+    /// Listeners to phases, progress and warnings
+    pub events: events::Events,
+}
+
+impl GenerateConfig {
+    /// Builds nothing into `src` until a source is set
+    pub fn new(src: &str) -> Self {
+        GenerateConfig {
+            channels: Vec::new(),
+            combined: false,
+            rev: None,
+            flake: None,
+            nixpkgspath: None,
+            evaluator: eval::Evaluator::NixEnv,
+            source: None,
+            nur: false,
+            cache: None,
+            advisories: false,
+            nvdapikey: None,
+            repology: false,
+            programs: false,
+            aliases: None,
+            mysql: None,
+            formats: vec![Format::Sqlite],
+            exports: Vec::new(),
+            compress: None,
+            changelog: None,
+            addedremoved: false,
+            history: false,
+            feed: false,
+            feedpackages: Vec::new(),
+            publish: Vec::new(),
+            webhooks: Vec::new(),
+            chat: chat::Chat::default(),
+            systems: Vec::new(),
+            filtersystems: false,
+            splitbysystem: false,
+            src: src.to_string(),
+            output: None,
+            dbname: "nixpkgs.db".to_string(),
+            force: false,
+            keep: None,
+            check: false,
+            strict: None,
+            duplicates: Duplicates::FirstWins,
+            options: false,
+            darwin: false,
+            channelurls: vec![channel::CHANNEL_URL.to_string()],
+            proxy: None,
+            timeout: Duration::from_secs(300),
+            retries: 4,
+            noverify: false,
+            batchsize: 10000,
+            progress: false,
+            events: events::Events::default(),
+        }
+    }
+}
+
+/// Outcome of `generate`
+pub struct GenerationReport {
+    /// Whether any package database was rebuilt, or would be in check mode
+    pub rebuilt: bool,
+    /// What happened to each package database, as written to summary.json
+    pub summary: summary::Summary,
+}
+
+/// Builds the package databases of every source in `args`, logging each one that fails
+pub async fn generate(mut args: GenerateConfig) -> Result<GenerationReport> {
+    // The revision would be built instead, like the conflicting flags of the CLI
+    if args.rev.is_some()
+        && (args.flake.is_some() || args.nixpkgspath.is_some() || args.source.is_some())
+    {
+        return Err(anyhow!(
+            "A revision can't be combined with a flake, a nixpkgs path or a package source"
+        ));
+    }
+    // Collected for the summary
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let collected = warnings.clone();
+    args.events.listen(move |event| {
+        if let events::Event::Warning(warning) = event {
+            collected.lock().unwrap().push(warning.clone());
+        }
+    });
+    let src = args.src;
+    let output = args.output.unwrap_or_else(|| src.clone());
+
+    let nur = if args.nur {
+        let (path, rev) = eval::flakesource(eval::NUR_FLAKE).await?;
+        info!("latestnurrev: {}", rev);
+        Some(eval::Nur { path, rev })
+    } else {
+        None
+    };
+
+    let config = BuildConfig {
+        systems: args.systems,
+        filtersystems: args.filtersystems,
+        splitbysystem: args.splitbysystem,
+        nur,
+        cache: args.cache,
+        advisories: args.advisories,
+        nvdapikey: args.nvdapikey,
+        repology: args.repology,
+        proxy: args.proxy,
+        aliases: args.aliases,
+        programs: args.programs,
+        mysql: args.mysql,
+        duckdb: args.formats.contains(&Format::Duckdb),
+        exports: args.exports,
+        compress: args.compress,
+        changelog: args.changelog,
+        addedremoved: args.addedremoved,
+        history: args
+            .history
+            .then(|| format!("{}/{}", output, history::HISTORY_DB)),
+        feed: args.feed.then_some(args.feedpackages),
+        publish: args.publish,
+        webhooks: args.webhooks,
+        chat: args.chat,
+        batchsize: args.batchsize,
+        progress: progress::Progress::detect(args.progress),
+        dbname: args.dbname,
+        force: args.force,
+        check: args.check,
+        strict: args.strict,
+        duplicates: args.duplicates,
+        keep: args.keep,
+        events: args.events,
+    };
+
+    if config.feed.is_some() && config.history.is_none() {
+        return Err(anyhow!(
+            "The feed is written from the history database, enable history as well"
+        ));
+    }
+
+    if config.duckdb && cfg!(not(feature = "duckdb")) {
+        return Err(anyhow!(
+            "Built without DuckDB support, enable the duckdb feature"
+        ));
+    }
+
+    let mut mirrors = channel::Mirrors::connect(
+        args.channelurls,
+        config.proxy.as_deref(),
+        args.timeout,
+        args.retries,
+    )?;
+    mirrors.verify = !args.noverify;
+
+    let mut summary = summary::Summary::new();
+    let mut failed = false;
+    let mut rebuilt = false;
+    let previous = config
+        .chat
+        .previous(&format!("{}/{}", output, config.dbname))
+        .await;
+    let start = Instant::now();
+    let custom: Option<Box<dyn source::PackageSource>> =
+        match (args.flake, args.nixpkgspath, args.source) {
+            (None, None, source) => source,
+            (Some(flake), None, None) => {
+                Some(Box::new(source::Flake::new(&flake, args.evaluator.clone())))
+            }
+            (None, Some(path), None) => Some(Box::new(source::Checkout::new(
+                &path,
+                args.evaluator.clone(),
+            ))),
+            _ => {
+                return Err(anyhow!(
+                    "Only one of a flake, a nixpkgs path or a package source can be built"
+                ))
+            }
+        };
+    let result = if let Some(rev) = &args.rev {
+        if args.channels.len() > 1 {
+            return Err(anyhow!(
+                "A revision can only be combined with a single channel"
+            ));
+        }
+        let channel = args.channels.first().map(|x| x.as_str());
+        let result = revdb(
+            &mirrors,
+            channel,
+            rev,
+            &src,
+            &output,
+            &args.evaluator,
+            &config,
+        )
+        .await;
+        Some((rev.to_string(), result))
+    } else if let Some(mut custom) = custom {
+        let result = sourcedb(custom.as_mut(), &src, &output, &config).await;
+        Some((custom.name().to_string(), result))
+    } else {
+        None
+    };
+    if let Some((source, result)) = result {
+        let dbfile = format!("{}/{}", output, config.dbname);
+        summary.add(&source, &dbfile, start, &result).await;
+        let result = match result {
+            Ok(true) if !config.check => {
+                rebuilt = true;
+                let dirs = (src.as_str(), output.as_str());
+                deliver(&mirrors, &config, &source, dirs, None, &previous)
+                    .await
+                    .map(|()| true)
+            }
+            x => x,
+        };
+        match result {
+            Ok(x) => rebuilt |= x,
+            Err(e) => {
+                error!("{}", e);
+                failed = true;
+                config.chat.failed(&mirrors, &source, &e).await;
+            }
+        }
+    }
+
+    let mut built = Vec::new();
+    for ver in args.channels.iter().filter(|_| args.rev.is_none()) {
+        let sourcedir = channeldir(&src, ver, args.channels.len());
+        let outdir = channeldir(&output, ver, args.channels.len());
+
+        let dbfile = format!("{}/{}", outdir, config.dbname);
+
+        let previous = config.chat.previous(&dbfile).await;
+        let start = Instant::now();
+        let channelconfig = config.forchannel(ver);
+        let mut channel =
+            source::Channel::new(&mirrors, ver, (&sourcedir, &outdir), &channelconfig);
+        let result = sourcedb(&mut channel, &sourcedir, &outdir, &channelconfig).await;
+        summary.add(ver, &dbfile, start, &result).await;
+        let updated = match result {
+            Ok(x) => x,
+            Err(e) => {
+                error!("{}: {}", ver, e);
+                failed = true;
+                config.chat.failed(&mirrors, ver, &e).await;
+                continue;
+            }
+        };
+        rebuilt |= updated;
+        built.push((ver.to_string(), dbfile));
+
+        if updated && !config.check {
+            let subdir = (args.channels.len() > 1).then_some(ver.as_str());
+            let dirs = (sourcedir.as_str(), outdir.as_str());
+            if let Err(e) = deliver(&mirrors, &config, ver, dirs, subdir, &previous).await {
+                error!("{}: {}", ver, e);
+                failed = true;
+                config.chat.failed(&mirrors, ver, &e).await;
+            }
+        }
+
+        if args.options && !config.check {
+            if args.channels.len() > 1 && !ver.starts_with("nixos-") {
+                info!("Skipping options for non-NixOS channel {}", ver);
+                continue;
+            }
+            if let Err(e) = options::downloadoptions(
+                &mirrors,
+                ver,
+                &sourcedir,
+                &outdir,
+                config.batchsize,
+                config.progress,
+                config.force,
+            )
+            .await
+            {
+                error!("{}: {}", ver, e);
+                failed = true;
+            }
+        }
+    }
+
+    if args.combined && !config.check {
+        let dbfile = format!("{}/{}", output, combined::COMBINED_DB);
+        if let Err(e) = combined::combine(&dbfile, &built).await {
+            error!("{}", e);
+            failed = true;
+        }
+    }
+
+    if args.darwin && !config.check {
+        if let Err(e) = options::darwinoptions(&src, &output, config.batchsize, config.force).await
+        {
+            error!("{}", e);
+            failed = true;
+        }
+    }
+
+    // Checking generates nothing, not even a summary
+    let outdir = (!config.check).then_some(output.as_str());
+    let warnings = std::mem::take(&mut *warnings.lock().unwrap());
+    let summary = summary.finish(warnings, outdir)?;
+    config.events.emit(events::Event::Summary(summary.clone()));
+
+    if failed && config.check {
+        return Err(anyhow!("Not every database could be checked"));
+    } else if failed {
+        return Err(anyhow!("Not every database could be generated"));
+    }
+    Ok(GenerationReport { rebuilt, summary })
+}
+
+/// Uploads the databases rebuilt from `source` in the output directory of `dirs` and the version
+/// marker of its source directory, if it has one, to `subdir` of every target to publish to, then
+/// announces them to every webhook and tells the chats, counting the packages not in `previous` as
+/// new. Logs each one that fails.
+async fn deliver(
+    mirrors: &channel::Mirrors,
+    config: &BuildConfig,
+    source: &str,
+    (sourcedir, outdir): (&str, &str),
+    subdir: Option<&str>,
+    previous: &HashSet<String>,
+) -> Result<()> {
+    let compressed = config.compress == Some(Compression::Zstd);
+    let mut files = Vec::new();
+    for db in config.databases() {
+        let names = if compressed {
+            vec![
+                format!("{}.zst", db),
+                format!("{}.zst.sha256", db),
+                format!("{}.sha256", db),
+            ]
+        } else {
+            vec![db]
+        };
+        files.extend(names.into_iter().map(|x| (format!("{}/{}", outdir, x), x)));
+    }
+    // Sources without versions, e.g. local checkouts, write no marker
+    let marker = config.marker("ver");
+    let markerfile = format!("{}/{}", sourcedir, marker);
+    let version = match fs::read_to_string(&markerfile) {
+        Ok(version) => {
+            files.push((markerfile, marker));
+            Some(version.trim().to_string())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", markerfile, e)),
+    };
+
+    // Uploads take as long as the databases are large, so they are not held to --timeout
+    let uploader = channel::clientbuilder(config.proxy.as_deref())?
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()?;
+    let mut failed = false;
+    for target in &config.publish {
+        if let Err(e) = publish::publish(
+            target,
+            &uploader,
+            mirrors.retries,
+            version.as_deref(),
+            subdir,
+            &files,
+        )
+        .await
+        {
+            error!("Failed to publish {} to {}: {}", source, target, e);
+            failed = true;
+        }
+    }
+    // Downstream would fetch what was published before
+    if failed {
+        return Err(anyhow!("Not every target could be published to"));
+    }
+
+    let dbfile = format!("{}/{}", outdir, config.dbname);
+    if !config.webhooks.is_empty() {
+        webhooks(mirrors, config, outdir, &dbfile).await?;
+    }
+    config
+        .chat
+        .updated(mirrors, source, &dbfile, previous)
+        .await
+}
+
+/// Announces the databases rebuilt in `outdir` to every webhook, logging each one that fails
+async fn webhooks(
+    mirrors: &channel::Mirrors,
+    config: &BuildConfig,
+    outdir: &str,
+    dbfile: &str,
+) -> Result<()> {
+    let compressed = config.compress == Some(Compression::Zstd);
+    let mut artifacts = Vec::new();
+    for db in config.databases() {
+        if compressed {
+            let zst = format!("{}.zst", db);
+            artifacts.push((format!("{}/{}", outdir, zst), zst));
+        }
+        artifacts.push((format!("{}/{}", outdir, db), db));
+    }
+    let payload = webhook::Payload::load(dbfile, &artifacts).await?;
+    let mut failed = false;
+    for url in &config.webhooks {
+        if let Err(e) = webhook::notify(&mirrors.client, mirrors.retries, url, &payload).await {
+            error!("Failed to notify {}: {}", url, e);
+            failed = true;
+        }
+    }
+    if failed {
+        return Err(anyhow!("Not every webhook could be notified"));
+    }
+    Ok(())
+}
+
+/// Directory the databases of `channel` are written to, its own one when `channels` are built
+pub fn channeldir(src: &str, channel: &str, channels: usize) -> String {
+    if channels > 1 {
+        format!("{}/{}", src, channel)
+    } else {
+        src.to_string()
+    }
+}
+
+/// Most parameters SQLite binds in one statement
+const SQLITE_MAX_VARIABLES: usize = 32766;
+
+/// Value of a column, bound with its type so NULLs, numbers and flags are stored as such
+enum Field {
+    Null,
+    Text(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl From<String> for Field {
+    fn from(x: String) -> Self {
+        Field::Text(x)
+    }
+}
+
+impl From<&str> for Field {
+    fn from(x: &str) -> Self {
+        Field::Text(x.to_string())
+    }
+}
+
+impl From<&String> for Field {
+    fn from(x: &String) -> Self {
+        Field::Text(x.clone())
+    }
+}
+
+impl From<i64> for Field {
+    fn from(x: i64) -> Self {
+        Field::Integer(x)
+    }
+}
+
+impl From<u32> for Field {
+    fn from(x: u32) -> Self {
+        Field::Integer(x.into())
+    }
+}
+
+impl From<usize> for Field {
+    fn from(x: usize) -> Self {
+        Field::Integer(x as i64)
+    }
+}
+
+impl From<bool> for Field {
+    fn from(x: bool) -> Self {
+        Field::Bool(x)
+    }
+}
+
+impl<T: Into<Field>> From<Option<T>> for Field {
+    fn from(x: Option<T>) -> Self {
+        x.map_or(Field::Null, Into::into)
+    }
+}
+
+/// Fields filling the leading columns of a row
+type Row = Vec<Field>;
+
+/// Names of the columns of `table`
+async fn columns(conn: &mut SqliteConnection, table: &str) -> Result<Vec<String>> {
+    let columns: Vec<(String,)> =
+        sqlx::query_as(r#"SELECT "name" FROM pragma_table_info(?) ORDER BY "cid""#)
+            .bind(table)
+            .fetch_all(conn)
+            .await?;
+    Ok(columns.into_iter().map(|x| x.0).collect())
+}
+
+/// Inserts `rows` into `table`, every `batchsize` of them in a transaction of their own
+async fn importrows(pool: &SqlitePool, table: &str, rows: &[Row], batchsize: usize) -> Result<()> {
+    for batch in rows.chunks(batchsize.max(1)) {
+        let mut tx = pool.begin().await?;
+        insertrows(&mut tx, table, batch).await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+/// Inserts `rows` into `table` by multi-row INSERTs on `conn`, so they are part of the
+/// transaction open there
+async fn insertrows(conn: &mut SqliteConnection, table: &str, rows: &[Row]) -> Result<()> {
+    let Some(width) = rows.first().map(|x| x.len()) else {
+        return Ok(());
+    };
+    let columns = columns(conn, table).await?;
+    for rows in rows.chunks((SQLITE_MAX_VARIABLES / width.max(1)).max(1)) {
+        let mut insert: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            r#"INSERT INTO "{}" ({}) "#,
+            table,
+            columns
+                .iter()
+                .take(width)
+                .map(|x| format!(r#""{}""#, x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        insert.push_values(rows, |mut values, row| {
+            for field in row {
+                match field {
+                    Field::Null => values.push_bind(None::<String>),
+                    Field::Text(x) => values.push_bind(x.as_str()),
+                    Field::Integer(x) => values.push_bind(*x),
+                    Field::Bool(x) => values.push_bind(*x),
+                };
+            }
+        });
+        insert.build().execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Returns whether `dbname` exists in `outdir` and `<name>.ver` in `sourcedir` matches `version`
+fn uptodate(
+    sourcedir: &str,
+    name: &str,
+    outdir: &str,
+    dbname: &str,
+    version: &str,
+) -> Result<bool> {
+    // Check if latest version is already downloaded
+    if let Ok(prevver) = fs::read_to_string(format!("{}/{}.ver", sourcedir, name)) {
+        if prevver == version && Path::new(&format!("{}/{}", outdir, dbname)).exists() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Creates the source and output directories of a database about to be built, a check only reads
+/// them
+fn createdirs(sourcedir: &str, outdir: &str) -> Result<()> {
+    for dir in [sourcedir, outdir] {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// Returns whether the package database is built from nixpkgs `version` and, when indexed, the
+/// current NUR
+fn pkgsuptodate(
+    sourcedir: &str,
+    outdir: &str,
+    version: &str,
+    config: &BuildConfig,
+) -> Result<bool> {
+    Ok(
+        uptodate(sourcedir, config.stem(), outdir, &config.dbname, version)?
+            && outputsuptodate(sourcedir, outdir, config),
+    )
+}
+
+/// Returns whether every output asked for exists and, when indexed, the current NUR is included,
+/// never when forced to rebuild
+fn outputsuptodate(sourcedir: &str, outdir: &str, config: &BuildConfig) -> bool {
+    if config.force {
+        return false;
+    }
+    // An earlier run may not have written every output asked for now
+    if !Path::new(&format!("{}/{}", outdir, config.dbname)).exists()
+        || !config
+            .outputs()
+            .iter()
+            .all(|x| Path::new(&format!("{}/{}", outdir, x)).exists())
+    {
+        return false;
+    }
+    match &config.nur {
+        Some(nur) => fs::read_to_string(format!("{}/{}", sourcedir, config.marker("nur")))
+            .is_ok_and(|x| x == nur.rev),
+        None => true,
+    }
+}
+
+/// Returns whether the package database of `name` needs rebuilding for `version`, `None` for a
+/// tree without one. In check mode what would be done is sent to the listeners, the caller stops
+/// there.
+fn needsrebuild(
+    sourcedir: &str,
+    outdir: &str,
+    name: &str,
+    version: Option<&str>,
+    config: &BuildConfig,
+) -> Result<bool> {
+    let uptodate = match version {
+        Some(version) => pkgsuptodate(sourcedir, outdir, version, config)?,
+        None => false,
+    };
+    if config.check {
+        let built = fs::read_to_string(format!("{}/{}", sourcedir, config.marker("ver")))
+            .ok()
+            .map(|x| x.trim().to_string());
+        config.events.emit(events::Event::Checked(events::Check {
+            source: name.to_string(),
+            version: version.map(|x| x.to_string()),
+            built,
+            uptodate,
+        }));
+    }
+    Ok(!uptodate)
+}
+
+/// Builds the databases for `rev`, from the matching release of `channel` if there is one and
+/// by evaluating nixpkgs from GitHub otherwise, returning whether they were rebuilt
+async fn revdb(
+    mirrors: &channel::Mirrors,
+    channelname: Option<&str>,
+    rev: &str,
+    sourcedir: &str,
+    outdir: &str,
+    evaluator: &eval::Evaluator,
+    config: &BuildConfig,
+) -> Result<bool> {
+    if let Some(channelname) = channelname {
+        debug!("Looking up {} in the releases of {}", rev, channelname);
+        if let Some(release) = mirrors.findrelease(channelname, rev).await? {
+            info!("Found release {} for {}", release.name, rev);
+            let config = config.forchannel(channelname);
+            let mut channel =
+                source::Channel::new(mirrors, channelname, (sourcedir, outdir), &config)
+                    .at(release, rev);
+            return sourcedb(&mut channel, sourcedir, outdir, &config).await;
+        }
+        info!("No release of {} found for {}", channelname, rev);
+    }
+    let flakeref = format!("github:NixOS/nixpkgs/{}", rev);
+    let mut flake = source::Flake::new(&flakeref, evaluator.clone());
+    sourcedb(&mut flake, sourcedir, outdir, config).await
+}
+
+/// Builds the databases from the version of `source` resolved, returning whether they were
+/// rebuilt
+async fn sourcedb(
+    source: &mut dyn source::PackageSource,
+    sourcedir: &str,
+    outdir: &str,
+    config: &BuildConfig,
+) -> Result<bool> {
+    config.events.phase(&format!("Checking {}", source.name()));
+    let version = source.resolve().await?;
+    if !needsrebuild(sourcedir, outdir, source.name(), version.as_deref(), config)? {
+        debug!("No new version of {} found", source.name());
+        return Ok(false);
+    }
+    if config.check {
+        return Ok(true);
+    }
+    createdirs(sourcedir, outdir)?;
+
+    config
+        .events
+        .phase(&format!("Reading packages of {}", source.name()));
+    let verfile = format!("{}/{}", sourcedir, config.marker("ver"));
+    let Some(packages) = source.packages().await? else {
+        // Still built from this version, which is the one compared against next time
+        if let Some(version) = version {
+            File::create(verfile)?.write_all(version.as_bytes())?;
+        }
+        return Ok(false);
+    };
+    let built = Source {
+        name: source.name().to_string(),
+        version: version.clone().unwrap_or_default(),
+        revision: source.revision().map(|x| x.to_string()),
+        path: source.tree().map(|x| {
+            fs::canonicalize(x)
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_else(|_| x.to_string())
+        }),
+    };
+    let result = builddb(
+        sourcedir,
+        outdir,
+        &built,
+        packages.0,
+        config,
+        Some(&*source),
+    )
+    .await;
+    source.finish(result.is_ok())?;
+    result?;
+
+    // Write version built to file, a source without one has nothing to compare against
+    match version {
+        Some(version) => File::create(verfile)?.write_all(version.as_bytes())?,
+        None if Path::new(&verfile).exists() => fs::remove_file(verfile)?,
+        None => (),
+    }
+    Ok(true)
+}
+
+/// Exports the package tables of `dbfile` into `outdir`
+pub async fn exportdb(dbfile: &str, outdir: &str, export: Export) -> Result<()> {
+    if !Path::new(dbfile).exists() {
+        return Err(anyhow!("{} does not exist", dbfile));
+    }
+    match export {
+        Export::Parquet => parquet::export(dbfile, outdir).await,
+        Export::Csv => csvexport::export(dbfile, outdir).await,
+        Export::Msgpack => Err(anyhow!(
+            "msgpack is written from the evaluated packages, pass --export msgpack when generating"
+        )),
+    }
+}
+
+/// Packages converted to rows by one rayon task
+const CHUNK_SIZE: usize = 500;
+
+/// Converted batches waiting for the database writer
+const ROW_BUFFER: usize = 2;
+
+/// Rows of a chunk of packages
+struct PackageRows {
+    pkgs: Vec<Row>,
+    meta: Vec<Row>,
+    pkglicenses: Vec<Row>,
+    pkgteams: Vec<Row>,
+    pkgmaintainers: Vec<Row>,
+    paths: Vec<Row>,
+    /// Rows of `nixpkgs_versions.db`
+    versions: Vec<Row>,
+    /// Licenses and teams are shared between packages, the writer inserts each one once
+    licenses: HashMap<String, License>,
+    teams: HashMap<String, Team>,
+    warnings: warnings::Warnings,
+}
+
+impl PackageRows {
+    /// Converts `packages` of `source` into rows
+    fn convert(
+        packages: &[(String, NixosPkg)],
+        source: &Source,
+        config: &BuildConfig,
+    ) -> Result<Self> {
+        let mut rows = Self {
+            pkgs: Vec::new(),
+            meta: Vec::new(),
+            pkglicenses: Vec::new(),
+            pkgteams: Vec::new(),
+            pkgmaintainers: Vec::new(),
+            paths: Vec::new(),
+            versions: Vec::new(),
+            licenses: HashMap::new(),
+            teams: HashMap::new(),
+            warnings: warnings::Warnings::default(),
+        };
+        for (pkg, data) in packages {
+            rows.add(pkg, data, source, config)?;
+        }
+        Ok(rows)
+    }
+
+    /// Adds the rows of package `pkg` of `source`
+    fn add(
+        &mut self,
+        pkg: &str,
+        data: &NixosPkg,
+        source: &Source,
+        config: &BuildConfig,
+    ) -> Result<()> {
+        self.pkgs.push(vec![
+            pkg.into(),
+            config
+                .systems
+                .iter()
+                .find(|x| data.supports(x))
+                .unwrap_or(&data.system)
+                .into(),
+            (&data.pname).into(),
+            (&data.version).into(),
+            if config.systems.is_empty() {
+                None
+            } else {
+                serde_json::to_string(
+                    &config
+                        .systems
+                        .iter()
+                        .filter(|x| data.supports(x))
+                        .collect::<Vec<_>>(),
+                )
+                .ok()
+            }
+            .into(),
+            if pkg.starts_with("nur.repos.") {
+                "nur"
+            } else {
+                "nixpkgs"
+            }
+            .into(),
+            // Filled in once every package is known, see checkcache
+            Field::Null,
+            data.outputname.as_ref().into(),
+            data.outputs
+                .as_ref()
+                .and_then(|x| {
+                    let mut names = x.keys().collect::<Vec<_>>();
+                    names.sort();
+                    serde_json::to_string(&names).ok()
+                })
+                .into(),
+        ]);
+
+        let position = data
+            .meta
+            .position
+            .as_deref()
+            .map(|x| splitposition(x, source.path.as_deref()));
+        // Flags a package doesn't set and that can't be derived are left NULL, unknown rather than
+        // false
+        self.meta.push(vec![
+            pkg.into(),
+            data.meta.broken.into(),
+            data.meta.insecure().into(),
+            data.meta.unsupported.into(),
+            data.meta.unfree().into(),
+            data.meta.description.as_deref().map(normalize).into(),
+            data.meta.longdescription.as_deref().map(normalize).into(),
+            data.meta.homepage.as_ref().and_then(|x| x.first()).into(),
+            data.meta
+                .maintainers
+                .as_ref()
+                .and_then(|x| serde_json::to_string(x).ok())
+                .into(),
+            data.meta.position.as_ref().map(|x| x.to_string()).into(),
+            data.meta
+                .license
+                .as_ref()
+                .and_then(|x| serde_json::to_string(x).ok())
+                .into(),
+            data.meta.platforms.as_ref().and_then(|x| x.json()).into(),
+            data.meta
+                .knownvulnerabilities
+                .as_ref()
+                .filter(|x| !x.is_empty())
+                .and_then(|x| serde_json::to_string(x).ok())
+                .into(),
+            data.meta.mainprogram.as_ref().map(|x| x.to_string()).into(),
+            data.meta
+                .badplatforms
+                .as_ref()
+                .and_then(|x| x.json())
+                .into(),
+            data.meta
+                .hydraplatforms
+                .as_ref()
+                .and_then(|x| x.json())
+                .into(),
+            data.meta
+                .sourceprovenance
+                .as_ref()
+                .and_then(|x| {
+                    // Keep the short names, e.g. fromSource or binaryNativeCode
+                    let names = match x {
+                        Value::Array(x) => x.iter().collect::<Vec<_>>(),
+                        x => vec![x],
+                    }
+                    .into_iter()
+                    .filter_map(|x| x.get("shortName").or(Some(x)).and_then(|x| x.as_str()))
+                    .collect::<Vec<_>>();
+                    serde_json::to_string(&names).ok()
+                })
+                .into(),
+            data.meta.changelog.as_ref().and_then(|x| x.first()).into(),
+            data.meta
+                .homepage
+                .as_ref()
+                .and_then(|x| serde_json::to_string(&x.all()).ok())
+                .into(),
+            position.map(|x| x.0).into(),
+            position.and_then(|x| x.1).into(),
+            position
+                .and_then(|(file, line)| {
+                    // NUR positions point into NUR, not nixpkgs
+                    if pkg.starts_with("nur.repos.") || file.starts_with('/') {
+                        return None;
+                    }
+                    let revision = source.revision.as_ref()?;
+                    Some(match line {
+                        Some(line) => {
+                            format!("{}/blob/{}/{}#L{}", NIXPKGS_URL, revision, file, line)
+                        }
+                        None => format!("{}/blob/{}/{}", NIXPKGS_URL, revision, file),
+                    })
+                })
+                .into(),
+            data.meta
+                .insecure()
+                .unwrap_or_default()
+                .then(|| data.permittedname())
+                .into(),
+        ]);
+
+        let mut names = HashSet::new();
+        for license in data.meta.license.iter().flat_map(|x| x.licenses()) {
+            let Some(name) = license.name().map(|x| x.to_string()) else {
+                continue;
+            };
+            if names.insert(name.clone()) {
+                self.pkglicenses.push(vec![pkg.into(), (&name).into()]);
+            }
+            self.licenses.entry(name).or_insert(license);
+        }
+
+        let mut names = HashSet::new();
+        for team in data.meta.teams() {
+            if names.insert(team.shortname.clone()) {
+                self.pkgteams
+                    .push(vec![pkg.into(), (&team.shortname).into()]);
+            }
+            self.teams.entry(team.shortname.clone()).or_insert(team);
+        }
+
+        for (maintainer, team) in data.meta.maintainers() {
+            self.pkgmaintainers.push(vec![
+                pkg.into(),
+                maintainer.name.as_ref().into(),
+                maintainer.github.as_ref().into(),
+                maintainer.githubid.into(),
+                maintainer.email.as_ref().into(),
+                maintainer.matrix.as_ref().into(),
+                team.into(),
+            ]);
+        }
+
+        for (output, path) in data.outputs.iter().flatten() {
+            if let Some((path, hash)) = path.as_ref().and_then(|x| Some((x, storehash(x)?))) {
+                self.paths
+                    .push(vec![pkg.into(), output.into(), path.into(), hash.into()]);
+            }
+        }
+
+        self.versions.push(vec![
+            pkg.into(),
+            (&data.pname).into(),
+            (&data.version).into(),
+        ]);
+        self.warnings.check(pkg, data);
+        Ok(())
+    }
+}
+
+/// Inserts converted rows into the databases being built
+struct PackageWriter {
+    pool: SqlitePool,
+    versionspool: SqlitePool,
+    /// Rows per transaction
+    batchsize: usize,
+    /// Licenses and teams already inserted
+    licenses: HashSet<String>,
+    teams: HashSet<String>,
+    /// Warnings of every batch, inserted once all are written
+    warnings: warnings::Warnings,
+}
+
+impl PackageWriter {
+    fn new(pool: SqlitePool, versionspool: SqlitePool, batchsize: usize) -> Self {
+        Self {
+            pool,
+            versionspool,
+            batchsize,
+            licenses: HashSet::new(),
+            teams: HashSet::new(),
+            warnings: warnings::Warnings::default(),
+        }
+    }
+
+    /// Removes the packages of `replaced` written earlier, then inserts the rows of a batch,
+    /// referenced tables first
+    async fn write(&mut self, replaced: Vec<String>, chunks: Vec<PackageRows>) -> Result<()> {
+        if !replaced.is_empty() {
+            self.remove(&replaced).await?;
+        }
+        let mut licenses: Vec<Row> = Vec::new();
+        let mut teams: Vec<Row> = Vec::new();
+        let mut members: Vec<Row> = Vec::new();
+        let mut pkgs = Vec::new();
+        let mut meta = Vec::new();
+        let mut pkglicenses = Vec::new();
+        let mut pkgteams = Vec::new();
+        let mut pkgmaintainers = Vec::new();
+        let mut paths = Vec::new();
+        let mut versions = Vec::new();
+        for chunk in chunks {
+            self.warnings.merge(chunk.warnings);
+            for (name, license) in chunk.licenses {
+                if self.licenses.insert(name.clone()) {
+                    licenses.push(vec![
+                        (&name).into(),
+                        license.spdxid.as_ref().into(),
+                        license.fullname.as_ref().into(),
+                        license.free.into(),
+                        license.url.as_ref().into(),
+                    ]);
+                }
+            }
+            for (name, team) in chunk.teams {
+                if self.teams.insert(name.clone()) {
+                    teams.push(vec![
+                        (&name).into(),
+                        team.scope.as_ref().into(),
+                        serde_json::to_string(&team.githubteams)?.into(),
+                    ]);
+                    for member in &team.members {
+                        members.push(vec![
+                            (&name).into(),
+                            member.github.as_ref().into(),
+                            member.name.as_ref().into(),
+                            member.email.as_ref().into(),
+                            member.matrix.as_ref().into(),
+                            member.githubid.into(),
+                        ]);
+                    }
+                }
+            }
+            for (rows, chunk) in [
+                (&mut pkgs, chunk.pkgs),
+                (&mut meta, chunk.meta),
+                (&mut pkglicenses, chunk.pkglicenses),
+                (&mut pkgteams, chunk.pkgteams),
+                (&mut pkgmaintainers, chunk.pkgmaintainers),
+                (&mut paths, chunk.paths),
+                (&mut versions, chunk.versions),
+            ] {
+                rows.extend(chunk);
+            }
+        }
+
+        // A batch is inserted whole or not at all, so no package is left without its metadata
+        let mut tx = self.pool.begin().await?;
+        for (table, rows) in [
+            ("licenses", licenses),
+            ("teams", teams),
+            ("pkgs", pkgs),
+            ("meta", meta),
+            ("pkglicenses", pkglicenses),
+            ("teammembers", members),
+            ("pkgteams", pkgteams),
+            ("pkgmaintainers", pkgmaintainers),
+            ("paths", paths),
+        ] {
+            insertrows(&mut tx, table, &rows).await?;
+        }
+        tx.commit().await?;
+        importrows(&self.versionspool, "pkgs", &versions, self.batchsize).await?;
+        Ok(())
+    }
+}
+
+impl PackageWriter {
+    /// Removes everything recorded about the packages of `attributes`
+    async fn remove(&self, attributes: &[String]) -> Result<()> {
+        let attributes = serde_json::to_string(attributes)?;
+        for pool in [&self.pool, &self.versionspool] {
+            // pkgs is referenced by the other tables, so it is emptied last
+            let mut tables = schema::tables(pool)
+                .await?
+                .into_iter()
+                .filter(|(table, columns)| {
+                    table != "pkgs" && columns.iter().any(|x| x.name == "attribute")
+                })
+                .map(|x| x.0)
+                .collect::<Vec<_>>();
+            tables.push("pkgs".to_string());
+            let mut tx = pool.begin().await?;
+            for table in tables {
+                sqlx::query(&format!(
+                    r#"DELETE FROM "{}" WHERE "attribute" IN (SELECT "value" FROM json_each(?))"#,
+                    table
+                ))
+                .bind(&attributes)
+                .execute(&mut tx)
+                .await?;
+            }
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies `policy` to package `pkg` whose attribute collides with that of the `previous` one,
+/// returning whether it replaces it
+fn collide(config: &BuildConfig, pkg: &str, previous: &str) -> Result<bool> {
+    match config.duplicates {
+        Duplicates::FirstWins => {
+            config.events.warning(format!(
+                "{} collides with {}, keeping {}",
+                pkg, previous, previous
+            ));
+            Ok(false)
+        }
+        Duplicates::LastWins => {
+            config.events.warning(format!(
+                "{} collides with {}, keeping {}",
+                pkg, previous, pkg
+            ));
+            Ok(true)
+        }
+        Duplicates::Error => Err(anyhow!("{} collides with {}", pkg, previous)),
+    }
+}
+
+/// Records which packages of `outpaths` the binary cache at `url` has
+async fn checkcache(
+    pool: &SqlitePool,
+    url: &str,
+    outpaths: &[(String, String)],
+    proxy: Option<&str>,
+) -> Result<()> {
+    debug!("Checking {} for cached packages", url);
+    let cached = cache::incache(url, outpaths.iter().map(|x| x.1.as_str()), proxy).await?;
+    let mut tx = pool.begin().await?;
+    for (pkg, outpath) in outpaths {
+        if let Some(x) = cached.get(outpath.as_str()) {
+            sqlx::query(r#"UPDATE "pkgs" SET "in_cache" = ? WHERE "attribute" = ?"#)
+                .bind(if *x { 1 } else { 0 })
+                .bind(pkg)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Creates the package and versions databases in `outdir` from `packages` of `source`, inserting
+/// them in batches as they are read, along with the tables of its own `origin` adds. Returns how
+/// long each phase took.
+async fn builddb(
+    sourcedir: &str,
+    outdir: &str,
+    source: &Source,
+    mut packages: stream::PackageStream,
+    config: &BuildConfig,
+    origin: Option<&dyn source::PackageSource>,
+) -> Result<bench::Timings> {
+    config
+        .events
+        .phase(&format!("Building {} from {}", config.dbname, source.name));
+    let mut timings = bench::Timings::default();
+    let mut start = Instant::now();
+    let mut nurpackages = config.nur.as_ref().map(eval::nurpackages);
+
+    // Whatever packages.json the database was built from, it won't be once rebuilt
+    let validatorsfile = format!("{}/{}", sourcedir, config.marker("etag"));
+    if Path::new(&validatorsfile).exists() {
+        fs::remove_file(&validatorsfile)?;
+    }
+
+    let dbfile = format!("{}/{}", outdir, config.dbname);
+    let scratch = schema::scratchfile(&dbfile);
+    let pool = schema::scratchdb(&dbfile, &schema::NIXPKGS).await?;
+    let versionsfile = format!("{}/{}", outdir, config.versionsdb());
+    let versionspool = schema::scratchdb(&versionsfile, &schema::VERSIONS).await?;
+
+    // Only these need every package at once, everything else is inserted as it is read
+    let keep = config.advisories || config.repology || config.exports.contains(&Export::Msgpack);
+    let mut kept: Vec<(String, NixosPkg)> = Vec::new();
+    let mut outpaths: Vec<(String, String)> = Vec::new();
+    let mut count = 0;
+    let mut parsed = 0;
+
+    debug!("Inserting packages into database");
+    // Packages by attribute key, to find those colliding
+    let mut attributes = HashMap::new();
+    // Packages already sent to the writer that a later one replaces
+    let mut replaced = Vec::new();
+    let (rowtx, mut rowrx) = mpsc::channel::<(Vec<String>, Vec<PackageRows>)>(ROW_BUFFER);
+    let mut writer = PackageWriter::new(pool.clone(), versionspool.clone(), config.batchsize);
+    let writer = tokio::spawn(async move {
+        while let Some((replaced, rows)) = rowrx.recv().await {
+            writer.write(replaced, rows).await?;
+        }
+        Ok::<_, anyhow::Error>(writer.warnings)
+    });
+    let mut batch: Vec<(String, NixosPkg)> = Vec::with_capacity(config.batchsize);
+    let bar = config.progress.count("Inserting", "packages");
+    loop {
+        let package = match packages.next().await {
+            Some(x) => Some(x),
+            None => match &mut nurpackages {
+                Some(nur) => nur
+                    .next()
+                    .await
+                    .map(|(attr, data)| (format!("nur.repos.{}", attr), data)),
+                None => None,
+            },
+        };
+        let done = package.is_none();
+        if let Some((pkg, data)) = package {
+            parsed += 1;
+            if config.filtersystems && !config.systems.iter().any(|x| data.supports(x)) {
+                continue;
+            }
+            let key = config.attributekey(&pkg);
+            if let Some(previous) = attributes.insert(key.clone(), pkg.clone()) {
+                if !collide(config, &pkg, &previous)? {
+                    attributes.insert(key, previous);
+                    continue;
+                }
+                match batch.iter().position(|x| x.0 == previous) {
+                    Some(i) => {
+                        batch.remove(i);
+                    }
+                    None => {
+                        count -= 1;
+                        kept.retain(|x| x.0 != previous);
+                        outpaths.retain(|x| x.0 != previous);
+                        replaced.push(previous);
+                    }
+                }
+            }
+            batch.push((pkg, data));
+        }
+        if batch.len() < config.batchsize && !done {
+            continue;
+        }
+
+        // Converted on every core while the writer inserts the previous batch
+        let rows = tokio::task::block_in_place(|| {
+            batch
+                .par_chunks(CHUNK_SIZE)
+                .map(|x| PackageRows::convert(x, source, config))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        if rowtx
+            .send((std::mem::take(&mut replaced), rows))
+            .await
+            .is_err()
+        {
+            // The writer failed, its error is returned below
+            break;
+        }
+        count += batch.len();
+        bar.inc(batch.len() as u64);
+        if !batch.is_empty() {
+            config.events.emit(events::Event::Packages(count as u64));
+        }
+        daemon::alive();
+        for (pkg, data) in batch.drain(..) {
+            if config.cache.is_some() {
+                if let Some(outpath) = data.outpath() {
+                    outpaths.push((pkg.clone(), outpath.to_string()));
+                }
+            }
+            if keep {
+                kept.push((pkg, data));
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    drop(rowtx);
+    let warnings = writer.await??;
+    bar.finish();
+    if warnings.count() > 0 {
+        config.events.warning(format!(
+            "{} package fields could not be fully stored, see the warnings table",
+            warnings.count()
+        ));
+        importrows(&pool, "warnings", &warnings.rows()?, config.batchsize).await?;
+    }
+    let mut errors = packages.finish().await?;
+    if let Some(nur) = nurpackages {
+        errors.extend(nur.finish().await?.into_iter().map(|x| stream::ParseError {
+            attribute: format!("nur.repos.{}", x.attribute),
+            error: x.error,
+        }));
+    }
+    if !errors.is_empty() {
+        let total = parsed + errors.len();
+        config.events.warning(format!(
+            "{} of {} packages could not be parsed and were left out, see the errors table",
+            errors.len(),
+            total
+        ));
+        let mut rows: Vec<Row> = Vec::new();
+        for error in &errors {
+            debug!("Failed to parse {}: {}", error.attribute, error.error);
+            rows.push(vec![(&error.attribute).into(), (&error.error).into()]);
+        }
+        importrows(&pool, "errors", &rows, config.batchsize).await?;
+        let rate = errors.len() as f64 / total as f64;
+        if config.strict.is_some_and(|x| rate > x) {
+            return Err(anyhow!(
+                "{:.2}% of packages could not be parsed, more than strict mode allows",
+                rate * 100.0
+            ));
+        }
+    }
+    start = timings.record("insert", start);
+    if config.filtersystems {
+        info!("{} packages available on {:?}", count, config.systems);
+    }
+    let packages = kept.iter().map(|(x, y)| (x, y)).collect::<Vec<_>>();
+
+    if let Some(url) = &config.cache {
+        checkcache(&pool, url, &outpaths, config.proxy.as_deref()).await?;
+        start = timings.record("cache", start);
+    }
+
+    debug!("Indexing packages for full-text search");
+    sqlx::query(
+        r#"
+        INSERT INTO "pkgs_fts" ("attribute", "pname", "description", "longdescription")
+        SELECT "pkgs"."attribute", "pkgs"."pname", "meta"."description", "meta"."longdescription"
+        FROM "pkgs" LEFT JOIN "meta" ON "pkgs"."attribute" = "meta"."attribute"
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    start = timings.record("index", start);
+
+    if config.advisories {
+        let mut rows: Vec<Row> = Vec::new();
+        for vuln in advisories::vulnerabilities(
+            sourcedir,
+            config.nvdapikey.as_deref(),
+            &packages,
+            config.proxy.as_deref(),
+        )
+        .await?
+        {
+            rows.push(vec![
+                vuln.attribute.into(),
+                (&vuln.cve).into(),
+                vuln.severity.as_ref().into(),
+                vuln.url().into(),
+            ]);
+        }
+        debug!("Inserting vulnerabilities into database");
+        importrows(&pool, "vulnerabilities", &rows, config.batchsize).await?;
+        start = timings.record("advisories", start);
+    }
+
+    if config.repology {
+        let mut rows: Vec<Row> = Vec::new();
+        for (pname, upstream) in
+            repology::upstream(sourcedir, &packages, config.proxy.as_deref()).await?
+        {
+            rows.push(vec![
+                pname.into(),
+                (&upstream.project).into(),
+                (&upstream.version).into(),
+            ]);
+        }
+        debug!("Inserting upstream versions into database");
+        importrows(&pool, "upstream", &rows, config.batchsize).await?;
+        start = timings.record("repology", start);
+    }
+
+    let aliases: HashMap<String, String> = match (&config.aliases, &source.path) {
+        (Some(file), _) => serde_json::from_reader(BufReader::new(File::open(file)?))?,
+        (None, Some(path)) => eval::aliases(path).await.unwrap_or_else(|e| {
+            config
+                .events
+                .warning(format!("Failed to evaluate aliases: {}", e));
+            HashMap::new()
+        }),
+        (None, None) => HashMap::new(),
+    };
+    let rows = aliases
+        .iter()
+        .map(|(alias, attribute)| vec![alias.into(), attribute.into()])
+        .collect::<Vec<Row>>();
+    debug!("Inserting {} aliases into database", aliases.len());
+    importrows(&pool, "aliases", &rows, config.batchsize).await?;
+    start = timings.record("aliases", start);
+
+    sqlx::query(
+        r#"
+        INSERT INTO "generation_info" (
+            "channel", "version", "variant", "revision",
+            "generated_at", "generator_version", "package_count"
+        ) VALUES (?, ?, ?, ?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), ?, ?)
+        "#,
+    )
+    .bind(&source.name)
+    .bind(&source.version)
+    .bind(channel::variant(&source.name))
+    .bind(&source.revision)
+    .bind(env!("CARGO_PKG_VERSION"))
+    .bind(count as i64)
+    .execute(&pool)
+    .await?;
+
+    if let Some(origin) = origin {
+        origin.extend(&scratch).await?;
+        start = timings.record("extend", start);
+    }
+
+    if let Some(keep) = config.keep {
+        let verfile = format!("{}/{}", sourcedir, config.marker("ver"));
+        match fs::read_to_string(&verfile) {
+            Ok(version) if Path::new(&dbfile).exists() => {
+                archive::archive(&dbfile, config.stem(), version.trim(), keep)?
+            }
+            Ok(_) => (),
+            Err(_) if Path::new(&dbfile).exists() => config
+                .events
+                .warning(format!("{} has no {}, not archiving it", dbfile, verfile)),
+            Err(_) => (),
+        }
+    }
+    // Compared before the previous database is replaced
+    if config.changelog.is_some() || config.addedremoved {
+        let diff = match Path::new(&dbfile).exists() {
+            true => Some(diff::diff(&dbfile, &scratch).await?),
+            false => None,
+        };
+        if let Some(format) = config.changelog {
+            let file = format!("{}/{}", outdir, config.changelogfile(format));
+            match &diff {
+                Some(diff) => {
+                    let from =
+                        fs::read_to_string(format!("{}/{}", sourcedir, config.marker("ver"))).ok();
+                    let versions = (from.as_deref().map(|x| x.trim()), source.version.as_str());
+                    changelog::write(&file, format, &source.name, versions, diff)?;
+                }
+                None => changelog::remove(&file)?,
+            }
+        }
+        if config.addedremoved {
+            changelog::addedremoved(outdir, diff.as_ref())?;
+        }
+        start = timings.record("changelog", start);
+    }
+    schema::persist(pool, &dbfile).await?;
+    debug!("Finished creating nixpkgs database");
+    schema::persist(versionspool, &versionsfile).await?;
+    start = timings.record("persist", start);
+
+    if let Some(history) = &config.history {
+        history::record(history, &source.name, &dbfile).await?;
+        if let Some(watchlist) = &config.feed {
+            let file = format!("{}/{}", outdir, feed::FEED_FILE);
+            feed::write(history, &source.name, watchlist, &file).await?;
+        }
+        start = timings.record("history", start);
+    }
+
+    if config.splitbysystem {
+        split::splitbysystem(&dbfile, outdir, config.stem(), &config.systems).await?;
+        start = timings.record("split", start);
+    }
+
+    #[cfg(feature = "duckdb")]
+    if config.duckdb {
+        duckdb::export(&dbfile, &format!("{}/{}.duckdb", outdir, config.stem())).await?;
+        start = timings.record("duckdb", start);
+    }
+
+    for export in &config.exports {
+        match export {
+            Export::Msgpack => msgpack::export(
+                &format!("{}/{}.msgpack", outdir, config.stem()),
+                source,
+                &packages,
+            )?,
+            x => exportdb(&dbfile, outdir, *x).await?,
+        }
+    }
+    if !config.exports.is_empty() {
+        start = timings.record("export", start);
+    }
+
+    if let Some(url) = &config.mysql {
+        mysql::export(&dbfile, url).await?;
+        start = timings.record("mysql", start);
+    }
+
+    if let Some(Compression::Zstd) = config.compress {
+        for db in config.databases() {
+            compress::zstd(&format!("{}/{}", outdir, db))?;
+        }
+        timings.record("compress", start);
+    }
+
+    // Write NUR revision indexed to file
+    if let Some(nur) = &config.nur {
+        File::create(format!("{}/{}", sourcedir, config.marker("nur")))?
+            .write_all(nur.rev.as_bytes())?;
+    }
+    Ok(timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use anyhow::Result;
+    use sqlx::SqlitePool;
+
+    use crate::{source::JsonFile, GeneratorBuilder};
+
+    const PACKAGES: &str = r#"{"version": 2, "packages": {
+        "Foo": {"name": "Foo-1.0", "pname": "Foo", "version": "1.0", "system": "x86_64-linux", "meta": {}},
+        "foo": {"name": "foo-2.0", "pname": "foo", "version": "2.0", "system": "x86_64-linux", "meta": {}}
+    }}"#;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn attributes_differing_in_case_kept_in_sqlite() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().to_string_lossy().to_string();
+        let file = format!("{}/packages.json", src);
+        fs::write(&file, PACKAGES)?;
+        GeneratorBuilder::new(&src)
+            .source(JsonFile::new(&file))
+            .generate()
+            .await?;
+
+        let pool = SqlitePool::connect(&format!("sqlite://{}/nixpkgs.db", src)).await?;
+        let attributes: Vec<(String,)> =
+            sqlx::query_as(r#"SELECT "attribute" FROM "pkgs" ORDER BY "attribute""#)
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(attributes, vec![("Foo".to_string(),), ("foo".to_string(),)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rev_conflicts_with_source() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().to_string_lossy().to_string();
+        let file = format!("{}/packages.json", src);
+        fs::write(&file, PACKAGES)?;
+        let result = GeneratorBuilder::new(&src)
+            .rev("abcdef1234567890abcdef1234567890abcdef12")
+            .source(JsonFile::new(&file))
+            .generate()
+            .await;
+        assert!(result.is_err());
+        assert!(!Path::new(&format!("{}/nixpkgs.db", src)).exists());
+        Ok(())
+    }
+}
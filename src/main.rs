@@ -1,399 +1,729 @@
-use std::{
-    collections::HashMap,
-    error,
-    fs::{self, File},
-    io::{BufReader, Write},
-    path::Path,
-    process::{Command, Stdio},
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use log::error;
+use nix_data_generator::{
+    bench, cache, channel, channeldir, chat, config, daemon, diff, eval, events::Event, exportdb,
+    options, progress, publish, registry, serve, verify, ChangelogFormat, Compression, Duplicates,
+    Export, Format, GenerateConfig,
 };
 
-use anyhow::{anyhow, Context, Result};
-use clap::{arg, Parser};
-use log::{debug, error, info};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+/// Exit code when a package database was rebuilt, or would be with --check. Runs where every
+/// database is up to date exit with 0 and failed ones with 1.
+const UPDATED: i32 = 10;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Channel version to build
-    #[arg(short, long)]
-    ver: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-    /// Source directory
-    #[arg(short, long)]
-    src: String,
+    /// Without a subcommand the arguments of generate are taken
+    #[command(flatten)]
+    generate: GenerateArgs,
+
+    #[command(flatten)]
+    global: GlobalArgs,
 }
 
-#[derive(Debug, Deserialize)]
-struct NixosPkgList {
-    packages: HashMap<String, NixosPkg>,
+// Flags every subcommand takes after its name, a doc comment would replace the about of Args
+#[derive(clap::Args, Clone)]
+struct GlobalArgs {
+    /// Channel server to resolve and download channels from
+    #[arg(long, global = true, default_value = channel::CHANNEL_URL)]
+    channel_url: String,
+
+    /// Comma separated fallback channel mirrors, tried in order after --channel-url
+    #[arg(long, global = true, value_delimiter = ',')]
+    mirror: Vec<String>,
+
+    /// Show progress even when stderr is not a terminal, as plain lines
+    #[arg(long, global = true)]
+    progress: bool,
+
+    /// Proxy for every request, instead of the HTTP_PROXY and HTTPS_PROXY environment variables
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+
+    /// Seconds before a request to a channel server is given up on, downloads resume afterwards
+    #[arg(long, global = true, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
+    /// Times a failed request to a channel server is retried, waiting longer each time
+    #[arg(long, global = true, default_value_t = 4)]
+    retries: u32,
+
+    /// Accept channel downloads whose SHA-256 the release page doesn't list, only warning
+    #[arg(long, global = true)]
+    no_verify: bool,
+
+    /// Rows inserted per transaction, lower it on slow disks or network filesystems
+    #[arg(long, global = true, default_value_t = 10000, value_parser = clap::value_parser!(u64).range(1..))]
+    batch_size: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct NixosPkg {
-    pname: String,
-    version: String,
-    system: String,
-    meta: Meta,
+impl GlobalArgs {
+    /// Channel servers to try in order
+    fn channelurls(&self) -> Vec<String> {
+        std::iter::once(self.channel_url.clone())
+            .chain(self.mirror.iter().cloned())
+            .collect()
+    }
+
+    fn mirrors(&self) -> Result<channel::Mirrors> {
+        let mut mirrors = channel::Mirrors::connect(
+            self.channelurls(),
+            self.proxy.as_deref(),
+            Duration::from_secs(self.timeout),
+            self.retries,
+        )?;
+        mirrors.verify = !self.no_verify;
+        Ok(mirrors)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Meta {
-    pub broken: Option<bool>,
-    pub insecure: Option<bool>,
-    pub unsupported: Option<bool>,
-    pub unfree: Option<bool>,
-    pub description: Option<String>,
-    #[serde(rename = "longDescription")]
-    pub longdescription: Option<String>,
-    pub homepage: Option<StrOrVec>,
-    pub maintainers: Option<Value>,
-    pub position: Option<String>,
-    pub license: Option<LicenseEnum>,
-    pub platforms: Option<Platform>,
+#[derive(clap::Args, Clone)]
+struct GenerateArgs {
+    /// Generate every channel of a TOML configuration file instead, nix-data-generator.toml
+    /// without a path
+    #[arg(long, exclusive = true, num_args = 0..=1, default_missing_value = config::CONFIG_FILE)]
+    config: Option<String>,
+
+    /// Channel version to build, may be repeated to build each channel into its own subdirectory
+    #[arg(short, long, required_unless_present_any = ["flake", "nixpkgs_path", "rev", "config"])]
+    ver: Vec<String>,
+
+    /// Also merge every --ver into one nixpkgs_combined.db with a channel column
+    #[arg(long, requires = "ver", conflicts_with = "rev")]
+    combined: bool,
+
+    /// Exact nixpkgs git revision to build, looked up in the releases of --ver or evaluated from GitHub
+    #[arg(short, long, conflicts_with_all = ["flake", "nixpkgs_path"])]
+    rev: Option<String>,
+
+    /// Nixpkgs flake reference to evaluate instead of a channel
+    #[arg(short, long, conflicts_with = "ver")]
+    flake: Option<String>,
+
+    /// Local nixpkgs checkout to evaluate instead of a channel
+    #[arg(short = 'p', long, conflicts_with_all = ["ver", "flake"])]
+    nixpkgs_path: Option<String>,
+
+    /// Evaluate --flake, --nixpkgs-path or --rev with nix-eval-jobs, recursing into all package sets
+    #[arg(short, long, conflicts_with = "ver")]
+    eval_jobs: bool,
+
+    /// Number of nix-eval-jobs workers
+    #[arg(long, default_value_t = 1, requires = "eval_jobs")]
+    workers: usize,
+
+    /// Memory limit per nix-eval-jobs worker in MiB
+    #[arg(long, default_value_t = 4096, requires = "eval_jobs")]
+    max_memory_size: usize,
+
+    /// Also index the Nix User Repository, evaluated against <nixpkgs>
+    #[arg(long)]
+    nur: bool,
+
+    /// Check which packages are in the binary cache, needs output paths from an evaluating source
+    #[arg(long)]
+    check_cache: bool,
+
+    /// Binary cache to check packages against
+    #[arg(long, default_value = cache::CACHE_URL, requires = "check_cache")]
+    cache_url: String,
+
+    /// Match packages against NVD CVEs into a vulnerabilities table, cached for a day
+    #[arg(long)]
+    advisories: bool,
+
+    /// NVD API key, speeds up downloading the CVEs considerably
+    #[arg(long, env = "NVD_API_KEY", requires = "advisories")]
+    nvd_api_key: Option<String>,
+
+    /// Record the newest upstream version of each pname from Repology, cached for a day
+    #[arg(long)]
+    repology: bool,
+
+    /// Add the command-not-found programs of nixos-* channels as a programs table
+    #[arg(long, conflicts_with_all = ["flake", "nixpkgs_path"])]
+    programs: bool,
+
+    /// JSON file mapping alias attribute names to their targets, evaluated from aliases.nix for
+    /// flakes and local checkouts when not given
+    #[arg(long)]
+    aliases: Option<String>,
+
+    /// Also mirror the generated package database into the MySQL database at this URL
+    #[arg(long)]
+    mysql_url: Option<String>,
+
+    /// Comma separated formats to write the package database in, nixpkgs.db is always kept
+    #[arg(long, value_delimiter = ',', default_value = "sqlite")]
+    format: Vec<Format>,
+
+    /// Also write a compressed copy and checksums of each generated database
+    #[arg(long)]
+    compress: Option<Compression>,
+
+    /// Write the packages upgraded, downgraded, added and removed since the previous package
+    /// database next to it whenever it is rebuilt
+    #[arg(long)]
+    changelog: Option<ChangelogFormat>,
+
+    /// Write the packages added and removed since the previous package database to added.json
+    /// and removed.json next to it whenever it is rebuilt
+    #[arg(long)]
+    added_removed: bool,
+
+    /// Record when each package version was first and last built in history.db, which unlike
+    /// the other databases keeps its rows across runs
+    #[arg(long)]
+    history: bool,
+
+    /// Write updates.atom, a feed of the version bumps recorded in history.db, next to each
+    /// rebuilt package database
+    #[arg(long, requires = "history")]
+    feed: bool,
+
+    /// Comma separated packages to only list the updates of in the feed
+    #[arg(long, value_delimiter = ',', requires = "feed")]
+    feed_packages: Vec<String>,
+
+    /// Upload the rebuilt databases, their compressed copies and checksums with --compress, and
+    /// the version marker to a target such as s3://bucket/prefix, github:owner/repo or
+    /// sftp://user@host/path, may be repeated
+    #[arg(long, value_parser = publish::target)]
+    publish: Vec<publish::Target>,
+
+    /// POST the channel, version, revision and SHA-256 of each file as JSON to this URL whenever
+    /// the databases are rebuilt, after publishing them, may be repeated
+    #[arg(long)]
+    webhook: Vec<reqwest::Url>,
+
+    /// Discord webhook to post updated and failed databases to
+    #[arg(long)]
+    discord_webhook: Option<reqwest::Url>,
+
+    /// Matrix room ID to post updated and failed databases to, through --matrix-homeserver
+    #[arg(long, requires = "matrix_homeserver")]
+    matrix_room: Option<String>,
+
+    /// Homeserver of the account posting to --matrix-room
+    #[arg(long, requires = "matrix_room")]
+    matrix_homeserver: Option<reqwest::Url>,
+
+    /// Access token of the account posting to --matrix-room
+    #[arg(long, env = "MATRIX_ACCESS_TOKEN", hide_env_values = true)]
+    matrix_access_token: Option<String>,
+
+    /// Comma separated additional files to export the package tables to, next to the databases
+    #[arg(long, value_delimiter = ',')]
+    export: Vec<Export>,
+
+    /// Comma separated systems to record package availability for, darwin channels default to darwin systems
+    #[arg(long, value_delimiter = ',')]
+    system: Vec<String>,
+
+    /// Only keep packages available on at least one of the given systems
+    #[arg(long, requires = "system")]
+    filter_system: bool,
+
+    /// Also write nixpkgs-<system>.db for each of the given systems with only its packages
+    #[arg(long, requires = "system")]
+    split_by_system: bool,
+
+    /// Source directory, holding the version markers and downloads
+    #[arg(short, long, required_unless_present = "config")]
+    src: Option<String>,
+
+    /// Directory to write the databases to, defaults to --src
+    #[arg(long)]
+    output: Option<String>,
+
+    /// File name of the package database, the versions and per-system databases and the version
+    /// markers in --src are named after it
+    #[arg(long, default_value = "nixpkgs.db", value_parser = dbname)]
+    db_name: String,
+
+    /// Rebuild the databases even when they are built from the latest version already
+    #[arg(long)]
+    force: bool,
+
+    /// Archive the package database being replaced as archive/<stem>-<version>.db, keeping the
+    /// newest N archives
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    keep: Option<u64>,
+
+    /// Only print which package databases would be rebuilt without building anything
+    #[arg(long)]
+    check: bool,
+
+    /// Fail when more than this share of the packages, 0 by default, can't be parsed instead of
+    /// leaving them out and listing them in the errors table
+    #[arg(long, value_name = "RATE", num_args = 0..=1, default_missing_value = "0", value_parser = rate)]
+    strict: Option<f64>,
+
+    /// Which package to keep of those whose attributes collide, exactly or, with --mysql-url, only
+    /// by case
+    #[arg(long, default_value = "first-wins")]
+    duplicates: Duplicates,
+
+    /// Also print the summary written to summary.json on stdout
+    #[arg(long)]
+    json: bool,
+
+    /// Keep running, checking for new releases every --interval or on --schedule and
+    /// regenerating what advanced
+    #[arg(long, conflicts_with = "check")]
+    watch: bool,
+
+    /// Time between the checks of --watch, e.g. 30m, 6h or 1d
+    #[arg(long, default_value = "6h", value_parser = daemon::interval, requires = "watch")]
+    interval: Duration,
+
+    /// Cron expression in local time to check on with --watch instead, e.g. "0 3 * * *"
+    #[arg(long, value_parser = daemon::schedule, requires = "watch", conflicts_with = "interval")]
+    schedule: Option<croner::Cron>,
+
+    /// Address to serve Prometheus metrics of the generations of --watch on at /metrics
+    #[arg(long, requires = "watch")]
+    metrics_listen: Option<std::net::SocketAddr>,
+
+    /// Also generate a NixOS options database
+    #[arg(short, long, conflicts_with_all = ["flake", "nixpkgs_path", "rev"])]
+    options: bool,
+
+    /// Also generate a nix-darwin options database
+    #[arg(short, long)]
+    darwin: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(untagged)]
-enum StrOrVec {
-    Single(String),
-    List(Vec<String>),
+impl GenerateArgs {
+    /// When --watch runs again
+    fn rerun(&self) -> daemon::Rerun {
+        match &self.schedule {
+            Some(cron) => daemon::Rerun::Schedule(Box::new(cron.clone())),
+            None => daemon::Rerun::After(self.interval),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(untagged)]
-pub enum Platform {
-    Single(String),
-    List(Vec<String>),
-    ListList(Vec<Vec<String>>),
-    Unknown(Value),
+#[derive(clap::Args)]
+struct OptionsArgs {
+    /// NixOS channel version to generate options for, may be repeated to generate each channel
+    /// into its own subdirectory
+    #[arg(short, long, required_unless_present = "darwin")]
+    ver: Vec<String>,
+
+    /// Also generate a nix-darwin options database
+    #[arg(short, long)]
+    darwin: bool,
+
+    /// Source directory, holding the version markers and downloads
+    #[arg(short, long)]
+    src: String,
+
+    /// Directory to write the databases to, defaults to --src
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Regenerate the databases even when they are generated from the latest version already
+    #[arg(long)]
+    force: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(untagged)]
-enum LicenseEnum {
-    Single(License),
-    List(Vec<License>),
-    SingleStr(String),
-    VecStr(Vec<String>),
-    Mixed(Vec<LicenseEnum>),
+#[derive(Subcommand)]
+enum Commands {
+    /// Build the package databases, the default without a subcommand
+    Generate(Box<GenerateArgs>),
+    /// Generate the NixOS or nix-darwin options databases
+    Options(OptionsArgs),
+    /// Index the packages of every flake in the flake registry into flakes.db
+    Registry {
+        /// Source directory
+        #[arg(short, long)]
+        src: String,
+
+        /// System to index packages for, defaults to the current system
+        #[arg(long)]
+        system: Option<String>,
+    },
+    /// Export the package tables of an already generated nixpkgs.db
+    Export {
+        /// Format to export to
+        #[arg(value_enum)]
+        format: Export,
+
+        /// Source directory holding nixpkgs.db, the export is written next to it
+        #[arg(short, long)]
+        src: String,
+
+        /// File name of the package database to export
+        #[arg(long, default_value = "nixpkgs.db", value_parser = dbname)]
+        db_name: String,
+    },
+    /// Build nixpkgs.db from a cached packages.json and report how long each phase took
+    Bench {
+        /// Channel whose latest packages.json.br is downloaded once and kept in --src
+        #[arg(short, long, required_unless_present = "packages")]
+        ver: Option<String>,
+
+        /// packages.json or packages.json.br to build from instead of a channel
+        #[arg(long, conflicts_with = "ver")]
+        packages: Option<String>,
+
+        /// Directory the packages are cached in, the database is built in its bench subdirectory
+        #[arg(short, long)]
+        src: String,
+    },
+    /// Compare the packages of two package databases
+    Diff {
+        /// Package database to compare against
+        old: String,
+
+        /// Package database to compare
+        new: String,
+
+        /// Print the differences as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check the databases generated into a directory for corruption and truncation
+    Verify {
+        /// Directory holding the databases
+        dir: String,
+
+        /// File name of the package database to check
+        #[arg(long, default_value = "nixpkgs.db", value_parser = dbname)]
+        db_name: String,
+    },
+    /// Serve a JSON API to search the generated packages and options
+    Serve {
+        /// Source directory holding nixpkgs.db and nixosoptions.db
+        #[arg(short, long)]
+        src: String,
+
+        /// File name of the package database to serve
+        #[arg(long, default_value = "nixpkgs.db", value_parser = dbname)]
+        db_name: String,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+
+        /// Address to serve the gRPC service on as well
+        #[arg(long)]
+        grpc_listen: Option<std::net::SocketAddr>,
+
+        /// Oldest the package database may be for /readyz to succeed, e.g. 2d
+        #[arg(long, value_parser = daemon::interval)]
+        max_age: Option<Duration>,
+    },
+    /// Print shell completions
+    Completions {
+        /// Shell to complete in
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct License {
-    pub free: Option<bool>,
-    #[serde(rename = "fullName")]
-    pub fullname: Option<String>,
-    #[serde(rename = "spdxId")]
-    pub spdxid: Option<String>,
-    pub url: Option<String>,
+/// Checks that a --db-name is a plain file name
+fn dbname(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.contains('/') {
+        return Err("must be a file name without a directory".to_string());
+    }
+    Ok(name.to_string())
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct PkgMaintainer {
-    pub email: Option<String>,
-    pub github: Option<String>,
-    pub matrix: Option<String>,
-    pub name: Option<String>,
+/// Checks that a --strict rate is a share between 0 and 1
+fn rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+        _ => Err("must be a number between 0 and 1".to_string()),
+    }
 }
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
+    daemon::watchdog();
     let args = Args::parse();
+    let global = args.global;
 
-    match downloaddb(&args.ver, &args.src).await {
-        Ok(_) => (),
-        Err(e) => {
-            error!("{}", e);
-            std::process::exit(1);
+    let result = match args
+        .command
+        .unwrap_or(Commands::Generate(Box::new(args.generate)))
+    {
+        Commands::Generate(generate) if generate.config.is_some() => {
+            let file = generate.config.unwrap_or_default();
+            match loadconfig(&file) {
+                Ok(runs) => match runs.first().filter(|x| x.0.watch) {
+                    Some(first) => {
+                        let (rerun, metrics) = (first.0.rerun(), first.0.metrics_listen);
+                        daemon::watch(rerun, metrics, || configdbs(&file, &runs)).await
+                    }
+                    None => match configdbs(&file, &runs).await {
+                        Ok(true) => std::process::exit(UPDATED),
+                        x => x.map(|_| ()),
+                    },
+                },
+                Err(e) => Err(e),
+            }
+        }
+        Commands::Generate(generate) if generate.watch => {
+            daemon::watch(generate.rerun(), generate.metrics_listen, || {
+                generatedbs(generate.as_ref().clone(), &global)
+            })
+            .await
+        }
+        Commands::Generate(generate) => match generatedbs(*generate, &global).await {
+            Ok(true) => std::process::exit(UPDATED),
+            x => x.map(|_| ()),
+        },
+        Commands::Options(options) => optionsdbs(options, &global).await,
+        Commands::Registry { src, system } => {
+            registry::registrydb(&src, system.as_deref(), global.batch_size as usize).await
         }
+        Commands::Export {
+            format,
+            src,
+            db_name,
+        } => exportdb(&format!("{}/{}", src, db_name), &src, format).await,
+        Commands::Bench { ver, packages, src } => match global.mirrors() {
+            Ok(mirrors) => {
+                bench::bench(
+                    &mirrors,
+                    ver.as_deref(),
+                    packages.as_deref(),
+                    &src,
+                    global.batch_size as usize,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        },
+        Commands::Diff { old, new, json } => diff::diff(&old, &new).await.and_then(|diff| {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                diff.print();
+            }
+            Ok(())
+        }),
+        Commands::Verify { dir, db_name } => {
+            verify::verify(&dir, &db_name).await.and_then(|checks| {
+                let failed = checks.iter().filter(|x| x.problem.is_some()).count();
+                for check in checks {
+                    match check.problem {
+                        Some(problem) => {
+                            println!("FAIL {} {}: {}", check.database, check.check, problem)
+                        }
+                        None => println!("ok   {} {}", check.database, check.check),
+                    }
+                }
+                match failed {
+                    0 => Ok(()),
+                    n => Err(anyhow!("{} checks failed", n)),
+                }
+            })
+        }
+        Commands::Serve {
+            src,
+            db_name,
+            listen,
+            grpc_listen,
+            max_age,
+        } => {
+            serve::serve(
+                &format!("{}/{}", src, db_name),
+                listen,
+                grpc_listen,
+                max_age,
+            )
+            .await
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                env!("CARGO_PKG_NAME"),
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(1);
     }
 }
 
-async fn downloaddb(mut version: &str, sourcedir: &str) -> Result<()> {
-    let verurl = format!("https://channels.nixos.org/{}", version);
-    debug!("Checking nixpkgs version");
-    let resp = reqwest::blocking::get(&verurl)?;
-    let latestnixpkgsver = if resp.status().is_success() {
-        resp.url()
-            .path_segments()
-            .context("No path segments found")?
-            .last()
-            .context("Last element not found")?
-            .to_string()
-    } else {
-        let resp = reqwest::blocking::get("https://channels.nixos.org/nixos-unstable")?;
-        if resp.status().is_success() {
-            version = "unstable";
-            resp.url()
-                .path_segments()
-                .context("No path segments found")?
-                .last()
-                .context("Last element not found")?
-                .to_string()
-        } else {
-            return Err(anyhow!("Could not find latest nixpkgs version"));
-        }
+/// Builds the package databases of every source in `args`, logging each one that fails, and
+/// returns whether any was rebuilt
+async fn generatedbs(args: GenerateArgs, global: &GlobalArgs) -> Result<bool> {
+    let matrix = match (args.matrix_room, args.matrix_homeserver) {
+        (Some(room), Some(homeserver)) => Some(chat::Matrix {
+            homeserver,
+            room,
+            token: args
+                .matrix_access_token
+                .ok_or_else(|| anyhow!("--matrix-room needs --matrix-access-token"))?,
+        }),
+        _ => None,
     };
-    debug!("Latest nixpkgs version: {}", latestnixpkgsver);
-
-    let latestpkgsver = latestnixpkgsver
-        .strip_prefix("nixos-")
-        .unwrap_or(&latestnixpkgsver);
-    let latestpkgsver = latestpkgsver
-        .strip_prefix("nixpkgs-")
-        .unwrap_or(&latestpkgsver);
-    info!("latestnixpkgsver: {}", latestpkgsver);
-
-    // Check if source directory exists
-    let srcdir = Path::new(sourcedir);
-    if !srcdir.exists() {
-        // create source directory
-        fs::create_dir_all(srcdir)?;
+
+    // Fields are set one by one as settings may be added to GenerateConfig
+    let mut config = GenerateConfig::new(
+        &args
+            .src
+            .expect("clap requires --src for generate without --config"),
+    );
+    config.channels = args.ver;
+    config.combined = args.combined;
+    config.rev = args.rev;
+    config.flake = args.flake;
+    config.nixpkgspath = args.nixpkgs_path;
+    if args.eval_jobs {
+        config.evaluator = eval::Evaluator::EvalJobs {
+            workers: args.workers,
+            maxmemory: args.max_memory_size,
+        };
     }
+    config.nur = args.nur;
+    config.cache = args.check_cache.then_some(args.cache_url);
+    config.advisories = args.advisories;
+    config.nvdapikey = args.nvd_api_key;
+    config.repology = args.repology;
+    config.programs = args.programs;
+    config.aliases = args.aliases;
+    config.mysql = args.mysql_url;
+    config.formats = args.format;
+    config.exports = args.export;
+    config.compress = args.compress;
+    config.changelog = args.changelog;
+    config.addedremoved = args.added_removed;
+    config.history = args.history;
+    config.feed = args.feed;
+    config.feedpackages = args.feed_packages;
+    config.publish = args.publish;
+    config.webhooks = args.webhook;
+    config.chat = chat::Chat {
+        discord: args.discord_webhook,
+        matrix,
+    };
+    config.systems = args.system;
+    config.filtersystems = args.filter_system;
+    config.splitbysystem = args.split_by_system;
+    config.output = args.output;
+    config.dbname = args.db_name;
+    config.force = args.force;
+    config.keep = args.keep.map(|x| x as usize);
+    config.check = args.check;
+    config.strict = args.strict;
+    config.duplicates = args.duplicates;
+    config.options = args.options;
+    config.darwin = args.darwin;
+    config.channelurls = global.channelurls();
+    config.proxy = global.proxy.clone();
+    config.timeout = Duration::from_secs(global.timeout);
+    config.retries = global.retries;
+    config.noverify = global.no_verify;
+    config.batchsize = global.batch_size as usize;
+    config.progress = global.progress;
+    let json = args.json;
+    config.events.listen(move |event| match event {
+        Event::Checked(check) => println!("{}", check),
+        Event::Summary(summary) if json => match serde_json::to_string_pretty(summary) {
+            Ok(summary) => println!("{}", summary),
+            Err(e) => error!("Failed to print the summary: {}", e),
+        },
+        _ => (),
+    });
+    Ok(nix_data_generator::generate(config).await?.rebuilt)
+}
 
-    // Check if latest version is already downloaded
-    if let Ok(prevver) = fs::read_to_string(&format!("{}/nixpkgs.ver", sourcedir)) {
-        if prevver == latestpkgsver && Path::new(&format!("{}/nixpkgs.db", sourcedir)).exists() {
-            debug!("No new version of nixpkgs found");
-            return Ok(());
-        }
+/// Parses the configuration `file` into the arguments of each channel, checking every channel
+/// before any is generated
+fn loadconfig(file: &str) -> Result<Vec<(GenerateArgs, GlobalArgs)>> {
+    let runs = config::load(file)?
+        .iter()
+        .map(|x| {
+            // Only the message, the usage it comes with is that of the command line
+            let args = Args::try_parse_from(x).map_err(|e| {
+                let e = e.to_string();
+                anyhow!("{}: {}", file, e.lines().next().unwrap_or_default())
+            })?;
+            let Some(Commands::Generate(generate)) = args.command else {
+                unreachable!("configurations are loaded as generate arguments");
+            };
+            Ok((*generate, args.global))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Every channel is generated in each round, so they are watched together
+    if runs.windows(2).any(|x| {
+        let watch = |x: &GenerateArgs| (x.watch, x.rerun(), x.metrics_listen);
+        watch(&x[0].0) != watch(&x[1].0)
+    }) {
+        return Err(anyhow!(
+            "{}: watch, interval, schedule and metrics-listen must be the same for every channel",
+            file
+        ));
     }
+    Ok(runs)
+}
 
-    let url = format!("https://channels.nixos.org/{}/packages.json.br", version);
+/// Runs generate for every channel of the configuration `file`, logging each one that fails, and
+/// returns whether any was rebuilt
+async fn configdbs(file: &str, runs: &[(GenerateArgs, GlobalArgs)]) -> Result<bool> {
+    let mut failed = false;
+    let mut rebuilt = false;
+    for (generate, global) in runs {
+        match generatedbs(generate.clone(), global).await {
+            Ok(x) => rebuilt |= x,
+            Err(e) => {
+                error!("{}", e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return Err(anyhow!("Not every channel of {} could be generated", file));
+    }
+    Ok(rebuilt)
+}
 
-    // Download file with reqwest blocking
-    debug!("Downloading packages.json.br");
-    let client = reqwest::blocking::Client::builder().brotli(true).build()?;
-    let resp = client.get(url).send()?;
-    if resp.status().is_success() {
-        // resp is pkgsjson
-        debug!("Successfully downloaded packages.json.br");
-        let db = format!("sqlite://{}/nixpkgs.db", sourcedir);
+/// Generates the options databases of every channel in `args`, logging each one that fails
+async fn optionsdbs(args: OptionsArgs, global: &GlobalArgs) -> Result<()> {
+    let mirrors = global.mirrors()?;
+    let batchsize = global.batch_size as usize;
+    let progress = progress::Progress::detect(global.progress);
+    let output = args.output.as_deref().unwrap_or(&args.src);
 
-        if Path::new(&format!("{}/nixpkgs.db", sourcedir)).exists() {
-            fs::remove_file(&format!("{}/nixpkgs.db", sourcedir))?;
-        }
-        debug!("Creating SQLite database");
-        Sqlite::create_database(&db).await?;
-        let pool = SqlitePool::connect(&db).await?;
-        sqlx::query(
-            r#"
-                CREATE TABLE "pkgs" (
-                    "attribute"	TEXT NOT NULL UNIQUE,
-                    "system"	TEXT,
-                    "pname"	TEXT,
-                    "version"	TEXT,
-                    PRIMARY KEY("attribute")
-                )
-                "#,
-        )
-        .execute(&pool)
-        .await?;
-        sqlx::query(
-            r#"
-            CREATE TABLE "meta" (
-                "attribute"	TEXT NOT NULL UNIQUE,
-                "broken"	INTEGER,
-                "insecure"	INTEGER,
-                "unsupported"	INTEGER,
-                "unfree"	INTEGER,
-                "description"	TEXT,
-                "longdescription"	TEXT,
-                "homepage"	TEXT,
-                "maintainers"	JSON,
-                "position"	TEXT,
-                "license"	JSON,
-                "platforms"	JSON,
-                FOREIGN KEY("attribute") REFERENCES "pkgs"("attribute"),
-                PRIMARY KEY("attribute")
-            )
-                "#,
+    let mut failed = false;
+    for ver in &args.ver {
+        let sourcedir = channeldir(&args.src, ver, args.ver.len());
+        let outdir = channeldir(output, ver, args.ver.len());
+        if let Err(e) = options::downloadoptions(
+            &mirrors, ver, &sourcedir, &outdir, batchsize, progress, args.force,
         )
-        .execute(&pool)
-        .await?;
-        sqlx::query(
-            r#"
-            CREATE UNIQUE INDEX "attributes" ON "pkgs" ("attribute")
-            "#,
-        )
-        .execute(&pool)
-        .await?;
-        sqlx::query(
-            r#"
-            CREATE UNIQUE INDEX "metaattributes" ON "meta" ("attribute")
-            "#,
-        )
-        .execute(&pool)
-        .await?;
-        sqlx::query(
-            r#"
-            CREATE INDEX "pnames" ON "pkgs" ("pname")
-            "#,
-        )
-        .execute(&pool)
-        .await?;
-
-        debug!("Reading packages.json.br");
-        let pkgjson: NixosPkgList =
-            serde_json::from_reader(BufReader::new(resp)).expect("Failed to parse packages.json");
-
-        debug!("Creating csv data");
-        let mut wtr = csv::Writer::from_writer(vec![]);
-        for (pkg, data) in &pkgjson.packages {
-            wtr.serialize((
-                pkg,
-                data.system.to_string(),
-                data.pname.to_string(),
-                data.version.to_string(),
-            ))?;
-        }
-        let data = String::from_utf8(wtr.into_inner()?)?;
-        debug!("Inserting data into database");
-        let mut cmd = Command::new("sqlite3")
-            .arg("-csv")
-            .arg(&format!("{}/nixpkgs.db", sourcedir))
-            .arg(".import '|cat -' pkgs")
-            .stdin(Stdio::piped())
-            .spawn()?;
-        let cmd_stdin = cmd.stdin.as_mut().unwrap();
-        cmd_stdin.write_all(data.as_bytes())?;
-        let _status = cmd.wait()?;
-        let mut metawtr = csv::Writer::from_writer(vec![]);
-        for (pkg, data) in &pkgjson.packages {
-            metawtr.serialize((
-                pkg,
-                if let Some(x) = data.meta.broken {
-                    if x {
-                        1
-                    } else {
-                        0
-                    }
-                } else {
-                    0
-                },
-                if let Some(x) = data.meta.insecure {
-                    if x {
-                        1
-                    } else {
-                        0
-                    }
-                } else {
-                    0
-                },
-                if let Some(x) = data.meta.unsupported {
-                    if x {
-                        1
-                    } else {
-                        0
-                    }
-                } else {
-                    0
-                },
-                if let Some(x) = data.meta.unfree {
-                    if x {
-                        1
-                    } else {
-                        0
-                    }
-                } else {
-                    0
-                },
-                data.meta.description.as_ref().map(|x| x.to_string()),
-                data.meta.longdescription.as_ref().map(|x| x.to_string()),
-                data.meta.homepage.as_ref().and_then(|x| match x {
-                    StrOrVec::List(x) => x.first().map(|x| x.to_string()),
-                    StrOrVec::Single(x) => Some(x.to_string()),
-                }),
-                data.meta
-                    .maintainers
-                    .as_ref()
-                    .and_then(|x| match serde_json::to_string(x) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    }),
-                data.meta.position.as_ref().map(|x| x.to_string()),
-                data.meta
-                    .license
-                    .as_ref()
-                    .and_then(|x| match serde_json::to_string(x) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    }),
-                data.meta.platforms.as_ref().and_then(|x| match x {
-                    Platform::Unknown(_) => None,
-                    _ => match serde_json::to_string(x) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    },
-                }),
-            ))?;
+        .await
+        {
+            error!("{}: {}", ver, e);
+            failed = true;
         }
-        let metadata = String::from_utf8(metawtr.into_inner()?)?;
-        debug!("Inserting metadata into database");
-        let mut metacmd = Command::new("sqlite3")
-            .arg("-csv")
-            .arg(&format!("{}/nixpkgs.db", sourcedir))
-            .arg(".import '|cat -' meta")
-            .stdin(Stdio::piped())
-            .spawn()?;
-        let metacmd_stdin = metacmd.stdin.as_mut().unwrap();
-        metacmd_stdin.write_all(metadata.as_bytes())?;
-        let _status = metacmd.wait()?;
-        debug!("Finished creating nixpkgs database");
-
-        // Create version database
-        let db = format!("sqlite://{}/nixpkgs_versions.db", sourcedir);
-        Sqlite::create_database(&db).await?;
-        let pool = SqlitePool::connect(&db).await?;
-        sqlx::query(
-            r#"
-                CREATE TABLE "pkgs" (
-                    "attribute"	TEXT NOT NULL UNIQUE,
-                    "pname"	TEXT,
-                    "version"	TEXT,
-                    PRIMARY KEY("attribute")
-                )
-                "#,
-        )
-        .execute(&pool)
-        .await?;
-        sqlx::query(
-            r#"
-            CREATE UNIQUE INDEX "attributes" ON "pkgs" ("attribute")
-            "#,
-        )
-        .execute(&pool)
-        .await?;
-        sqlx::query(
-            r#"
-            CREATE INDEX "pnames" ON "pkgs" ("attribute")
-            "#,
-        )
-        .execute(&pool)
-        .await?;
+    }
 
-        let mut wtr = csv::Writer::from_writer(vec![]);
-        for (pkg, data) in &pkgjson.packages {
-            wtr.serialize((pkg, data.pname.to_string(), data.version.to_string()))?;
+    if args.darwin {
+        if let Err(e) = options::darwinoptions(&args.src, output, batchsize, args.force).await {
+            error!("{}", e);
+            failed = true;
         }
-        let data = String::from_utf8(wtr.into_inner()?)?;
-        let mut cmd = Command::new("sqlite3")
-            .arg("-csv")
-            .arg(&format!("{}/nixpkgs_versions.db", sourcedir))
-            .arg(".import '|cat -' pkgs")
-            .stdin(Stdio::piped())
-            .spawn()?;
-        let cmd_stdin = cmd.stdin.as_mut().unwrap();
-        cmd_stdin.write_all(data.as_bytes())?;
-        let _status = cmd.wait()?;
-
-        // Write version downloaded to file
-        File::create(format!("{}/nixpkgs.ver", sourcedir))?.write_all(latestpkgsver.as_bytes())?;
-    } else {
-        return Err(anyhow!("Failed to download latest packages.json"));
+    }
+
+    if failed {
+        return Err(anyhow!("Not every options database could be generated"));
     }
     Ok(())
 }
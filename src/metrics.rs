@@ -0,0 +1,183 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use log::{error, info};
+
+/// Metrics of the generations run by this process
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics::new());
+
+/// A metric of each source, its name, type, help and value
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&Source) -> Option<f64>,
+);
+
+const FAMILIES: [Family; 5] = [
+    (
+        "nixdata_last_success_timestamp_seconds",
+        "gauge",
+        "When a generation last finished without failing",
+        |x| x.lastsuccess,
+    ),
+    (
+        "nixdata_last_update_timestamp_seconds",
+        "gauge",
+        "When the databases were last rebuilt",
+        |x| x.lastupdate,
+    ),
+    (
+        "nixdata_generation_duration_seconds",
+        "gauge",
+        "Seconds the last generation took",
+        |x| x.duration,
+    ),
+    (
+        "nixdata_packages",
+        "gauge",
+        "Packages in the package database",
+        |x| x.packages.map(|x| x as f64),
+    ),
+    (
+        "nixdata_generation_failures_total",
+        "counter",
+        "Generations that failed",
+        |x| x.failures.map(|x| x as f64),
+    ),
+];
+
+/// Counters and gauges exposed to Prometheus
+pub struct Metrics {
+    sources: BTreeMap<String, Source>,
+    /// Bytes downloaded from the channel servers
+    downloadbytes: Option<u64>,
+}
+
+/// Metrics of the databases of one channel, flake reference or nixpkgs path
+#[derive(Default)]
+struct Source {
+    /// When a generation last finished without failing, seconds since the epoch
+    lastsuccess: Option<f64>,
+    /// When the databases were last rebuilt, seconds since the epoch
+    lastupdate: Option<f64>,
+    /// Seconds the last generation took
+    duration: Option<f64>,
+    packages: Option<i64>,
+    failures: Option<u64>,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Metrics {
+            sources: BTreeMap::new(),
+            downloadbytes: None,
+        }
+    }
+
+    /// Records that the package database of `source`, last rebuilt at `generated` seconds since
+    /// the epoch, holds `packages` packages
+    pub fn generated(&mut self, source: &str, generated: f64, packages: i64) {
+        let source = self.sources.entry(source.to_string()).or_default();
+        source.lastupdate = Some(generated);
+        source.packages = Some(packages);
+    }
+
+    /// The metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help, value) in FAMILIES {
+            let values = self
+                .sources
+                .iter()
+                .filter_map(|(source, x)| Some((source, value(x)?)))
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for (source, value) in values {
+                let _ = writeln!(out, "{}{{source=\"{}\"}} {}", name, label(source), value);
+            }
+        }
+        if let Some(bytes) = self.downloadbytes {
+            let _ = writeln!(
+                out,
+                "# HELP nixdata_download_bytes_total Bytes downloaded from the channel servers\n\
+                # TYPE nixdata_download_bytes_total counter\n\
+                nixdata_download_bytes_total {}",
+                bytes
+            );
+        }
+        out
+    }
+}
+
+/// Escapes `value` for a label
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Records a generation of `source` that took `duration`, `Some` whether it rebuilt the databases
+/// with `packages` packages or `None` when it failed
+pub fn generation(source: &str, duration: Duration, rebuilt: Option<bool>, packages: Option<i64>) {
+    let mut metrics = METRICS.lock().unwrap();
+    let source = metrics.sources.entry(source.to_string()).or_default();
+    source.duration = Some(duration.as_secs_f64());
+    source.failures = Some(source.failures.unwrap_or(0) + rebuilt.is_none() as u64);
+    if let Some(rebuilt) = rebuilt {
+        let now = now();
+        source.lastsuccess = Some(now);
+        if rebuilt {
+            source.lastupdate = Some(now);
+        }
+        source.packages = packages.or(source.packages);
+    }
+}
+
+/// Counts `bytes` downloaded from the channel servers
+pub fn downloaded(bytes: u64) {
+    *METRICS.lock().unwrap().downloadbytes.get_or_insert(0) += bytes;
+}
+
+/// Serves the metrics of this process on `listen` at `/metrics` in the background
+pub fn spawn(listen: SocketAddr) -> Result<()> {
+    let server = axum::Server::try_bind(&listen)?;
+    info!("Serving metrics on http://{}/metrics", listen);
+    tokio::spawn(async move {
+        let app = Router::new().route("/metrics", get(metrics));
+        if let Err(e) = server.serve(app.into_make_service()).await {
+            error!("Failed to serve metrics: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Answers with `metrics` in the Prometheus text format
+pub fn respond(metrics: &Metrics) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+async fn metrics() -> impl IntoResponse {
+    respond(&METRICS.lock().unwrap())
+}
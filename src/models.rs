@@ -0,0 +1,260 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A package of `packages.json`, or as evaluated from a nixpkgs tree
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NixosPkg {
+    /// Derivation name, usually `<pname>-<version>`
+    pub name: Option<String>,
+    pub pname: String,
+    pub version: String,
+    pub system: String,
+    pub meta: Meta,
+    /// Store paths by output name, channel `packages.json` leaves them `null`
+    pub outputs: Option<HashMap<String, Option<String>>>,
+    /// Output installed by default
+    #[serde(rename = "outputName")]
+    pub outputname: Option<String>,
+}
+
+impl NixosPkg {
+    /// Store path of the `out` output, or of the first output if there is none
+    pub(crate) fn outpath(&self) -> Option<&str> {
+        let outputs = self.outputs.as_ref()?;
+        outputs
+            .get("out")
+            .or_else(|| outputs.values().next())?
+            .as_deref()
+    }
+
+    /// Name permittedInsecurePackages has to list for the package to be built when insecure
+    pub(crate) fn permittedname(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}", self.pname, self.version))
+    }
+
+    /// Whether the package can be built for `system`, packages without platforms support everything
+    pub(crate) fn supports(&self, system: &str) -> bool {
+        self.meta
+            .platforms
+            .as_ref()
+            .is_none_or(|x| x.supports(system))
+            && !self.meta.badplatforms.as_ref().is_some_and(|x| match x {
+                Platform::Unknown(_) => false,
+                x => x.supports(system),
+            })
+    }
+}
+
+/// The `meta` attribute of a package
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Meta {
+    pub broken: Option<bool>,
+    pub insecure: Option<bool>,
+    pub unsupported: Option<bool>,
+    pub unfree: Option<bool>,
+    pub description: Option<String>,
+    #[serde(rename = "longDescription")]
+    pub longdescription: Option<String>,
+    pub homepage: Option<StrOrVec>,
+    pub maintainers: Option<Value>,
+    pub teams: Option<Value>,
+    pub position: Option<String>,
+    pub license: Option<LicenseEnum>,
+    pub platforms: Option<Platform>,
+    #[serde(rename = "knownVulnerabilities")]
+    pub knownvulnerabilities: Option<Vec<String>>,
+    #[serde(rename = "mainProgram")]
+    pub mainprogram: Option<String>,
+    #[serde(rename = "badPlatforms")]
+    pub badplatforms: Option<Platform>,
+    #[serde(rename = "hydraPlatforms")]
+    pub hydraplatforms: Option<Platform>,
+    #[serde(rename = "sourceProvenance")]
+    pub sourceprovenance: Option<Value>,
+    pub changelog: Option<StrOrVec>,
+}
+
+/// A string or a list of them, such as `meta.homepage`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum StrOrVec {
+    Single(String),
+    List(Vec<String>),
+}
+
+impl StrOrVec {
+    pub(crate) fn first(&self) -> Option<String> {
+        match self {
+            StrOrVec::List(x) => x.first().map(|x| x.to_string()),
+            StrOrVec::Single(x) => Some(x.to_string()),
+        }
+    }
+
+    pub(crate) fn all(&self) -> Vec<&str> {
+        match self {
+            StrOrVec::List(x) => x.iter().map(|x| x.as_str()).collect(),
+            StrOrVec::Single(x) => vec![x],
+        }
+    }
+}
+
+/// `meta.platforms` and the like, a system, a list of them or a pattern
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Platform {
+    Single(String),
+    List(Vec<String>),
+    ListList(Vec<Vec<String>>),
+    Unknown(Value),
+}
+
+impl Platform {
+    /// Whether `system` is listed, platform patterns can't be matched so they are assumed supported
+    pub(crate) fn supports(&self, system: &str) -> bool {
+        match self {
+            Platform::Single(x) => x == system,
+            Platform::List(x) => x.iter().any(|x| x == system),
+            Platform::ListList(x) => x.iter().flatten().any(|x| x == system),
+            Platform::Unknown(_) => true,
+        }
+    }
+
+    /// Platform list as JSON, `None` for patterns that can't be represented
+    pub(crate) fn json(&self) -> Option<String> {
+        match self {
+            Platform::Unknown(_) => None,
+            x => serde_json::to_string(x).ok(),
+        }
+    }
+}
+
+/// `meta.license`, license attribute sets, bare names or lists mixing them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum LicenseEnum {
+    Single(License),
+    List(Vec<License>),
+    SingleStr(String),
+    VecStr(Vec<String>),
+    Mixed(Vec<LicenseEnum>),
+}
+
+impl LicenseEnum {
+    /// Every license listed, bare strings become licenses with only a short name
+    pub(crate) fn licenses(&self) -> Vec<License> {
+        let named = |x: &String| License {
+            free: None,
+            fullname: None,
+            shortname: Some(x.to_string()),
+            spdxid: None,
+            url: None,
+        };
+        match self {
+            LicenseEnum::Single(x) => vec![x.clone()],
+            LicenseEnum::List(x) => x.clone(),
+            LicenseEnum::SingleStr(x) => vec![named(x)],
+            LicenseEnum::VecStr(x) => x.iter().map(named).collect(),
+            LicenseEnum::Mixed(x) => x.iter().flat_map(|x| x.licenses()).collect(),
+        }
+    }
+}
+
+/// A license from `lib.licenses`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct License {
+    pub free: Option<bool>,
+    #[serde(rename = "fullName")]
+    pub fullname: Option<String>,
+    #[serde(rename = "shortName")]
+    pub shortname: Option<String>,
+    #[serde(rename = "spdxId")]
+    pub spdxid: Option<String>,
+    pub url: Option<String>,
+}
+
+impl License {
+    /// Name identifying the license, as not every license has an SPDX id
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.shortname
+            .as_deref()
+            .or(self.spdxid.as_deref())
+            .or(self.fullname.as_deref())
+    }
+}
+
+/// A maintainer from `lib.maintainers`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PkgMaintainer {
+    pub email: Option<String>,
+    pub github: Option<String>,
+    #[serde(rename = "githubId")]
+    pub githubid: Option<i64>,
+    pub matrix: Option<String>,
+    pub name: Option<String>,
+}
+
+/// A nixpkgs team from `lib.teams`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Team {
+    #[serde(rename = "shortName")]
+    pub shortname: String,
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub members: Vec<PkgMaintainer>,
+    #[serde(rename = "githubTeams", default)]
+    pub githubteams: Vec<String>,
+}
+
+impl Meta {
+    /// `meta.unfree`, or as nixpkgs decides it when unset: whether any license isn't free, where
+    /// licenses that don't say are free
+    pub(crate) fn unfree(&self) -> Option<bool> {
+        self.unfree.or_else(|| {
+            let licenses = self.license.as_ref()?.licenses();
+            Some(licenses.iter().any(|x| x.free == Some(false)))
+        })
+    }
+
+    /// `meta.insecure`, or as nixpkgs decides it when unset: whether it has known vulnerabilities
+    pub(crate) fn insecure(&self) -> Option<bool> {
+        self.insecure
+            .or_else(|| Some(!self.knownvulnerabilities.as_ref()?.is_empty()))
+    }
+
+    /// Teams in `meta.teams`, and teams listed as maintainers
+    pub(crate) fn teams(&self) -> Vec<Team> {
+        [&self.teams, &self.maintainers]
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x.as_array())
+            .flatten()
+            .filter(|x| x.get("shortName").is_some() && x.get("members").is_some())
+            .filter_map(|x| serde_json::from_value(x.clone()).ok())
+            .collect()
+    }
+
+    /// Maintainers listed one by one, then the members of each team along with its name
+    pub(crate) fn maintainers(&self) -> Vec<(PkgMaintainer, Option<String>)> {
+        let mut maintainers = self
+            .maintainers
+            .iter()
+            .filter_map(|x| x.as_array())
+            .flatten()
+            .filter(|x| x.get("members").is_none())
+            .filter_map(|x| serde_json::from_value(x.clone()).ok())
+            .map(|x| (x, None))
+            .collect::<Vec<_>>();
+        let mut teams = HashSet::new();
+        for team in self.teams() {
+            if teams.insert(team.shortname.clone()) {
+                let name = team.shortname;
+                maintainers.extend(team.members.into_iter().map(|x| (x, Some(name.clone()))));
+            }
+        }
+        maintainers
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use anyhow::Result;
+use log::debug;
+use rmp_serde::Serializer;
+use serde::{Serialize, Serializer as _};
+
+use crate::{models::NixosPkg, Source};
+
+/// Layout version of `nixpkgs.msgpack`, bumped whenever readers of older files would misread it
+pub const FORMAT_VERSION: u32 = 1;
+
+/// First value of `nixpkgs.msgpack`, readers check `format` before reading on
+#[derive(Serialize)]
+struct Header<'a> {
+    format: u32,
+    generator: &'a str,
+    channel: &'a str,
+    version: &'a str,
+    revision: Option<&'a str>,
+}
+
+/// Writes `packages` to `file` as two MessagePack values: a [`Header`], then a map of attribute
+/// names to packages laid out like `packages.json`
+pub fn export(file: &str, source: &Source, packages: &[(&String, &NixosPkg)]) -> Result<()> {
+    debug!("Exporting {} packages to {}", packages.len(), file);
+    let mut writer = BufWriter::new(File::create(file)?);
+    // Structs as maps keep files readable after fields are added
+    let mut ser = Serializer::new(&mut writer).with_struct_map();
+    Header {
+        format: FORMAT_VERSION,
+        generator: env!("CARGO_PKG_VERSION"),
+        channel: &source.name,
+        version: &source.version,
+        revision: source.revision.as_deref(),
+    }
+    .serialize(&mut ser)?;
+    ser.collect_map(packages.iter().copied())?;
+    writer.flush()?;
+    Ok(())
+}
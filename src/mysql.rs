@@ -0,0 +1,114 @@
+use anyhow::Result;
+use log::debug;
+use sqlx::{migrate::Migrator, MySql, MySqlPool, QueryBuilder, Row, SqlitePool};
+
+use crate::schema;
+
+/// Schema of the MySQL mirror of `nixpkgs.db`
+static MIGRATOR: Migrator = sqlx::migrate!("migrations/mysql");
+
+/// Rows per INSERT, well below the 65535 placeholders MySQL allows in one statement
+const BATCH_SIZE: usize = 1000;
+
+/// Mirrors the tables of the generated SQLite database `dbfile` into the MySQL database at `url`,
+/// replacing whatever they held before
+pub async fn export(dbfile: &str, url: &str) -> Result<()> {
+    debug!("Exporting {} to MySQL", dbfile);
+    let sqlite = SqlitePool::connect(&format!("sqlite://{}", dbfile)).await?;
+    let mysql = MySqlPool::connect(url).await?;
+    MIGRATOR.run(&mysql).await?;
+
+    let tables: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT `table_name` FROM `information_schema`.`tables`
+        WHERE `table_schema` = DATABASE()
+        AND `table_name` NOT IN ('_sqlx_migrations', 'schema_version')
+        "#,
+    )
+    .fetch_all(&mysql)
+    .await?;
+
+    let sqlitetables = schema::tables(&sqlite).await?;
+
+    // Readers see either the previous or the new data, never a mix
+    let mut tx = mysql.begin().await?;
+    for (table,) in tables {
+        sqlx::query(&format!("DELETE FROM `{}`", table))
+            .execute(&mut tx)
+            .await?;
+
+        // Only copy the columns both schemas know about, either may be newer than the other
+        let sqlitecolumns = sqlitetables
+            .iter()
+            .find(|x| x.0 == table)
+            .map(|x| x.1.as_slice())
+            .unwrap_or_default();
+        let columns: Vec<(String,)> = sqlx::query_as(
+            r#"
+            SELECT `column_name` FROM `information_schema`.`columns`
+            WHERE `table_schema` = DATABASE() AND `table_name` = ?
+            ORDER BY `ordinal_position`
+            "#,
+        )
+        .bind(&table)
+        .fetch_all(&mut tx)
+        .await?;
+        let columns = columns
+            .into_iter()
+            .map(|x| x.0)
+            .filter(|x| sqlitecolumns.iter().any(|y| &y.name == x))
+            .collect::<Vec<_>>();
+        if columns.is_empty() {
+            continue;
+        }
+
+        // Everything is read as text, MySQL converts it back to the column types
+        let select = format!(
+            r#"SELECT {} FROM "{}" ORDER BY rowid LIMIT ? OFFSET ?"#,
+            columns
+                .iter()
+                .map(|x| format!(r#"CAST("{}" AS TEXT)"#, x))
+                .collect::<Vec<_>>()
+                .join(", "),
+            table
+        );
+        let mut offset = 0;
+        loop {
+            let rows = sqlx::query(&select)
+                .bind(BATCH_SIZE as i64)
+                .bind(offset as i64)
+                .fetch_all(&sqlite)
+                .await?;
+            if rows.is_empty() {
+                break;
+            }
+            offset += rows.len();
+
+            let mut insert: QueryBuilder<MySql> = QueryBuilder::new(format!(
+                "INSERT INTO `{}` ({}) ",
+                table,
+                columns
+                    .iter()
+                    .map(|x| format!("`{}`", x))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            insert.push_values(&rows, |mut values, row| {
+                for i in 0..columns.len() {
+                    values.push_bind(row.get::<Option<String>, _>(i));
+                }
+            });
+            insert.build().execute(&mut tx).await?;
+        }
+        debug!("Exported {} rows of {}", offset, table);
+    }
+    sqlx::query("DELETE FROM `schema_version`")
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("INSERT INTO `schema_version` (`version`) VALUES (?)")
+        .bind(schema::version(&MIGRATOR))
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
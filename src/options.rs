@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::process::Command;
+
+use crate::{
+    channel::{self, releaseversion, Mirrors},
+    createdirs, importrows,
+    progress::Progress,
+    schema, uptodate, Row,
+};
+
+const DARWIN_FLAKE: &str = "github:LnL7/nix-darwin";
+
+#[derive(Debug, Deserialize)]
+struct NixosOption {
+    pub declarations: Option<Value>,
+    pub default: Option<Value>,
+    pub description: Option<Value>,
+    pub example: Option<Value>,
+    #[serde(rename = "readOnly")]
+    pub readonly: Option<bool>,
+    #[serde(rename = "type")]
+    pub opttype: Option<String>,
+}
+
+/// Renders an option value, unwrapping `literalExpression`/`literalMD`/`mdDoc` wrappers to their text
+fn literal(value: &Value) -> Option<String> {
+    match value {
+        Value::Object(obj) if obj.contains_key("_type") => obj
+            .get("text")
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_string()),
+        Value::String(x) => Some(x.to_string()),
+        x => serde_json::to_string(x).ok(),
+    }
+}
+
+pub async fn downloadoptions(
+    mirrors: &Mirrors,
+    version: &str,
+    sourcedir: &str,
+    outdir: &str,
+    batchsize: usize,
+    progress: Progress,
+    force: bool,
+) -> Result<()> {
+    if !version.starts_with("nixos-") {
+        return Err(anyhow!(
+            "NixOS options are only available for nixos-* channels, not {}",
+            version
+        ));
+    }
+
+    debug!("Checking nixos version");
+    let release = mirrors
+        .latestrelease(version)
+        .await?
+        .ok_or_else(|| anyhow!("Could not find latest nixos version"))?;
+    debug!("Latest nixos version: {}", release.name);
+
+    let latestnixosver = releaseversion(&release.name);
+    info!("latestnixosver: {}", latestnixosver);
+
+    if uptodate(
+        sourcedir,
+        "nixosoptions",
+        outdir,
+        "nixosoptions.db",
+        latestnixosver,
+    )? && !force
+    {
+        debug!("No new version of nixos options found");
+        return Ok(());
+    }
+    createdirs(sourcedir, outdir)?;
+
+    debug!("Downloading options.json.br");
+    let optsfile = format!("{}/options.json.br", sourcedir);
+    release
+        .downloadto(mirrors, "options.json.br", &optsfile, None, progress)
+        .await
+        .context("Failed to download latest options.json")?;
+    debug!("Successfully downloaded options.json.br");
+    debug!("Reading options.json.br");
+    let reader = channel::decompress(&optsfile, progress)?;
+    let optjson: Result<HashMap<String, NixosOption>, _> =
+        tokio::task::spawn_blocking(move || serde_json::from_reader(reader)).await?;
+    fs::remove_file(&optsfile)?;
+    let optjson = optjson.context("Failed to parse options.json")?;
+    createdb(outdir, "nixosoptions", &optjson, batchsize).await?;
+    debug!("Finished creating nixos options database");
+
+    // Write version downloaded to file
+    File::create(format!("{}/nixosoptions.ver", sourcedir))?
+        .write_all(latestnixosver.as_bytes())?;
+    Ok(())
+}
+
+/// nix-darwin does not publish its options on a channel, so they are built from its flake
+pub async fn darwinoptions(
+    sourcedir: &str,
+    outdir: &str,
+    batchsize: usize,
+    force: bool,
+) -> Result<()> {
+    debug!("Checking nix-darwin revision");
+    let output = Command::new("nix")
+        .arg("flake")
+        .arg("metadata")
+        .arg("--json")
+        .arg(DARWIN_FLAKE)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to get nix-darwin flake metadata: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let metadata: Value = serde_json::from_slice(&output.stdout)?;
+    let latestdarwinrev = metadata
+        .get("revision")
+        .and_then(|x| x.as_str())
+        .context("nix-darwin flake metadata has no revision")?;
+    info!("latestdarwinrev: {}", latestdarwinrev);
+
+    if uptodate(
+        sourcedir,
+        "darwinoptions",
+        outdir,
+        "darwinoptions.db",
+        latestdarwinrev,
+    )? && !force
+    {
+        debug!("No new version of nix-darwin options found");
+        return Ok(());
+    }
+    createdirs(sourcedir, outdir)?;
+
+    debug!("Building nix-darwin options.json");
+    let output = Command::new("nix")
+        .arg("build")
+        .arg("--no-link")
+        .arg("--print-out-paths")
+        .arg(format!("{}/{}#optionsJSON", DARWIN_FLAKE, latestdarwinrev))
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to build nix-darwin options: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let outpath = String::from_utf8(output.stdout)?;
+    let outpath = outpath.trim();
+    let optfile = [
+        "share/doc/darwin/options.json",
+        "share/doc/nixos/options.json",
+    ]
+    .iter()
+    .map(|x| format!("{}/{}", outpath, x))
+    .find(|x| Path::new(x).exists())
+    .context("options.json not found in nix-darwin optionsJSON output")?;
+
+    debug!("Reading {}", optfile);
+    let reader = BufReader::new(File::open(&optfile)?);
+    let optjson: Result<HashMap<String, NixosOption>, _> =
+        tokio::task::spawn_blocking(move || serde_json::from_reader(reader)).await?;
+    let optjson = optjson.context("Failed to parse nix-darwin options.json")?;
+    createdb(outdir, "darwinoptions", &optjson, batchsize).await?;
+    debug!("Finished creating nix-darwin options database");
+
+    // Write revision downloaded to file
+    File::create(format!("{}/darwinoptions.ver", sourcedir))?
+        .write_all(latestdarwinrev.as_bytes())?;
+    Ok(())
+}
+
+/// Creates `<name>.db` in `outdir` with an `options` table filled from `optjson`
+async fn createdb(
+    outdir: &str,
+    name: &str,
+    optjson: &HashMap<String, NixosOption>,
+    batchsize: usize,
+) -> Result<()> {
+    let dbfile = format!("{}/{}.db", outdir, name);
+    let pool = schema::scratchdb(&dbfile, &schema::OPTIONS).await?;
+
+    debug!("Creating option rows");
+    let mut rows: Vec<Row> = Vec::new();
+    for (opt, data) in optjson {
+        rows.push(vec![
+            opt.into(),
+            data.description.as_ref().and_then(literal).into(),
+            data.opttype.as_ref().into(),
+            data.default.as_ref().and_then(literal).into(),
+            data.example.as_ref().and_then(literal).into(),
+            data.declarations
+                .as_ref()
+                .and_then(|x| serde_json::to_string(x).ok())
+                .into(),
+            data.readonly.unwrap_or(false).into(),
+        ]);
+    }
+    debug!("Inserting options into database");
+    importrows(&pool, "options", &rows, batchsize).await?;
+    schema::persist(pool, &dbfile).await
+}
@@ -0,0 +1,131 @@
+use std::{fs::File, sync::Arc};
+
+use ::parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use anyhow::{anyhow, Result};
+use log::debug;
+use sqlx::{sqlite::SqliteRow, Row, SqlitePool};
+
+use crate::schema::{self, Column};
+
+/// Rows per Parquet row group, also the rows read from SQLite at a time
+const ROW_GROUP_SIZE: usize = 100000;
+
+/// Writes the package tables of the generated SQLite database `dbfile` as Parquet files into
+/// `outdir`
+pub async fn export(dbfile: &str, outdir: &str) -> Result<()> {
+    let sqlite = SqlitePool::connect(&format!("sqlite://{}", dbfile)).await?;
+    let tables = schema::tables(&sqlite).await?;
+    for table in schema::PACKAGE_TABLES {
+        let columns = &tables
+            .iter()
+            .find(|x| x.0 == table)
+            .ok_or_else(|| anyhow!("{} has no {} table", dbfile, table))?
+            .1;
+        let file = format!("{}/{}.parquet", outdir, table);
+        debug!("Exporting {} to {}", table, file);
+        let rows = writetable(&sqlite, table, columns, File::create(&file)?).await?;
+        debug!("Exported {} rows of {}", rows, table);
+    }
+    Ok(())
+}
+
+/// Writes every row of `table` to `file`, returning how many there were
+async fn writetable(
+    sqlite: &SqlitePool,
+    table: &str,
+    columns: &[Column],
+    file: File,
+) -> Result<usize> {
+    // Every column is nullable, SQLite doesn't enforce NOT NULL on the imported data anyway
+    let message = format!(
+        "message {} {{ {} }}",
+        table,
+        columns
+            .iter()
+            .map(|x| if x.integer {
+                format!("OPTIONAL INT64 {};", x.name)
+            } else {
+                format!("OPTIONAL BYTE_ARRAY {} (STRING);", x.name)
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = SerializedFileWriter::new(
+        file,
+        Arc::new(parse_message_type(&message)?),
+        Arc::new(props),
+    )?;
+
+    let select = format!(
+        r#"SELECT {} FROM "{}" ORDER BY rowid LIMIT ? OFFSET ?"#,
+        columns
+            .iter()
+            .map(|x| format!(
+                r#"CAST("{}" AS {})"#,
+                x.name,
+                if x.integer { "INTEGER" } else { "TEXT" }
+            ))
+            .collect::<Vec<_>>()
+            .join(", "),
+        table
+    );
+    let mut offset = 0;
+    loop {
+        let rows = sqlx::query(&select)
+            .bind(ROW_GROUP_SIZE as i64)
+            .bind(offset as i64)
+            .fetch_all(sqlite)
+            .await?;
+        if rows.is_empty() {
+            break;
+        }
+        offset += rows.len();
+
+        let mut group = writer.next_row_group()?;
+        for (i, column) in columns.iter().enumerate() {
+            let mut col = group
+                .next_column()?
+                .ok_or_else(|| anyhow!("Missing Parquet column {}", column.name))?;
+            if column.integer {
+                let (values, levels) = levels(&rows, |x| x.get::<Option<i64>, _>(i));
+                col.typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)?;
+            } else {
+                let (values, levels) = levels(&rows, |x| {
+                    x.get::<Option<String>, _>(i)
+                        .map(|x| ByteArray::from(x.into_bytes()))
+                });
+                col.typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            col.close()?;
+        }
+        group.close()?;
+    }
+    writer.close()?;
+    Ok(offset)
+}
+
+/// Splits one column of `rows` into its non-null values and the definition level of each row
+fn levels<T>(rows: &[SqliteRow], get: impl Fn(&SqliteRow) -> Option<T>) -> (Vec<T>, Vec<i16>) {
+    let mut values = Vec::with_capacity(rows.len());
+    let mut levels = Vec::with_capacity(rows.len());
+    for row in rows {
+        match get(row) {
+            Some(x) => {
+                values.push(x);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+    (values, levels)
+}
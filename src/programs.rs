@@ -0,0 +1,59 @@
+use std::{
+    fs::{self, File},
+    process::Stdio,
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use sqlx::SqlitePool;
+use tokio::{io, process::Command};
+
+use crate::channel::{self, Mirrors, Release};
+
+/// Copies the command-not-found data shipped in the `nixexprs.tar.xz` of `release` into a
+/// `programs` table of `dbfile`. Only nixos-* channels ship it.
+pub async fn importprograms(mirrors: &Mirrors, release: &Release, dbfile: &str) -> Result<()> {
+    debug!("Downloading nixexprs.tar.xz");
+    let resp = release
+        .download(&mirrors.client, "nixexprs.tar.xz")
+        .await?
+        .error_for_status()?;
+
+    let programsfile = format!("{}.programs", dbfile);
+    let mut cmd = Command::new("tar")
+        .arg("-xJO")
+        .arg("--wildcards")
+        .arg("*/programs.sqlite")
+        .stdin(Stdio::piped())
+        .stdout(File::create(&programsfile)?)
+        .spawn()?;
+    let mut cmd_stdin = cmd.stdin.take().context("Failed to open tar stdin")?;
+    io::copy(&mut channel::body(resp), &mut cmd_stdin).await?;
+    drop(cmd_stdin);
+    let status = cmd.wait().await?;
+    if !status.success() {
+        fs::remove_file(&programsfile)?;
+        return Err(anyhow!("programs.sqlite not found in nixexprs.tar.xz"));
+    }
+
+    debug!("Inserting programs into database");
+    let pool = SqlitePool::connect(&format!("sqlite://{}", dbfile)).await?;
+    // ATTACH only applies to the connection it runs on
+    let mut conn = pool.acquire().await?;
+    sqlx::query("ATTACH DATABASE ? AS cnf")
+        .bind(&programsfile)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO "programs" SELECT "name", "package", "system" FROM cnf."Programs"
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("DETACH DATABASE cnf")
+        .execute(&mut *conn)
+        .await?;
+    fs::remove_file(&programsfile)?;
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle, TermLike};
+
+/// How progress of the long running phases is shown
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Hidden,
+    /// Progress bars redrawn in place
+    Bars,
+    /// Plain lines for logs, at most one a second per phase
+    Lines,
+}
+
+impl Progress {
+    /// Bars when stderr is a terminal, otherwise lines if `forced`
+    pub fn detect(forced: bool) -> Self {
+        if io::stderr().is_terminal() {
+            Progress::Bars
+        } else if forced {
+            Progress::Lines
+        } else {
+            Progress::Hidden
+        }
+    }
+
+    /// Progress of `msg` over `len` bytes, when known
+    pub fn bytes(&self, msg: &str, len: Option<u64>) -> ProgressBar {
+        self.bar(msg, len, self.bytestemplate(len.is_some()))
+    }
+
+    /// Sets the length of a [`Progress::bytes`] bar once it is known
+    pub fn setlength(&self, bar: &ProgressBar, len: u64) {
+        bar.set_length(len);
+        bar.set_style(style(self.bytestemplate(true)));
+        bar.disable_steady_tick();
+    }
+
+    fn bytestemplate(&self, known: bool) -> &'static str {
+        match (self, known) {
+            (Progress::Lines, true) => "{msg}: {percent}%",
+            (Progress::Lines, false) => "{msg}: {bytes}",
+            (_, true) => "{msg} [{bar:40}] {bytes}/{total_bytes} ({percent}%, {eta})",
+            (_, false) => "{spinner} {msg} {bytes}",
+        }
+    }
+
+    /// Progress of `msg` counting `unit` without a known total
+    pub fn count(&self, msg: &str, unit: &str) -> ProgressBar {
+        let template = match self {
+            Progress::Lines => format!("{{msg}}: {{human_pos}} {}", unit),
+            _ => format!("{{spinner}} {{msg}} {{human_pos}} {}", unit),
+        };
+        self.bar(msg, None, &template)
+    }
+
+    fn bar(&self, msg: &str, len: Option<u64>, template: &str) -> ProgressBar {
+        let target = match self {
+            Progress::Hidden => return ProgressBar::hidden(),
+            Progress::Bars => ProgressDrawTarget::stderr(),
+            Progress::Lines => ProgressDrawTarget::term_like_with_hz(Box::<Lines>::default(), 1),
+        };
+        let bar = ProgressBar::with_draw_target(len, target)
+            .with_message(msg.to_string())
+            .with_style(style(template));
+        // Spinners only move when redrawn
+        if *self == Progress::Bars && len.is_none() {
+            bar.enable_steady_tick(Duration::from_millis(100));
+        }
+        bar
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ")
+}
+
+/// Writes each redraw as a line of its own, leaving out lines that didn't change
+#[derive(Debug, Default)]
+struct Lines {
+    last: Mutex<String>,
+}
+
+impl TermLike for Lines {
+    fn width(&self) -> u16 {
+        80
+    }
+
+    fn move_cursor_up(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, s: &str) -> io::Result<()> {
+        // Lines are padded to the width to overwrite what was drawn before
+        let s = s.trim_end();
+        let mut last = self.last.lock().unwrap();
+        if s.is_empty() || *last == s {
+            return Ok(());
+        }
+        *last = s.to_string();
+        writeln!(io::stderr(), "{}", s)
+    }
+
+    fn write_str(&self, s: &str) -> io::Result<()> {
+        self.write_line(s)
+    }
+
+    fn clear_line(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use log::info;
+use reqwest::Client;
+
+use crate::{channel, github, s3, sftp};
+
+/// Where --publish uploads the generated databases to
+#[derive(Clone)]
+pub enum Target {
+    /// Objects below `prefix` in an S3 bucket
+    S3 { bucket: String, prefix: String },
+    /// Assets of the release of a GitHub repository tagged with the version published
+    GitHub { repository: String },
+    /// Directory on a host reachable over SSH, `dir` is relative to the home directory unless it
+    /// starts with a `/`
+    Sftp {
+        host: String,
+        port: Option<u16>,
+        dir: String,
+    },
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::S3 { bucket, prefix } if prefix.is_empty() => write!(f, "s3://{}", bucket),
+            Target::S3 { bucket, prefix } => write!(f, "s3://{}/{}", bucket, prefix),
+            Target::GitHub { repository } => write!(f, "github:{}", repository),
+            Target::Sftp { host, port, dir } => {
+                write!(f, "sftp://{}", host)?;
+                if let Some(port) = port {
+                    write!(f, ":{}", port)?;
+                }
+                match dir.strip_prefix('/') {
+                    Some(dir) => write!(f, "/{}", dir),
+                    None => write!(f, "/~/{}", dir),
+                }
+            }
+        }
+    }
+}
+
+/// Parses a target such as `s3://bucket/prefix`, `github:owner/repo` or
+/// `sftp://user@host:port/path`, where a path starting with `/~/` is in the home directory
+pub fn target(s: &str) -> Result<Target, String> {
+    if let Some(path) = s.strip_prefix("s3://") {
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        if bucket.is_empty() {
+            return Err(format!("{} has no bucket", s));
+        }
+        return Ok(Target::S3 {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        });
+    }
+    if let Some(repository) = s.strip_prefix("github:") {
+        if repository.split('/').filter(|x| !x.is_empty()).count() != 2 {
+            return Err(format!(
+                "{} is not a repository such as owner/repo",
+                repository
+            ));
+        }
+        return Ok(Target::GitHub {
+            repository: repository.to_string(),
+        });
+    }
+    if let Some(url) = s.strip_prefix("sftp://") {
+        let (authority, path) = url.split_once('/').unwrap_or((url, ""));
+        // An IPv6 address is bracketed, its colons are not a port
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                Some(
+                    port.parse()
+                        .map_err(|_| format!("{} is not a port", port))?,
+                ),
+            ),
+            _ => (authority, None),
+        };
+        if host.is_empty() {
+            return Err(format!("{} has no host", s));
+        }
+        let dir = match path.strip_prefix('~') {
+            Some(home) => home.trim_matches('/').to_string(),
+            None if path.is_empty() => String::new(),
+            None => format!("/{}", path.trim_matches('/')),
+        };
+        return Ok(Target::Sftp {
+            host: host.to_string(),
+            port,
+            dir,
+        });
+    }
+    Err(format!(
+        "{} is not a target such as s3://bucket/prefix, github:owner/repo or sftp://host/path",
+        s
+    ))
+}
+
+/// Uploads `files` built from `version`, each a path and the name to publish it as, to `subdir`
+/// of `target`. They are uploaded in order, so the version marker goes last.
+///
+/// Releases on GitHub have no directories, they are tagged `<subdir>-<version>` instead, so only
+/// versioned sources can be published there.
+pub async fn publish(
+    target: &Target,
+    client: &Client,
+    retries: u32,
+    version: Option<&str>,
+    subdir: Option<&str>,
+    files: &[(String, String)],
+) -> Result<()> {
+    info!("Publishing to {}", target);
+    match target {
+        Target::S3 { bucket, prefix } => {
+            let bucket = s3::Bucket::fromenv(bucket)?;
+            for (file, name) in files {
+                let key = [prefix.as_str(), subdir.unwrap_or_default(), name]
+                    .iter()
+                    .filter(|x| !x.is_empty())
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join("/");
+                channel::retry(&format!("uploading {}", name), retries, || {
+                    bucket.put(client, &key, file)
+                })
+                .await?;
+            }
+        }
+        Target::GitHub { repository } => {
+            let version = version.ok_or_else(|| {
+                anyhow!(
+                    "The source has no version to tag a release of {} with",
+                    repository
+                )
+            })?;
+            let repository = github::Repository::fromenv(repository)?;
+            let tag = match subdir {
+                Some(subdir) => format!("{}-{}", subdir, version),
+                None => version.to_string(),
+            };
+            for (file, name) in files {
+                // The release is looked up again for each file, a failed upload may leave an
+                // asset behind that has to be replaced
+                channel::retry(&format!("uploading {}", name), retries, || async {
+                    let release = repository.release(client, &tag).await?;
+                    repository.upload(client, &release, name, file).await
+                })
+                .await?;
+            }
+        }
+        Target::Sftp { host, port, dir } => {
+            let dir = match subdir {
+                Some(subdir) if dir.is_empty() => subdir.to_string(),
+                Some(subdir) => format!("{}/{}", dir.trim_end_matches('/'), subdir),
+                None => dir.clone(),
+            };
+            sftp::upload(host, *port, &dir, files).await?;
+        }
+    }
+    Ok(())
+}
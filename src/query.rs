@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Serializer};
+use sqlx::{FromRow, SqlitePool};
+
+/// A generated package database, opened for reading only as generations update it in place
+pub struct Database {
+    pub(crate) pool: SqlitePool,
+}
+
+/// A package as listed
+#[derive(Serialize, FromRow)]
+pub struct PackageSummary {
+    pub attribute: String,
+    pub pname: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+/// A package with all of its metadata
+#[derive(Serialize, FromRow)]
+pub struct Package {
+    pub attribute: String,
+    pub system: Option<String>,
+    pub pname: Option<String>,
+    pub version: Option<String>,
+    #[serde(serialize_with = "json")]
+    pub systems: Option<String>,
+    pub repo: Option<String>,
+    pub in_cache: Option<bool>,
+    pub outputname: Option<String>,
+    #[serde(serialize_with = "json")]
+    pub outputs: Option<String>,
+    pub broken: Option<bool>,
+    pub insecure: Option<bool>,
+    pub unsupported: Option<bool>,
+    pub unfree: Option<bool>,
+    pub description: Option<String>,
+    pub longdescription: Option<String>,
+    pub homepage: Option<String>,
+    #[serde(serialize_with = "json")]
+    pub maintainers: Option<String>,
+    pub position: Option<String>,
+    #[serde(serialize_with = "json")]
+    pub license: Option<String>,
+    #[serde(serialize_with = "json")]
+    pub platforms: Option<String>,
+    #[serde(serialize_with = "json")]
+    pub knownvulnerabilities: Option<String>,
+    pub mainprogram: Option<String>,
+    pub changelog: Option<String>,
+    pub source_url: Option<String>,
+    /// Name to add to permittedInsecurePackages when insecure
+    pub permitted_insecure: Option<String>,
+}
+
+/// A maintainer of a package, directly or through one of its teams
+#[derive(Serialize, FromRow)]
+pub struct Maintainer {
+    pub name: Option<String>,
+    pub github: Option<String>,
+    pub githubid: Option<i64>,
+    pub email: Option<String>,
+    pub matrix: Option<String>,
+    /// Team the maintainer is a member of, `None` when listed directly
+    pub team: Option<String>,
+}
+
+/// Writes a JSON column as the JSON it holds rather than as a string
+pub(crate) fn json<S: Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value
+        .as_deref()
+        .and_then(|x| serde_json::from_str::<serde_json::Value>(x).ok())
+        .serialize(serializer)
+}
+
+/// `search` as an FTS5 query matching the beginnings of every word of it
+pub(crate) fn fts(search: &str) -> Option<String> {
+    let words = search
+        .split_whitespace()
+        .map(|x| format!("\"{}\"*", x.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+impl Database {
+    /// Opens the package database `dbfile`
+    pub async fn open(dbfile: &str) -> Result<Self> {
+        if !Path::new(dbfile).exists() {
+            return Err(anyhow!("{} has not been generated", dbfile));
+        }
+        Ok(Database {
+            pool: SqlitePool::connect(&format!("sqlite://{}?mode=ro", dbfile)).await?,
+        })
+    }
+
+    /// Packages matching every word of `search` at the beginning of a word of their names or
+    /// descriptions, best matches first
+    pub async fn search(&self, search: &str) -> Result<Vec<PackageSummary>> {
+        match fts(search) {
+            Some(fts) => Ok(self.page(Some(&fts), -1, 0).await?.1),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The package `attribute` with all of its metadata
+    pub async fn get_package(&self, attribute: &str) -> Result<Option<Package>> {
+        Ok(sqlx::query_as(
+            r#"SELECT "pkgs"."attribute", "system", "pname", "version", "systems", "repo", "in_cache",
+                "outputname", "outputs", "broken", "insecure", "unsupported", "unfree", "description",
+                "longdescription", "homepage", "maintainers", "position", "license", "platforms",
+                "knownvulnerabilities", "mainprogram", "changelog", "source_url",
+                "permitted_insecure"
+            FROM "pkgs"
+            LEFT JOIN "meta" ON "meta"."attribute" = "pkgs"."attribute"
+            WHERE "pkgs"."attribute" = ?"#,
+        )
+        .bind(attribute)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Packages named `pname`, e.g. python311 and python312 for python3, by attribute
+    pub async fn versions_of(&self, pname: &str) -> Result<Vec<PackageSummary>> {
+        Ok(sqlx::query_as(
+            r#"SELECT "pkgs"."attribute", "pkgs"."pname", "pkgs"."version", "meta"."description"
+            FROM "pkgs"
+            LEFT JOIN "meta" ON "meta"."attribute" = "pkgs"."attribute"
+            WHERE "pkgs"."pname" = ?
+            ORDER BY "pkgs"."attribute""#,
+        )
+        .bind(pname)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Maintainers of the package `attribute`, those listed directly first
+    pub async fn maintainers(&self, attribute: &str) -> Result<Vec<Maintainer>> {
+        Ok(sqlx::query_as(
+            r#"SELECT "name", "github", "githubid", "email", "matrix", "team"
+            FROM "pkgmaintainers"
+            WHERE "attribute" = ?
+            ORDER BY "team" IS NOT NULL, "rowid""#,
+        )
+        .bind(attribute)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Packages maintained by the GitHub user `github`, directly or through a team, by attribute
+    pub async fn maintained_by(&self, github: &str) -> Result<Vec<PackageSummary>> {
+        Ok(sqlx::query_as(
+            r#"SELECT "pkgs"."attribute", "pkgs"."pname", "pkgs"."version", "meta"."description"
+            FROM "pkgs"
+            LEFT JOIN "meta" ON "meta"."attribute" = "pkgs"."attribute"
+            WHERE "pkgs"."attribute" IN (
+                SELECT "attribute" FROM "pkgmaintainers" WHERE "github" = ? COLLATE NOCASE
+            )
+            ORDER BY "pkgs"."attribute""#,
+        )
+        .bind(github)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// How many packages match the FTS5 query `fts`, or how many there are without one, and
+    /// `limit` of them after the first `offset`, every one with a negative `limit`
+    pub(crate) async fn page(
+        &self,
+        fts: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<PackageSummary>)> {
+        Ok(match fts {
+            Some(fts) => {
+                let (total,): (i64,) =
+                    sqlx::query_as(r#"SELECT COUNT(*) FROM "pkgs_fts" WHERE "pkgs_fts" MATCH ?"#)
+                        .bind(fts)
+                        .fetch_one(&self.pool)
+                        .await?;
+                let items = sqlx::query_as(
+                    r#"SELECT "pkgs"."attribute", "pkgs"."pname", "pkgs"."version", "meta"."description"
+                    FROM "pkgs_fts"
+                    JOIN "pkgs" ON "pkgs"."attribute" = "pkgs_fts"."attribute"
+                    LEFT JOIN "meta" ON "meta"."attribute" = "pkgs"."attribute"
+                    WHERE "pkgs_fts" MATCH ?
+                    ORDER BY "pkgs_fts"."rank", "pkgs"."attribute"
+                    LIMIT ? OFFSET ?"#,
+                )
+                .bind(fts)
+                .bind(limit)
+                .bind(offset)
+                .fetch_all(&self.pool)
+                .await?;
+                (total, items)
+            }
+            None => {
+                let (total,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM "pkgs""#)
+                    .fetch_one(&self.pool)
+                    .await?;
+                let items = sqlx::query_as(
+                    r#"SELECT "pkgs"."attribute", "pkgs"."pname", "pkgs"."version", "meta"."description"
+                    FROM "pkgs"
+                    LEFT JOIN "meta" ON "meta"."attribute" = "pkgs"."attribute"
+                    ORDER BY "pkgs"."attribute"
+                    LIMIT ? OFFSET ?"#,
+                )
+                .bind(limit)
+                .bind(offset)
+                .fetch_all(&self.pool)
+                .await?;
+                (total, items)
+            }
+        })
+    }
+}
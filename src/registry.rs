@@ -0,0 +1,148 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{importrows, schema, Row};
+
+/// Picks the searchable fields of every package so the whole output evaluates to plain JSON
+const PACKAGES_APPLY: &str = r#"
+builtins.mapAttrs (_: p: {
+  name = p.name or null;
+  pname = p.pname or null;
+  version = p.version or null;
+  description = p.meta.description or null;
+})
+"#;
+
+#[derive(Debug, Deserialize)]
+struct FlakePkg {
+    name: Option<String>,
+    pname: Option<String>,
+    version: Option<String>,
+    description: Option<String>,
+}
+
+/// A flake registry entry
+struct RegistryEntry {
+    /// Registry name, e.g. `flake:nixpkgs`
+    from: String,
+    /// Flake reference it resolves to
+    to: String,
+}
+
+/// Reads the user, system and global flake registries
+async fn registry() -> Result<Vec<RegistryEntry>> {
+    let output = Command::new("nix")
+        .arg("registry")
+        .arg("list")
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to list flake registry: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    // Lines look like `global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable`. The user and
+    // system registries are listed before the global one and take precedence over it, so only the
+    // first entry of each flake is kept
+    let mut seen = HashSet::new();
+    Ok(String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some(RegistryEntry {
+                from: fields.next()?.to_string(),
+                to: fields.next()?.to_string(),
+            })
+        })
+        .filter(|entry| seen.insert(entry.from.clone()))
+        .collect())
+}
+
+async fn currentsystem() -> Result<String> {
+    let output = Command::new("nix")
+        .arg("eval")
+        .arg("--impure")
+        .arg("--raw")
+        .arg("--expr")
+        .arg("builtins.currentSystem")
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to get current system: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Evaluates `packages.<system>` of the flake `flakeref`
+async fn flakepackages(flakeref: &str, system: &str) -> Result<HashMap<String, FlakePkg>> {
+    let output = Command::new("nix")
+        .arg("eval")
+        .arg("--json")
+        .arg(format!("{}#packages.{}", flakeref, system))
+        .arg("--apply")
+        .arg(PACKAGES_APPLY)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+pub async fn registrydb(sourcedir: &str, system: Option<&str>, batchsize: usize) -> Result<()> {
+    let system = match system {
+        Some(system) => system.to_string(),
+        None => currentsystem().await?,
+    };
+    info!("Indexing flake registry packages for {}", system);
+
+    let mut rows: Vec<Row> = Vec::new();
+    for entry in registry().await? {
+        debug!("Evaluating {} ({})", entry.from, entry.to);
+        let packages = match flakepackages(&entry.to, &system).await {
+            Ok(packages) => packages,
+            Err(e) => {
+                warn!("Skipping {}: {}", entry.from, e);
+                continue;
+            }
+        };
+        for (attr, data) in packages {
+            rows.push(vec![
+                (&entry.from).into(),
+                (&entry.to).into(),
+                attr.into(),
+                data.name.into(),
+                data.pname.into(),
+                data.version.into(),
+                data.description.into(),
+            ]);
+        }
+    }
+
+    if !Path::new(sourcedir).exists() {
+        fs::create_dir_all(sourcedir)?;
+    }
+    let dbfile = format!("{}/flakes.db", sourcedir);
+    let pool = schema::scratchdb(&dbfile, &schema::FLAKES).await?;
+
+    debug!("Inserting flake packages into database");
+    importrows(&pool, "flakes", &rows, batchsize).await?;
+    schema::persist(pool, &dbfile).await?;
+    debug!("Finished creating flakes database");
+    Ok(())
+}
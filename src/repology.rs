@@ -0,0 +1,149 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{channel, models::NixosPkg};
+
+/// Repology project listing
+const REPOLOGY_URL: &str = "https://repology.org/api/v1/projects";
+
+/// Repology repository tracking nixpkgs master
+const REPOLOGY_REPO: &str = "nix_unstable";
+
+/// Repology asks API users to stay below one request per second
+const REPOLOGY_DELAY: Duration = Duration::from_millis(1100);
+
+/// How long the downloaded versions are reused before fetching them again
+const REPOLOGY_CACHE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Newest version of the Repology project a pname belongs to
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Upstream {
+    pub project: String,
+    pub version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RepologyPackage {
+    repo: String,
+    srcname: Option<String>,
+    binname: Option<String>,
+    visiblename: Option<String>,
+    version: String,
+    status: Option<String>,
+}
+
+/// Downloads every Repology project packaged in nixpkgs, keyed by the nixpkgs names of its packages
+async fn download(proxy: Option<&str>) -> Result<HashMap<String, Upstream>> {
+    let client = channel::clientbuilder(proxy)?
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .timeout(Duration::from_secs(60))
+        .build()?;
+    let mut upstream = HashMap::new();
+    let mut start = String::new();
+    loop {
+        debug!("Downloading Repology projects from {:?}", start);
+        let url = if start.is_empty() {
+            format!("{}/", REPOLOGY_URL)
+        } else {
+            format!("{}/{}/", REPOLOGY_URL, start)
+        };
+        let resp = client
+            .get(url)
+            .query(&[("inrepo", REPOLOGY_REPO)])
+            .send()
+            .await?
+            .error_for_status()?;
+        let page: HashMap<String, Vec<RepologyPackage>> =
+            serde_json::from_slice(&resp.bytes().await?)?;
+
+        // Pages start at and include the given project
+        let mut projects = page
+            .into_iter()
+            .filter(|(x, _)| *x != start)
+            .collect::<Vec<_>>();
+        if projects.is_empty() {
+            break;
+        }
+        projects.sort_by(|a, b| a.0.cmp(&b.0));
+        start = projects.last().map(|x| x.0.clone()).unwrap_or_default();
+
+        for (project, packages) in projects {
+            let Some(newest) = packages
+                .iter()
+                .find(|x| x.status.as_deref() == Some("newest"))
+            else {
+                continue;
+            };
+            let names = packages
+                .iter()
+                .filter(|x| x.repo == REPOLOGY_REPO)
+                .flat_map(|x| [&x.srcname, &x.binname, &x.visiblename])
+                .flatten()
+                .collect::<HashSet<_>>();
+            for name in names {
+                upstream.insert(
+                    name.to_string(),
+                    Upstream {
+                        project: project.to_string(),
+                        version: newest.version.to_string(),
+                    },
+                );
+            }
+        }
+        tokio::time::sleep(REPOLOGY_DELAY).await;
+    }
+    Ok(upstream)
+}
+
+/// Loads the versions cached in `sourcedir`, refreshing them from Repology when stale
+async fn upstreamversions(
+    sourcedir: &str,
+    proxy: Option<&str>,
+) -> Result<HashMap<String, Upstream>> {
+    let cachefile = format!("{}/repology.json", sourcedir);
+    let fresh = fs::metadata(&cachefile)
+        .and_then(|x| x.modified())
+        .ok()
+        .and_then(|x| SystemTime::now().duration_since(x).ok())
+        .is_some_and(|x| x < REPOLOGY_CACHE_AGE);
+    if fresh {
+        debug!("Using cached Repology versions from {}", cachefile);
+        return Ok(serde_json::from_reader(BufReader::new(File::open(
+            &cachefile,
+        )?))?);
+    }
+
+    info!("Downloading upstream versions from Repology, this takes a while");
+    let upstream = download(proxy).await?;
+    serde_json::to_writer(BufWriter::new(File::create(&cachefile)?), &upstream)?;
+    Ok(upstream)
+}
+
+/// Looks up the newest upstream version of every pname in `packages`
+pub async fn upstream<'a>(
+    sourcedir: &str,
+    packages: &[(&'a String, &'a NixosPkg)],
+    proxy: Option<&str>,
+) -> Result<Vec<(&'a str, Upstream)>> {
+    let mut versions = upstreamversions(sourcedir, proxy).await?;
+    let mut found = Vec::new();
+    for (_, data) in packages {
+        if let Some(x) = versions.remove(&data.pname) {
+            found.push((data.pname.as_str(), x));
+        }
+    }
+    info!("Found upstream versions for {} pnames", found.len());
+    Ok(found)
+}
@@ -0,0 +1,162 @@
+use std::env;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::debug;
+use reqwest::{Body, Client, Url};
+use sha2::{Digest, Sha256};
+use tokio_util::io::ReaderStream;
+
+use crate::compress;
+
+/// Region signed for unless `AWS_REGION` says otherwise, S3-compatible stores mostly ignore it
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// A bucket of S3 or of an S3-compatible object storage, configured like the AWS CLI through
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN`, `AWS_REGION` and
+/// `AWS_ENDPOINT_URL`
+pub struct Bucket {
+    name: String,
+    region: String,
+    /// Path-style endpoint of an S3-compatible store, AWS itself otherwise
+    endpoint: Option<String>,
+    accesskey: String,
+    secretkey: String,
+    sessiontoken: Option<String>,
+}
+
+impl Bucket {
+    /// The bucket `name` with the credentials of the environment
+    pub fn fromenv(name: &str) -> Result<Self> {
+        let var = |x: &str| env::var(x).ok().filter(|x| !x.is_empty());
+        Ok(Bucket {
+            name: name.to_string(),
+            region: var("AWS_REGION")
+                .or_else(|| var("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            endpoint: var("AWS_ENDPOINT_URL").map(|x| x.trim_end_matches('/').to_string()),
+            accesskey: var("AWS_ACCESS_KEY_ID")
+                .ok_or_else(|| anyhow!("AWS_ACCESS_KEY_ID is needed to publish to S3"))?,
+            secretkey: var("AWS_SECRET_ACCESS_KEY")
+                .ok_or_else(|| anyhow!("AWS_SECRET_ACCESS_KEY is needed to publish to S3"))?,
+            sessiontoken: var("AWS_SESSION_TOKEN"),
+        })
+    }
+
+    /// URL of the object `key`
+    fn url(&self, key: &str) -> Result<Url> {
+        let path = key.split('/').map(encode).collect::<Vec<_>>().join("/");
+        let url = match &self.endpoint {
+            Some(endpoint) => format!("{}/{}/{}", endpoint, self.name, path),
+            None => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                self.name, self.region, path
+            ),
+        };
+        Url::parse(&url).with_context(|| format!("Invalid S3 URL {}", url))
+    }
+
+    /// Uploads `file` as the object `key`. The request is signed with the SHA-256 of the file,
+    /// so S3 rejects it when the upload does not match.
+    pub async fn put(&self, client: &Client, key: &str, file: &str) -> Result<()> {
+        debug!("Uploading {} to s3://{}/{}", file, self.name, key);
+        let url = self.url(key)?;
+        let hash = compress::sha256(file)?;
+        let now = Utc::now();
+        let amzdate = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        // Signature Version 4, headers in the order they are signed in
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", hash.clone()),
+            ("x-amz-date", amzdate.clone()),
+        ];
+        if let Some(token) = &self.sessiontoken {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signedheaders = headers.iter().map(|x| x.0).collect::<Vec<_>>().join(";");
+        let canonicalrequest = format!(
+            "PUT\n{}\n\n{}\n{}\n{}",
+            url.path(),
+            headers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+                .collect::<String>(),
+            signedheaders,
+            hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let stringtosign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            amzdate,
+            scope,
+            Sha256::digest(canonicalrequest.as_bytes())
+        );
+        let signingkey = [date.as_str(), &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.secretkey).into_bytes(), |key, x| {
+                hmac(&key, x)
+            });
+        let signature = hmac(&signingkey, &stringtosign)
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect::<String>();
+
+        let mut request = client
+            .put(url)
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.accesskey, scope, signedheaders, signature
+                ),
+            )
+            .header("content-length", tokio::fs::metadata(file).await?.len())
+            .header("content-type", "application/octet-stream");
+        for (name, value) in headers.into_iter().skip(1) {
+            request = request.header(name, value);
+        }
+        let body = Body::wrap_stream(ReaderStream::new(tokio::fs::File::open(file).await?));
+        let response = request.body(body).send().await?;
+        // Server errors are left to the caller to retry
+        if response.status().is_server_error() {
+            response.error_for_status_ref()?;
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to upload {} to s3://{}/{}: {} {}",
+                file,
+                self.name,
+                key,
+                response.status(),
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes a segment of an object key the way it is signed
+fn encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (x as char).to_string()
+            }
+            x => format!("%{:02X}", x),
+        })
+        .collect()
+}
@@ -0,0 +1,398 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use log::{debug, warn};
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+    Connection, SqliteConnection, SqliteExecutor, SqlitePool,
+};
+
+/// Schema of `nixpkgs.db`
+pub static NIXPKGS: Migrator = sqlx::migrate!("migrations/nixpkgs");
+/// Schema of `nixpkgs_versions.db`
+pub static VERSIONS: Migrator = sqlx::migrate!("migrations/versions");
+/// Schema of `nixosoptions.db` and `darwinoptions.db`
+pub static OPTIONS: Migrator = sqlx::migrate!("migrations/options");
+/// Schema of `flakes.db`
+pub static FLAKES: Migrator = sqlx::migrate!("migrations/flakes");
+/// Schema of `history.db`
+pub static HISTORY: Migrator = sqlx::migrate!("migrations/history");
+
+/// Tables of `nixpkgs.db` written out by the table exports
+pub const PACKAGE_TABLES: [&str; 2] = ["pkgs", "meta"];
+
+/// `PRAGMA application_id` of every generated database, "NxDG"
+pub const APPLICATION_ID: i32 = 0x4e784447;
+
+/// Page size of generated databases, larger pages suit their long text columns
+const PAGE_SIZE: u32 = 8192;
+
+/// Page cache of each connection while building, in KiB
+const CACHE_SIZE: u32 = 64 * 1024;
+
+/// Latest schema version of `migrator`, recorded in the `schema_version` table
+pub fn version(migrator: &Migrator) -> i64 {
+    migrator.iter().map(|x| x.version).max().unwrap_or(0)
+}
+
+/// Opens `dbfile` upgraded to the latest schema of `migrator` and emptied of its data.
+/// Databases that can't be upgraded, like those from before migrations, are recreated.
+pub async fn opendb(dbfile: &str, migrator: &Migrator) -> Result<SqlitePool> {
+    if Path::new(dbfile).exists() {
+        let pool = SqlitePool::connect_with(bulkoptions(dbfile)).await?;
+        match migrate(&pool, migrator).await {
+            Ok(()) => return Ok(pool),
+            Err(e) => {
+                warn!("Recreating {}, it can't be upgraded: {}", dbfile, e);
+                pool.close().await;
+                fs::remove_file(dbfile)?;
+            }
+        }
+    }
+
+    debug!("Creating SQLite database {}", dbfile);
+    let pool = SqlitePool::connect_with(bulkoptions(dbfile)).await?;
+    migrate(&pool, migrator).await?;
+    Ok(pool)
+}
+
+/// Opens `dbfile` upgraded to the latest schema of `migrator`, keeping its data. For databases
+/// accumulating rows across runs, which unlike the others can't be rebuilt from one run.
+pub async fn keepdb(dbfile: &str, migrator: &Migrator) -> Result<SqlitePool> {
+    let options = SqliteConnectOptions::new()
+        .filename(dbfile)
+        .create_if_missing(true)
+        .page_size(PAGE_SIZE);
+    let pool = SqlitePool::connect_with(options).await?;
+    migrator.run(&pool).await?;
+    let mut tx = pool.begin().await?;
+    sqlx::query(r#"DELETE FROM "schema_version""#)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(r#"INSERT INTO "schema_version" ("version") VALUES (?)"#)
+        .bind(version(migrator))
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    stamp(&pool, migrator).await?;
+    Ok(pool)
+}
+
+/// Connection settings for loading `dbfile` in bulk. Durability is traded for speed, an
+/// interrupted run leaves no version file behind and the database is rebuilt on the next one.
+fn bulkoptions(dbfile: &str) -> SqliteConnectOptions {
+    SqliteConnectOptions::new()
+        .filename(dbfile)
+        .create_if_missing(true)
+        // Rows can't reference missing packages, and deleting a package deletes its metadata
+        .foreign_keys(true)
+        // Only applies to new databases, before any table exists
+        .page_size(PAGE_SIZE)
+        // Readers don't block the bulk load
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(SqliteSynchronous::Off)
+        .pragma("cache_size", format!("-{}", CACHE_SIZE))
+}
+
+/// Where `dbfile` is built before [`persist`] writes it out
+pub fn scratchfile(dbfile: &str) -> String {
+    format!("{}.partial", dbfile)
+}
+
+/// Creates an empty database with the latest schema of `migrator` to build `dbfile` in.
+/// Readers of `dbfile` keep seeing the previous database until [`persist`] replaces it.
+pub async fn scratchdb(dbfile: &str, migrator: &Migrator) -> Result<SqlitePool> {
+    let scratch = scratchfile(dbfile);
+    // Left behind by an interrupted run
+    removedb(&scratch)?;
+    opendb(&scratch, migrator).await
+}
+
+/// Writes the database built in `pool` to `dbfile`. A previous `dbfile` of the same schema is
+/// updated in place so its readers keep their connections, otherwise the database is written
+/// compacted and replaces the previous one at once.
+pub async fn persist(pool: SqlitePool, dbfile: &str) -> Result<()> {
+    if Path::new(dbfile).exists() {
+        match update(&pool, dbfile).await {
+            Ok(true) => {
+                pool.close().await;
+                return removedb(&scratchfile(dbfile));
+            }
+            Ok(false) => debug!("{} has a different schema, rewriting it", dbfile),
+            Err(e) => warn!("Rewriting {}, it can't be updated in place: {}", dbfile, e),
+        }
+    }
+
+    debug!("Writing {}", dbfile);
+    let newfile = format!("{}.new", dbfile);
+    removedb(&newfile)?;
+    // Statistics for the query planner, copied along with the data
+    sqlx::query("ANALYZE").execute(&pool).await?;
+    // The copy is a rollback journal database, which unlike WAL can be read from read-only media
+    sqlx::query("VACUUM INTO ?")
+        .bind(&newfile)
+        .execute(&pool)
+        .await?;
+    pool.close().await;
+
+    // A stale WAL would be applied to the new file
+    removewal(dbfile)?;
+    fs::rename(&newfile, dbfile)?;
+    removedb(&scratchfile(dbfile))
+}
+
+/// Applies the rows that differ between the database built in `pool` and `dbfile` to `dbfile`,
+/// returning false without touching it when their schemas differ
+async fn update(pool: &SqlitePool, dbfile: &str) -> Result<bool> {
+    // ATTACH only applies to the connection it runs on
+    let mut conn = pool.acquire().await?;
+    sqlx::query(r#"ATTACH DATABASE ? AS "target""#)
+        .bind(dbfile)
+        .execute(&mut *conn)
+        .await?;
+    let updated = updatetarget(&mut conn).await;
+    sqlx::query(r#"DETACH DATABASE "target""#)
+        .execute(&mut *conn)
+        .await?;
+    updated
+}
+
+/// Updates the attached `target` database to the rows of `main` in one transaction
+async fn updatetarget(conn: &mut SqliteConnection) -> Result<bool> {
+    for pragma in ["application_id", "user_version"] {
+        let (built,): (i64,) = sqlx::query_as(&format!("PRAGMA main.{}", pragma))
+            .fetch_one(&mut *conn)
+            .await?;
+        let (previous,): (i64,) = sqlx::query_as(&format!("PRAGMA target.{}", pragma))
+            .fetch_one(&mut *conn)
+            .await?;
+        if built != previous {
+            return Ok(false);
+        }
+    }
+    debug!("Updating the previous database in place");
+
+    let tables = datatables(&mut *conn).await?;
+    let mut tx = conn.begin().await?;
+    // Rows are removed and added table by table, references only hold once all are done
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut tx)
+        .await?;
+    for table in tables {
+        let columns: Vec<(String, i64)> =
+            sqlx::query_as(r#"SELECT "name", "pk" FROM pragma_table_info(?) ORDER BY "cid""#)
+                .bind(&table)
+                .fetch_all(&mut tx)
+                .await?;
+        let names = columns
+            .iter()
+            .map(|x| format!(r#""{}""#, x.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let keys = columns.iter().filter(|x| x.1 > 0).collect::<Vec<_>>();
+
+        let (removed, changed) = if keys.is_empty() {
+            // Rows without a key can only be told apart by all of their columns, NULLs included
+            sqlx::query(&format!(
+                r#"CREATE TEMP TABLE "removed" AS SELECT {0} FROM target."{1}" EXCEPT SELECT {0} FROM main."{1}""#,
+                names, table
+            ))
+            .execute(&mut tx)
+            .await?;
+            sqlx::query(&format!(
+                r#"CREATE INDEX temp."removedrows" ON "removed" ({})"#,
+                names
+            ))
+            .execute(&mut tx)
+            .await?;
+            let removed = sqlx::query(&format!(
+                r#"
+                DELETE FROM target."{}" WHERE rowid IN (
+                    SELECT "t".rowid FROM target."{}" AS "t" JOIN temp."removed" AS "r" ON {}
+                )
+                "#,
+                table,
+                table,
+                columns
+                    .iter()
+                    .map(|x| format!(r#""t"."{0}" IS "r"."{0}""#, x.0))
+                    .collect::<Vec<_>>()
+                    .join(" AND ")
+            ))
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+            sqlx::query(r#"DROP TABLE temp."removed""#)
+                .execute(&mut tx)
+                .await?;
+            let added = sqlx::query(&format!(
+                r#"INSERT INTO target."{1}" ({0}) SELECT {0} FROM main."{1}" EXCEPT SELECT {0} FROM target."{1}""#,
+                names, table
+            ))
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+            (removed, added)
+        } else {
+            let removed = sqlx::query(&format!(
+                r#"DELETE FROM target."{0}" WHERE NOT EXISTS (SELECT 1 FROM main."{0}" AS "n" WHERE {1})"#,
+                table,
+                keys.iter()
+                    .map(|x| format!(r#""n"."{1}" IS "{0}"."{1}""#, table, x.0))
+                    .collect::<Vec<_>>()
+                    .join(" AND ")
+            ))
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+            // Changed rows are updated rather than replaced, replacing would delete referenced rows
+            let changed = sqlx::query(&format!(
+                r#"
+                INSERT INTO target."{1}" ({0})
+                SELECT {0} FROM (SELECT {0} FROM main."{1}" EXCEPT SELECT {0} FROM target."{1}") WHERE true
+                ON CONFLICT ({2}) DO UPDATE SET {3}
+                "#,
+                names,
+                table,
+                keys.iter()
+                    .map(|x| format!(r#""{}""#, x.0))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                columns
+                    .iter()
+                    .map(|x| format!(r#""{0}" = "excluded"."{0}""#, x.0))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+            (removed, changed)
+        };
+        debug!(
+            "Removed {} and added or changed {} rows of {}",
+            removed, changed, table
+        );
+    }
+    tx.commit().await?;
+
+    sqlx::query(r#"ANALYZE "target""#)
+        .execute(&mut *conn)
+        .await?;
+    Ok(true)
+}
+
+/// Removes `dbfile` and its WAL, if there are any
+fn removedb(dbfile: &str) -> Result<()> {
+    if Path::new(dbfile).exists() {
+        fs::remove_file(dbfile)?;
+    }
+    removewal(dbfile)
+}
+
+/// Removes the WAL of `dbfile`, if there is any
+fn removewal(dbfile: &str) -> Result<()> {
+    for file in [format!("{}-wal", dbfile), format!("{}-shm", dbfile)] {
+        if Path::new(&file).exists() {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Tables holding data, leaving out sqlx bookkeeping and the shadow tables of virtual tables,
+/// which their virtual table maintains
+async fn datatables(executor: impl SqliteExecutor<'_>) -> Result<Vec<String>> {
+    let tables: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT "name" FROM main."sqlite_master" AS "m"
+        WHERE "type" = 'table' AND "name" NOT LIKE 'sqlite_%' AND "name" != '_sqlx_migrations'
+        AND NOT EXISTS (
+            SELECT 1 FROM main."sqlite_master" AS "v"
+            WHERE "v"."sql" LIKE 'CREATE VIRTUAL TABLE%' AND "m"."name" LIKE "v"."name" || '\_%' ESCAPE '\'
+        )
+        "#,
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(tables.into_iter().map(|x| x.0).collect())
+}
+
+/// Runs the pending migrations, clears every table and stamps the schema version
+async fn migrate(pool: &SqlitePool, migrator: &Migrator) -> Result<()> {
+    migrator.run(pool).await?;
+
+    let tables = datatables(pool).await?;
+    let mut tx = pool.begin().await?;
+    // Tables are cleared in no particular order, so only check references once all are empty
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut tx)
+        .await?;
+    for table in tables {
+        sqlx::query(&format!(r#"DELETE FROM "{}""#, table))
+            .execute(&mut tx)
+            .await?;
+    }
+    sqlx::query(r#"INSERT INTO "schema_version" ("version") VALUES (?)"#)
+        .bind(version(migrator))
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    stamp(pool, migrator).await
+}
+
+/// Lets tools identify our databases and their layout without reading any table
+async fn stamp(pool: &SqlitePool, migrator: &Migrator) -> Result<()> {
+    sqlx::query(&format!("PRAGMA application_id = {}", APPLICATION_ID))
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!("PRAGMA user_version = {}", version(migrator)))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Column of a generated table
+pub struct Column {
+    pub name: String,
+    /// Declared as `INTEGER`, every other column holds text
+    pub integer: bool,
+}
+
+/// Tables of a generated database with their columns, leaving out sqlx bookkeeping and the
+/// full-text search index
+pub async fn tables(pool: &SqlitePool) -> Result<Vec<(String, Vec<Column>)>> {
+    let names: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT "name" FROM "sqlite_master" AS "m"
+        WHERE "type" = 'table' AND "name" NOT LIKE 'sqlite_%' AND "name" != '_sqlx_migrations'
+        AND "sql" NOT LIKE 'CREATE VIRTUAL TABLE%'
+        AND NOT EXISTS (
+            SELECT 1 FROM "sqlite_master" AS "v"
+            WHERE "v"."sql" LIKE 'CREATE VIRTUAL TABLE%' AND "m"."name" LIKE "v"."name" || '\_%' ESCAPE '\'
+        )
+        ORDER BY "rootpage"
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut tables = Vec::new();
+    for (name,) in names {
+        let columns: Vec<(String, String)> =
+            sqlx::query_as(r#"SELECT "name", "type" FROM pragma_table_info(?) ORDER BY "cid""#)
+                .bind(&name)
+                .fetch_all(pool)
+                .await?;
+        let columns = columns
+            .into_iter()
+            .map(|(name, coltype)| Column {
+                name,
+                integer: coltype.eq_ignore_ascii_case("INTEGER"),
+            })
+            .collect();
+        tables.push((name, columns));
+    }
+    Ok(tables)
+}
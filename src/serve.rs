@@ -0,0 +1,436 @@
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    extract::{Path as UrlPath, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+
+use crate::{
+    feed, graphql, grpc, history, metrics,
+    query::{self, json, Database, Package, PackageSummary},
+};
+
+/// Results per page unless asked for fewer or more
+pub const PER_PAGE: u32 = 50;
+
+/// Most results a page can have
+const MAX_PER_PAGE: u32 = 500;
+
+/// Databases the API reads from
+pub struct Databases {
+    pub packages: Database,
+    pub options: Option<SqlitePool>,
+    pub history: Option<SqlitePool>,
+}
+
+/// Serves the package database `dbfile` and, when generated, `nixosoptions.db` next to it as a
+/// JSON API on `listen`:
+///
+/// - `/packages?search=&page=&per_page=` lists packages, matching the search against the names
+///   and descriptions
+/// - `/packages/<attribute>` shows a package along with its metadata
+/// - `/options?search=&page=&per_page=` lists NixOS options, matching the search against the
+///   option names
+/// - `/updates.atom?channel=&packages=` is an Atom feed of the package version bumps recorded in
+///   `history.db` next to it, only of the comma separated packages when given
+/// - `/graphql` answers GraphQL queries for packages along with their metadata, maintainers and
+///   licenses, and serves GraphiQL to browsers
+/// - `/metrics` exposes when the package database was generated and its package count to
+///   Prometheus
+/// - `/healthz` answers 503 unless the databases are present and can be read
+/// - `/readyz` also answers 503 when the package database was generated longer than `maxage` ago
+///
+/// With `grpclisten`, the `nixdata.Packages` gRPC service of `proto/nixdata.proto` is served on it
+/// as well.
+pub async fn serve(
+    dbfile: &str,
+    listen: SocketAddr,
+    grpclisten: Option<SocketAddr>,
+    maxage: Option<Duration>,
+) -> Result<()> {
+    if !Path::new(dbfile).exists() {
+        return Err(anyhow!("{} has not been generated", dbfile));
+    }
+    let optionsfile = Path::new(dbfile)
+        .with_file_name("nixosoptions.db")
+        .to_string_lossy()
+        .to_string();
+    let optionsdb = if Path::new(&optionsfile).exists() {
+        Some(readonly(&optionsfile).await?)
+    } else {
+        info!(
+            "{} has not been generated, not serving options",
+            optionsfile
+        );
+        None
+    };
+    let historyfile = Path::new(dbfile)
+        .with_file_name(history::HISTORY_DB)
+        .to_string_lossy()
+        .to_string();
+    let historydb = if Path::new(&historyfile).exists() {
+        Some(readonly(&historyfile).await?)
+    } else {
+        None
+    };
+    let files = std::iter::once(dbfile.to_string())
+        .chain(optionsdb.is_some().then_some(optionsfile))
+        .collect();
+    let dbs = Arc::new(Databases {
+        packages: Database::open(dbfile).await?,
+        options: optionsdb,
+        history: historydb,
+    });
+    let health = Arc::new(Health {
+        dbs: dbs.clone(),
+        files,
+        maxage,
+    });
+
+    let app = Router::new()
+        .route("/packages", get(packages))
+        .route("/packages/:attribute", get(package))
+        .route("/options", get(options))
+        .route("/updates.atom", get(updates))
+        .route("/metrics", get(prometheus))
+        .with_state(dbs.clone())
+        .merge(
+            Router::new()
+                .route("/healthz", get(healthz))
+                .route("/readyz", get(readyz))
+                .with_state(health),
+        )
+        .merge(
+            Router::new()
+                .route("/graphql", get(graphql::graphiql).post(graphql::graphql))
+                .with_state(graphql::schema(dbs.clone())),
+        );
+    info!("Serving {} on http://{}", dbfile, listen);
+    let http = async {
+        axum::Server::try_bind(&listen)?
+            .serve(app.into_make_service())
+            .await?;
+        Ok(())
+    };
+    match grpclisten {
+        Some(grpclisten) => tokio::try_join!(http, grpc::serve(dbs, grpclisten)).map(|_| ()),
+        None => http.await,
+    }
+}
+
+/// Opens `dbfile` for reading only, generations update it in place while it is served
+async fn readonly(dbfile: &str) -> Result<SqlitePool> {
+    Ok(SqlitePool::connect(&format!("sqlite://{}?mode=ro", dbfile)).await?)
+}
+
+/// Query of the list endpoints
+#[derive(Deserialize)]
+pub struct Search {
+    pub search: Option<String>,
+    #[serde(default = "firstpage")]
+    pub page: u32,
+    #[serde(default = "perpage")]
+    pub per_page: u32,
+}
+
+fn firstpage() -> u32 {
+    1
+}
+
+fn perpage() -> u32 {
+    PER_PAGE
+}
+
+impl Search {
+    /// The search as an FTS5 query matching the beginnings of every word of it
+    fn fts(&self) -> Option<String> {
+        query::fts(self.search.as_deref()?)
+    }
+
+    /// The search as a LIKE pattern
+    fn like(&self) -> Option<String> {
+        let search = self.search.as_deref()?.trim();
+        (!search.is_empty()).then(|| {
+            format!(
+                "%{}%",
+                search
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            )
+        })
+    }
+
+    /// LIMIT and OFFSET of the requested page
+    fn limits(&self) -> (u32, u32) {
+        let perpage = self.per_page.clamp(1, MAX_PER_PAGE);
+        (perpage, self.page.max(1).saturating_sub(1) * perpage)
+    }
+
+    fn page<T>(&self, total: i64, items: Vec<T>) -> Page<T> {
+        Page {
+            total,
+            page: self.page.max(1),
+            per_page: self.limits().0,
+            items,
+        }
+    }
+}
+
+/// One page of results
+#[derive(Serialize)]
+pub struct Page<T> {
+    /// Results on every page
+    pub total: i64,
+    pub page: u32,
+    pub per_page: u32,
+    pub items: Vec<T>,
+}
+
+/// A NixOS option
+#[derive(Serialize, FromRow)]
+pub struct NixosOption {
+    pub attribute: String,
+    pub description: Option<String>,
+    #[sqlx(rename = "type")]
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub default: Option<String>,
+    pub example: Option<String>,
+    #[serde(serialize_with = "json")]
+    pub declarations: Option<String>,
+    pub readonly: Option<bool>,
+}
+
+/// The strings of a JSON list column, skipping anything else
+pub fn jsonlist(column: Option<&str>) -> Vec<String> {
+    column
+        .and_then(|x| serde_json::from_str::<Vec<serde_json::Value>>(x).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|x| x.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Failure of a request, answered as `{"error": ...}`
+enum ApiError {
+    NotFound(String),
+    /// The databases can't be served right now
+    Unavailable(String),
+    Internal(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        ApiError::Internal(e.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::NotFound(x) => (StatusCode::NOT_FOUND, x),
+            ApiError::Unavailable(x) => (StatusCode::SERVICE_UNAVAILABLE, x),
+            ApiError::Internal(e) => {
+                error!("{}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            }
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+impl Databases {
+    /// Page of the packages matching `search` in their names or descriptions, best matches first,
+    /// or of every package by attribute
+    pub async fn packages(&self, search: &Search) -> Result<Page<PackageSummary>> {
+        let (limit, offset) = search.limits();
+        let (total, items) = self
+            .packages
+            .page(search.fts().as_deref(), limit.into(), offset.into())
+            .await?;
+        Ok(search.page(total, items))
+    }
+
+    /// Metrics of the package database, when and from what it was generated
+    pub async fn metrics(&self) -> Result<metrics::Metrics> {
+        let info: Option<(String, f64, i64)> = sqlx::query_as(
+            r#"SELECT "channel", CAST(strftime('%s', "generated_at") AS REAL), "package_count"
+            FROM "generation_info""#,
+        )
+        .fetch_optional(&self.packages.pool)
+        .await?;
+        let mut metrics = metrics::Metrics::new();
+        if let Some((channel, generated, packages)) = info {
+            metrics.generated(&channel, generated, packages);
+        }
+        Ok(metrics)
+    }
+
+    /// Page of the NixOS options whose names contain `search`, `None` without an options database
+    pub async fn options(&self, search: &Search) -> Result<Option<Page<NixosOption>>> {
+        let Some(pool) = &self.options else {
+            return Ok(None);
+        };
+        let (limit, offset) = search.limits();
+        let like = search.like().unwrap_or_else(|| "%".to_string());
+        let (total,): (i64,) =
+            sqlx::query_as(r#"SELECT COUNT(*) FROM "options" WHERE "attribute" LIKE ? ESCAPE '\'"#)
+                .bind(&like)
+                .fetch_one(pool)
+                .await?;
+        let items = sqlx::query_as(
+            r#"SELECT "attribute", "description", "type", "default", "example", "declarations",
+                "readonly"
+            FROM "options"
+            WHERE "attribute" LIKE ? ESCAPE '\'
+            ORDER BY "attribute"
+            LIMIT ? OFFSET ?"#,
+        )
+        .bind(&like)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(Some(search.page(total, items)))
+    }
+}
+
+async fn packages(
+    State(dbs): State<Arc<Databases>>,
+    Query(search): Query<Search>,
+) -> Result<Json<Page<PackageSummary>>, ApiError> {
+    Ok(Json(dbs.packages(&search).await?))
+}
+
+async fn package(
+    State(dbs): State<Arc<Databases>>,
+    UrlPath(attribute): UrlPath<String>,
+) -> Result<Json<Package>, ApiError> {
+    dbs.packages
+        .get_package(&attribute)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No package {}", attribute)))
+}
+
+async fn options(
+    State(dbs): State<Arc<Databases>>,
+    Query(search): Query<Search>,
+) -> Result<Json<Page<NixosOption>>, ApiError> {
+    dbs.options(&search)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound("No options database has been generated".to_string()))
+}
+
+/// Query of the feed of updates
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    pub channel: Option<String>,
+    /// Comma separated attributes of the packages to list the updates of
+    pub packages: Option<String>,
+}
+
+async fn updates(
+    State(dbs): State<Arc<Databases>>,
+    Query(query): Query<FeedQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let pool = dbs
+        .history
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("No history database has been generated".to_string()))?;
+    let watchlist = query
+        .packages
+        .iter()
+        .flat_map(|x| x.split(','))
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    let updates = feed::updates(pool, query.channel.as_deref(), &watchlist).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/atom+xml")],
+        feed::atom(query.channel.as_deref(), &updates),
+    ))
+}
+
+async fn prometheus(State(dbs): State<Arc<Databases>>) -> Result<impl IntoResponse, ApiError> {
+    Ok(metrics::respond(&dbs.metrics().await?))
+}
+
+/// What `/healthz` and `/readyz` check
+struct Health {
+    dbs: Arc<Databases>,
+    /// Files of the databases served
+    files: Vec<String>,
+    /// Oldest the package database may be to be ready
+    maxage: Option<Duration>,
+}
+
+impl Health {
+    /// Fails unless every database is present and can be read
+    async fn live(&self) -> Result<()> {
+        if let Some(file) = self.files.iter().find(|x| !Path::new(x).exists()) {
+            return Err(anyhow!("{} is missing", file));
+        }
+        sqlx::query(r#"SELECT 1 FROM "pkgs" LIMIT 1"#)
+            .fetch_optional(&self.dbs.packages.pool)
+            .await
+            .with_context(|| format!("Failed to read {}", self.files[0]))?;
+        if let Some(pool) = &self.dbs.options {
+            sqlx::query(r#"SELECT 1 FROM "options" LIMIT 1"#)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to read the options database")?;
+        }
+        Ok(())
+    }
+
+    /// Also fails when the package database is older than `maxage`
+    async fn ready(&self) -> Result<()> {
+        self.live().await?;
+        let Some(maxage) = self.maxage else {
+            return Ok(());
+        };
+        let age: Option<(i64,)> = sqlx::query_as(
+            r#"SELECT CAST(strftime('%s', 'now') AS INTEGER)
+                - CAST(strftime('%s', "generated_at") AS INTEGER)
+            FROM "generation_info""#,
+        )
+        .fetch_optional(&self.dbs.packages.pool)
+        .await?;
+        let (age,) = age.ok_or_else(|| anyhow!("{} has no generation info", self.files[0]))?;
+        if age > maxage.as_secs() as i64 {
+            return Err(anyhow!(
+                "{} was generated {}s ago, more than {}s",
+                self.files[0],
+                age,
+                maxage.as_secs()
+            ));
+        }
+        Ok(())
+    }
+}
+
+async fn healthz(State(health): State<Arc<Health>>) -> Result<Json<serde_json::Value>, ApiError> {
+    health
+        .live()
+        .await
+        .map_err(|e| ApiError::Unavailable(format!("{:#}", e)))?;
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn readyz(State(health): State<Arc<Health>>) -> Result<Json<serde_json::Value>, ApiError> {
+    health
+        .ready()
+        .await
+        .map_err(|e| ApiError::Unavailable(format!("{:#}", e)))?;
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
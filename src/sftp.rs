@@ -0,0 +1,68 @@
+use std::process::Stdio;
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// Uploads `files`, each a path and the name to publish it as, to the directory `dir` on `host`
+/// with OpenSSH's `sftp`, authenticating without prompts as set up for `ssh`. Each file is
+/// written next to its destination first and renamed over it once complete, so a web server
+/// serving `dir` never serves half of one.
+pub async fn upload(
+    host: &str,
+    port: Option<u16>,
+    dir: &str,
+    files: &[(String, String)],
+) -> Result<()> {
+    let mut batch = String::new();
+    // Creates every missing parent, a leading `-` ignores the directories that exist already
+    let mut parent = if dir.starts_with('/') { "/" } else { "" }.to_string();
+    for component in dir.split('/').filter(|x| !x.is_empty()) {
+        parent.push_str(component);
+        batch.push_str(&format!("-mkdir {}\n", quote(&parent)));
+        parent.push('/');
+    }
+    for (file, name) in files {
+        let partial = format!("{}.{}.part", parent, name);
+        batch.push_str(&format!("put {} {}\n", quote(file), quote(&partial)));
+        // Atomically replaces the previous file on servers with the posix-rename extension
+        batch.push_str(&format!(
+            "rename {} {}\n",
+            quote(&partial),
+            quote(&format!("{}{}", parent, name))
+        ));
+    }
+
+    debug!("Uploading to {}:{} with sftp", host, dir);
+    let mut sftp = Command::new("sftp");
+    if let Some(port) = port {
+        sftp.arg("-P").arg(port.to_string());
+    }
+    let mut child = sftp
+        .arg("-b")
+        .arg("-")
+        .arg(host)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Dropping stdin once written ends the batch
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin.write_all(batch.as_bytes()).await?;
+    drop(stdin);
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to upload to {}:{}: {}",
+            host,
+            dir,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Quotes `path` as an argument of an sftp batch command
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
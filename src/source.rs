@@ -0,0 +1,358 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+};
+
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use log::{debug, info};
+
+use crate::{
+    channel::{self, Mirrors, Release, Validators},
+    compress,
+    eval::{self, Evaluator},
+    events::Events,
+    outputsuptodate, programs,
+    progress::Progress,
+    stream::PackageStream,
+    BuildConfig,
+};
+
+/// Where the packages of a package database come from. Channels, flakes, nixpkgs checkouts and
+/// `packages.json` files are built in, implement it to build package sets of your own.
+pub trait PackageSource: Send {
+    /// Flake reference, path or other name the databases record they were generated from
+    fn name(&self) -> &str;
+
+    /// Looks up the version to build, e.g. a git revision, the databases are only rebuilt when it
+    /// changes. `None` when there is no telling, which always rebuilds.
+    fn resolve(&mut self) -> BoxFuture<'_, Result<Option<String>>>;
+
+    /// Nixpkgs git revision of the version resolved, package positions link into it
+    fn revision(&self) -> Option<&str> {
+        None
+    }
+
+    /// Nixpkgs source tree of the version resolved, its aliases.nix is evaluated for aliases
+    fn tree(&self) -> Option<&str> {
+        None
+    }
+
+    /// The packages of the version resolved, `None` when they are known to be the ones last built,
+    /// e.g. when a channel release republishes them
+    fn packages(&mut self) -> BoxFuture<'_, Result<Option<Packages>>>;
+
+    /// Adds tables of its own to the package database being built at `dbfile`
+    fn extend<'a>(&'a self, _dbfile: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called once the databases were built from the packages, or failed to be when not `built`
+    fn finish(&mut self, _built: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Packages of a source, inserted as they are read
+pub struct Packages(pub(crate) PackageStream);
+
+impl Packages {
+    /// Packages of the `packages.json` read from `reader`
+    pub fn json(reader: impl Read + Send + 'static) -> Self {
+        Packages(PackageStream::parse(reader))
+    }
+
+    /// Packages already parsed, by attribute
+    #[cfg(feature = "models")]
+    pub fn parsed(packages: std::collections::HashMap<String, crate::models::NixosPkg>) -> Self {
+        Packages(PackageStream::from_map(packages))
+    }
+}
+
+/// A nixpkgs or NixOS channel, built from the `packages.json` of its latest release or of the one
+/// of a revision. Its download is skipped when a release republishes the packages last built.
+pub(crate) struct Channel {
+    mirrors: Mirrors,
+    channel: String,
+    /// Release to build, the latest one is looked up when resolving unless given
+    release: Option<Release>,
+    /// Nixpkgs git revision of `release`
+    revision: Option<String>,
+    pkgsfile: String,
+    /// Where the validators of the `packages.json` built are recorded
+    validatorsfile: String,
+    /// Validators of the `packages.json` last built, while everything built from it is there
+    validators: Option<Validators>,
+    /// Validators of the `packages.json` downloaded
+    downloaded: Option<Validators>,
+    /// Import the programs.sqlite of nixos-* channels
+    programs: bool,
+    progress: Progress,
+    events: Events,
+}
+
+impl Channel {
+    /// Builds `channel` with the markers and downloads in `sourcedir` and the databases in
+    /// `outdir`
+    pub(crate) fn new(
+        mirrors: &Mirrors,
+        channel: &str,
+        (sourcedir, outdir): (&str, &str),
+        config: &BuildConfig,
+    ) -> Self {
+        let validatorsfile = format!("{}/{}", sourcedir, config.marker("etag"));
+        Channel {
+            mirrors: mirrors.clone(),
+            channel: channel.to_string(),
+            release: None,
+            revision: None,
+            pkgsfile: format!("{}/packages.json.br", sourcedir),
+            validators: Validators::load(&validatorsfile)
+                .filter(|x| x.channel == channel && outputsuptodate(sourcedir, outdir, config)),
+            validatorsfile,
+            downloaded: None,
+            programs: config.programs,
+            progress: config.progress,
+            events: config.events.clone(),
+        }
+    }
+
+    /// Builds `release`, the one of nixpkgs revision `rev`, instead of the latest one
+    pub(crate) fn at(mut self, release: Release, rev: &str) -> Self {
+        self.release = Some(release);
+        self.revision = Some(rev.to_string());
+        self
+    }
+}
+
+impl PackageSource for Channel {
+    fn name(&self) -> &str {
+        &self.channel
+    }
+
+    fn resolve(&mut self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let release = match self.release.take() {
+                Some(release) => release,
+                None => {
+                    debug!("Checking nixpkgs version");
+                    self.mirrors
+                        .latestrelease(&self.channel)
+                        .await?
+                        .ok_or_else(|| anyhow!("Could not find latest nixpkgs version"))?
+                }
+            };
+            debug!("Latest nixpkgs version: {}", release.name);
+            let version = channel::releaseversion(&release.name).to_string();
+            info!("latestnixpkgsver: {}", version);
+            self.release = Some(release);
+            Ok(Some(version))
+        })
+    }
+
+    fn revision(&self) -> Option<&str> {
+        self.revision.as_deref()
+    }
+
+    fn packages(&mut self) -> BoxFuture<'_, Result<Option<Packages>>> {
+        Box::pin(async move {
+            let release = self.release.as_ref().context("Channel is not resolved")?;
+            // Only used to link package positions, so a missing revision is not fatal
+            if self.revision.is_none() {
+                self.revision = release
+                    .revision(&self.mirrors.client)
+                    .await
+                    .map_err(|e| debug!("Failed to get revision of {}: {}", release.name, e))
+                    .ok();
+            }
+
+            debug!("Downloading packages.json.br");
+            let Some(headers) = release
+                .downloadto(
+                    &self.mirrors,
+                    "packages.json.br",
+                    &self.pkgsfile,
+                    self.validators.as_ref(),
+                    self.progress,
+                )
+                .await
+                .context("Failed to download latest packages.json")?
+            else {
+                info!(
+                    "packages.json.br of {} is unchanged, skipping",
+                    release.name
+                );
+                return Ok(None);
+            };
+            debug!("Successfully downloaded packages.json.br");
+            self.downloaded = Some(Validators::new(&self.channel, &headers));
+            debug!("Reading packages.json.br");
+            Ok(Some(Packages(PackageStream::parse(channel::decompress(
+                &self.pkgsfile,
+                self.progress,
+            )?))))
+        })
+    }
+
+    fn extend<'a>(&'a self, dbfile: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match &self.release {
+                Some(release) if self.programs && self.channel.starts_with("nixos-") => {
+                    programs::importprograms(&self.mirrors, release, dbfile).await
+                }
+                _ if self.programs => {
+                    self.events.warning(format!(
+                        "{} does not ship programs.sqlite, skipping",
+                        self.channel
+                    ));
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn finish(&mut self, built: bool) -> Result<()> {
+        fs::remove_file(&self.pkgsfile)?;
+        match &self.downloaded {
+            Some(validators) if built => validators.save(&self.validatorsfile),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A nixpkgs flake, evaluated at the revision it locks to
+pub struct Flake {
+    flakeref: String,
+    evaluator: Evaluator,
+    /// Store path and revision of the locked flake
+    locked: Option<(String, String)>,
+}
+
+impl Flake {
+    pub fn new(flakeref: &str, evaluator: Evaluator) -> Self {
+        Flake {
+            flakeref: flakeref.to_string(),
+            evaluator,
+            locked: None,
+        }
+    }
+}
+
+impl PackageSource for Flake {
+    fn name(&self) -> &str {
+        &self.flakeref
+    }
+
+    fn resolve(&mut self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let (path, rev) = eval::flakesource(&self.flakeref).await?;
+            info!("latestflakerev: {}", rev);
+            self.locked = Some((path, rev.clone()));
+            Ok(Some(rev))
+        })
+    }
+
+    fn revision(&self) -> Option<&str> {
+        self.locked.as_ref().map(|x| x.1.as_str())
+    }
+
+    fn tree(&self) -> Option<&str> {
+        self.locked.as_ref().map(|x| x.0.as_str())
+    }
+
+    fn packages(&mut self) -> BoxFuture<'_, Result<Option<Packages>>> {
+        Box::pin(async move {
+            let path = match &self.locked {
+                Some((path, _)) => path.clone(),
+                None => eval::flakesource(&self.flakeref).await?.0,
+            };
+            Ok(Some(Packages(self.evaluator.evaluate(&path))))
+        })
+    }
+}
+
+/// A local nixpkgs checkout, versioned by its git revision while its tree is clean
+pub struct Checkout {
+    path: String,
+    evaluator: Evaluator,
+    rev: Option<String>,
+}
+
+impl Checkout {
+    pub fn new(path: &str, evaluator: Evaluator) -> Self {
+        Checkout {
+            path: path.to_string(),
+            evaluator,
+            rev: None,
+        }
+    }
+}
+
+impl PackageSource for Checkout {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn resolve(&mut self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            self.rev = eval::localrevision(&self.path);
+            match &self.rev {
+                Some(rev) => info!("localrev: {}", rev),
+                None => debug!(
+                    "{} is not a clean git checkout, always rebuilding",
+                    self.path
+                ),
+            }
+            Ok(self.rev.clone())
+        })
+    }
+
+    fn revision(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    fn tree(&self) -> Option<&str> {
+        Some(&self.path)
+    }
+
+    fn packages(&mut self) -> BoxFuture<'_, Result<Option<Packages>>> {
+        Box::pin(async move { Ok(Some(Packages(self.evaluator.evaluate(&self.path)))) })
+    }
+}
+
+/// A `packages.json`, or `packages.json.br`, on disk, versioned by its checksum
+pub struct JsonFile {
+    file: String,
+}
+
+impl JsonFile {
+    pub fn new(file: &str) -> Self {
+        JsonFile {
+            file: file.to_string(),
+        }
+    }
+}
+
+impl PackageSource for JsonFile {
+    fn name(&self) -> &str {
+        &self.file
+    }
+
+    fn resolve(&mut self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move { Ok(Some(compress::sha256(&self.file)?)) })
+    }
+
+    fn packages(&mut self) -> BoxFuture<'_, Result<Option<Packages>>> {
+        Box::pin(async move { Ok(Some(Packages(PackageStream::parse(open(&self.file)?)))) })
+    }
+}
+
+/// Reads the `packages.json` at `file`, decompressing it when it ends in `.br`
+pub(crate) fn open(file: &str) -> Result<Box<dyn Read + Send>> {
+    Ok(if file.ends_with(".br") {
+        Box::new(channel::decompress(file, Progress::Hidden)?)
+    } else {
+        Box::new(BufReader::new(File::open(file)?))
+    })
+}
@@ -0,0 +1,96 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use log::debug;
+use sqlx::SqlitePool;
+
+use crate::schema;
+
+/// File name of the part of the package database named after `stem` for `system`
+pub fn dbname(stem: &str, system: &str) -> String {
+    format!("{}-{}.db", stem, system)
+}
+
+/// Copies the generated `dbfile` into one database per system in `outdir` named after `stem`,
+/// each only holding the packages available on its system
+pub async fn splitbysystem(
+    dbfile: &str,
+    outdir: &str,
+    stem: &str,
+    systems: &[String],
+) -> Result<()> {
+    let pool = SqlitePool::connect(&format!("sqlite://{}", dbfile)).await?;
+    for system in systems {
+        let splitfile = format!("{}/{}", outdir, dbname(stem, system));
+        debug!("Splitting packages for {} into {}", system, splitfile);
+        // VACUUM INTO refuses to overwrite
+        if Path::new(&splitfile).exists() {
+            fs::remove_file(&splitfile)?;
+        }
+        sqlx::query("VACUUM INTO ?")
+            .bind(&splitfile)
+            .execute(&pool)
+            .await?;
+
+        let split = SqlitePool::connect(&format!("sqlite://{}", splitfile)).await?;
+        removeunsupported(&split, system).await?;
+        // Reclaims the space of the removed rows
+        sqlx::query("VACUUM").execute(&split).await?;
+        split.close().await;
+    }
+    Ok(())
+}
+
+/// Removes every package not available on `system` along with everything recorded about it
+async fn removeunsupported(pool: &SqlitePool, system: &str) -> Result<()> {
+    // Aliases point at attributes that need not be packages, so they are all kept
+    let tables = schema::tables(pool)
+        .await?
+        .into_iter()
+        .filter(|(table, columns)| {
+            !["pkgs", "aliases"].contains(&table.as_str())
+                && columns.iter().any(|x| x.name == "attribute")
+        })
+        .map(|x| x.0)
+        .chain(["pkgs_fts".to_string()])
+        .collect::<Vec<_>>();
+
+    let mut tx = pool.begin().await?;
+    // Tables reference pkgs, which is emptied last
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(
+        r#"
+        CREATE TEMP TABLE "unsupported" AS SELECT "attribute" FROM "pkgs"
+        WHERE NOT EXISTS (SELECT 1 FROM json_each("pkgs"."systems") WHERE "value" = ?)
+        "#,
+    )
+    .bind(system)
+    .execute(&mut tx)
+    .await?;
+    for table in tables.iter().map(String::as_str).chain(["pkgs"]) {
+        sqlx::query(&format!(
+            r#"DELETE FROM "{}" WHERE "attribute" IN (SELECT "attribute" FROM "unsupported")"#,
+            table
+        ))
+        .execute(&mut tx)
+        .await?;
+    }
+    sqlx::query(r#"DROP TABLE "unsupported""#)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(r#"DELETE FROM "programs" WHERE "system" != ?"#)
+        .bind(system)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(r#"UPDATE "pkgs" SET "system" = ?"#)
+        .bind(system)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(r#"UPDATE "generation_info" SET "package_count" = (SELECT COUNT(*) FROM "pkgs")"#)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, fmt, io::Read};
+
+use anyhow::{anyhow, Result};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde_json::Value;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::models::NixosPkg;
+
+/// Packages parsed ahead of the database inserts, bounding how many are held at once
+const PACKAGE_BUFFER: usize = 1024;
+
+/// Packages handed out one at a time while their source is still being read
+pub struct PackageStream {
+    packages: mpsc::Receiver<(String, NixosPkg)>,
+    producer: JoinHandle<Result<Vec<ParseError>>>,
+}
+
+/// A package that could not be parsed and is left out
+pub struct ParseError {
+    pub attribute: String,
+    pub error: String,
+}
+
+impl PackageStream {
+    /// Parses the `packages` object of a `packages.json` read from `reader` on a blocking thread,
+    /// skipping the packages that don't parse
+    pub fn parse(reader: impl Read + Send + 'static) -> Self {
+        Self::spawn(move |tx| {
+            let mut errors = Vec::new();
+            let mut de = serde_json::Deserializer::from_reader(reader);
+            PackagesFile(&tx, &mut errors).deserialize(&mut de)?;
+            de.end()?;
+            Ok(errors)
+        })
+    }
+
+    /// Hands out packages that were already evaluated
+    pub fn from_map(packages: HashMap<String, NixosPkg>) -> Self {
+        Self::spawn(move |tx| {
+            for package in packages {
+                tx.blocking_send(package)
+                    .map_err(|_| anyhow!("Package receiver closed"))?;
+            }
+            Ok(Vec::new())
+        })
+    }
+
+    /// Hands out the packages `producer` sends from a blocking thread, it returns those it skipped
+    pub(crate) fn spawn(
+        producer: impl FnOnce(mpsc::Sender<(String, NixosPkg)>) -> Result<Vec<ParseError>>
+            + Send
+            + 'static,
+    ) -> Self {
+        let (tx, packages) = mpsc::channel(PACKAGE_BUFFER);
+        Self {
+            packages,
+            producer: tokio::task::spawn_blocking(move || producer(tx)),
+        }
+    }
+
+    /// Next package, `None` once all are handed out or reading them failed
+    pub async fn next(&mut self) -> Option<(String, NixosPkg)> {
+        self.packages.recv().await
+    }
+
+    /// Waits for the source to be read to the end, returning the packages skipped as they failed
+    /// to parse, or why reading it failed
+    pub async fn finish(self) -> Result<Vec<ParseError>> {
+        drop(self.packages);
+        self.producer.await?
+    }
+}
+
+/// Parses a top level object of packages, as `nix-env -qa --json` prints, from `reader`, sending
+/// each package to `tx` as soon as it is parsed and returning those that don't parse
+pub(crate) fn packages(
+    reader: impl Read,
+    tx: &mpsc::Sender<(String, NixosPkg)>,
+) -> Result<Vec<ParseError>> {
+    let mut errors = Vec::new();
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    Packages(tx, &mut errors).deserialize(&mut de)?;
+    de.end()?;
+    Ok(errors)
+}
+
+/// Top level object of `packages.json`, sends its packages while skipping everything else
+struct PackagesFile<'a>(
+    &'a mpsc::Sender<(String, NixosPkg)>,
+    &'a mut Vec<ParseError>,
+);
+
+impl<'de> DeserializeSeed<'de> for PackagesFile<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for PackagesFile<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a packages.json object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "packages" {
+                map.next_value_seed(Packages(self.0, &mut *self.1))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `packages` object of `packages.json` or the output of `nix-env -qa --json`, sends each package as soon as it is parsed
+struct Packages<'a>(
+    &'a mpsc::Sender<(String, NixosPkg)>,
+    &'a mut Vec<ParseError>,
+);
+
+impl<'de> DeserializeSeed<'de> for Packages<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Packages<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object of packages")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        // Parsed in two steps so a package that doesn't fit the models only loses itself
+        while let Some((attribute, value)) = map.next_entry::<String, Value>()? {
+            match serde_json::from_value(value) {
+                Ok(package) => self
+                    .0
+                    .blocking_send((attribute, package))
+                    .map_err(|_| de::Error::custom("Package receiver closed"))?,
+                Err(e) => self.1.push(ParseError {
+                    attribute,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        Ok(())
+    }
+}
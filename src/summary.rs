@@ -0,0 +1,134 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::metrics;
+
+/// File the summary of a run is written to in its output directory
+const SUMMARY_FILE: &str = "summary.json";
+
+/// What happened to a database
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    UpToDate,
+    Updated,
+    Failed,
+}
+
+/// Outcome of one package database
+#[derive(Serialize, Clone, Debug)]
+pub struct Database {
+    /// Channel, flake reference or nixpkgs path
+    pub source: String,
+    pub status: Status,
+    /// Version or revision the database is built from
+    pub version: Option<String>,
+    /// Packages in the database
+    pub packages: Option<i64>,
+    /// Seconds spent on it
+    pub duration: f64,
+    pub error: Option<String>,
+}
+
+/// Outcome of a generate run, written as `summary.json`
+#[derive(Serialize, Clone, Debug)]
+pub struct Summary {
+    pub status: Status,
+    /// Seconds the whole run took
+    pub duration: f64,
+    pub databases: Vec<Database>,
+    /// Warnings about the packages built during the run, as sent to the listeners
+    pub warnings: Vec<String>,
+    #[serde(skip)]
+    start: Instant,
+}
+
+impl Summary {
+    pub(crate) fn new() -> Self {
+        Summary {
+            status: Status::UpToDate,
+            duration: 0.0,
+            databases: Vec::new(),
+            warnings: Vec::new(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the `result` of building `dbfile` from `source`, started at `start`
+    pub(crate) async fn add(
+        &mut self,
+        source: &str,
+        dbfile: &str,
+        start: Instant,
+        result: &Result<bool>,
+    ) {
+        let status = match result {
+            Ok(true) => Status::Updated,
+            Ok(false) => Status::UpToDate,
+            Err(_) => Status::Failed,
+        };
+        let (version, packages) = match generationinfo(dbfile).await {
+            Some((version, packages)) if status != Status::Failed => (version, Some(packages)),
+            _ => (None, None),
+        };
+        metrics::generation(
+            source,
+            start.elapsed(),
+            result.as_ref().ok().copied(),
+            packages,
+        );
+        self.databases.push(Database {
+            source: source.to_string(),
+            status,
+            version,
+            packages,
+            duration: start.elapsed().as_secs_f64(),
+            error: result.as_ref().err().map(|x| x.to_string()),
+        });
+    }
+
+    /// Finishes the summary with the `warnings` of the run and writes it to `outdir` unless
+    /// `outdir` is `None`
+    pub(crate) fn finish(mut self, warnings: Vec<String>, outdir: Option<&str>) -> Result<Self> {
+        self.duration = self.start.elapsed().as_secs_f64();
+        self.warnings = warnings;
+        self.status = if self.databases.iter().any(|x| x.status == Status::Failed) {
+            Status::Failed
+        } else if self.databases.iter().any(|x| x.status == Status::Updated) {
+            Status::Updated
+        } else {
+            Status::UpToDate
+        };
+        if let Some(outdir) = outdir.filter(|x| Path::new(x).exists()) {
+            let mut writer = BufWriter::new(File::create(format!("{}/{}", outdir, SUMMARY_FILE))?);
+            serde_json::to_writer_pretty(&mut writer, &self)?;
+            writer.flush()?;
+        }
+        Ok(self)
+    }
+}
+
+/// Version and package count recorded in `dbfile`, if it was generated
+async fn generationinfo(dbfile: &str) -> Option<(Option<String>, i64)> {
+    if !Path::new(dbfile).exists() {
+        return None;
+    }
+    let pool = SqlitePool::connect(&format!("sqlite://{}?mode=ro", dbfile))
+        .await
+        .ok()?;
+    let info = sqlx::query_as(r#"SELECT "version", "package_count" FROM "generation_info""#)
+        .fetch_optional(&pool)
+        .await
+        .ok()
+        .flatten();
+    pool.close().await;
+    info
+}
@@ -0,0 +1,180 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use sqlx::{migrate::Migrator, SqlitePool};
+
+use crate::{history, schema};
+
+/// Outcome of one check of a database
+pub struct Check {
+    /// File name of the database checked
+    pub database: String,
+    pub check: &'static str,
+    /// What is wrong, `None` when the check passed
+    pub problem: Option<String>,
+}
+
+/// Checks the databases generated into `dir` with the package database `dbname`: that SQLite
+/// finds them intact, that they have the latest schema, that the package database holds as many
+/// packages as it was built with and is the version of the version marker, and that every
+/// package has its metadata
+pub async fn verify(dir: &str, dbname: &str) -> Result<Vec<Check>> {
+    let stem = Path::new(dbname)
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .unwrap_or(dbname);
+    let mut checks = Vec::new();
+    let dbfile = format!("{}/{}", dir, dbname);
+    let Some(pool) = open(&dbfile, &schema::NIXPKGS, &mut checks).await? else {
+        return Ok(checks);
+    };
+    let mut check = |check, problem| {
+        checks.push(Check {
+            database: dbname.to_string(),
+            check,
+            problem,
+        })
+    };
+
+    let (packages,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM "pkgs""#)
+        .fetch_one(&pool)
+        .await?;
+    let info: Option<(Option<String>, i64)> =
+        sqlx::query_as(r#"SELECT "version", "package_count" FROM "generation_info""#)
+            .fetch_optional(&pool)
+            .await?;
+    check(
+        "package count",
+        match &info {
+            None => Some("No generation_info row".to_string()),
+            Some((_, count)) if *count != packages => {
+                Some(format!("{} packages, built with {}", packages, count))
+            }
+            Some(_) => None,
+        },
+    );
+    // Sources without a version have no marker
+    if let Ok(marker) = fs::read_to_string(format!("{}/{}.ver", dir, stem)) {
+        let version = info.as_ref().and_then(|x| x.0.as_deref()).unwrap_or("");
+        check(
+            "version",
+            (marker.trim() != version).then(|| {
+                format!(
+                    "Built from {}, but {}.ver is {}",
+                    version,
+                    stem,
+                    marker.trim()
+                )
+            }),
+        );
+    }
+    let (nometa, nopkg): (i64, i64) = sqlx::query_as(
+        r#"SELECT
+            (SELECT COUNT(*) FROM "pkgs" WHERE "attribute" NOT IN (SELECT "attribute" FROM "meta")),
+            (SELECT COUNT(*) FROM "meta" WHERE "attribute" NOT IN (SELECT "attribute" FROM "pkgs"))"#,
+    )
+    .fetch_one(&pool)
+    .await?;
+    check(
+        "metadata",
+        (nometa > 0 || nopkg > 0).then(|| {
+            format!(
+                "{} packages without metadata, metadata of {} missing packages",
+                nometa, nopkg
+            )
+        }),
+    );
+    pool.close().await;
+
+    let versionsdb = format!("{}_versions.db", stem);
+    if let Some(pool) = open(
+        &format!("{}/{}", dir, versionsdb),
+        &schema::VERSIONS,
+        &mut checks,
+    )
+    .await?
+    {
+        let (versions,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM "pkgs""#)
+            .fetch_one(&pool)
+            .await?;
+        pool.close().await;
+        checks.push(Check {
+            database: versionsdb,
+            check: "package count",
+            problem: (versions != packages)
+                .then(|| format!("{} packages, {} has {}", versions, dbname, packages)),
+        });
+    }
+
+    // Only checked when generated
+    for (name, migrator) in [
+        ("nixosoptions.db", &schema::OPTIONS),
+        ("darwinoptions.db", &schema::OPTIONS),
+        (history::HISTORY_DB, &schema::HISTORY),
+    ] {
+        let file = format!("{}/{}", dir, name);
+        if Path::new(&file).exists() {
+            if let Some(pool) = open(&file, migrator, &mut checks).await? {
+                pool.close().await;
+            }
+        }
+    }
+    Ok(checks)
+}
+
+/// Opens `dbfile` and checks its integrity and that it has the latest schema of `migrator`,
+/// returning it unless it is missing or corrupt
+async fn open(
+    dbfile: &str,
+    migrator: &Migrator,
+    checks: &mut Vec<Check>,
+) -> Result<Option<SqlitePool>> {
+    let database = Path::new(dbfile)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut check = |check, problem| {
+        checks.push(Check {
+            database: database.clone(),
+            check,
+            problem,
+        })
+    };
+    if !Path::new(dbfile).exists() {
+        check("exists", Some(format!("{} does not exist", dbfile)));
+        return Ok(None);
+    }
+    let pool = SqlitePool::connect(&format!("sqlite://{}?mode=ro", dbfile)).await?;
+    let results: Vec<(String,)> = match sqlx::query_as("PRAGMA integrity_check")
+        .fetch_all(&pool)
+        .await
+    {
+        Ok(x) => x,
+        Err(e) => vec![(e.to_string(),)],
+    };
+    if results.len() != 1 || results[0].0 != "ok" {
+        let results = results.into_iter().map(|x| x.0).collect::<Vec<_>>();
+        check("integrity", Some(results.join(", ")));
+        pool.close().await;
+        return Ok(None);
+    }
+    check("integrity", None);
+
+    let latest = schema::version(migrator);
+    let version: Result<(i64,), _> =
+        sqlx::query_as(r#"SELECT MAX("version") FROM "schema_version""#)
+            .fetch_one(&pool)
+            .await;
+    let (userversion,): (i64,) = sqlx::query_as("PRAGMA user_version")
+        .fetch_one(&pool)
+        .await?;
+    check(
+        "schema",
+        match version {
+            Ok((x,)) if x == latest && userversion == latest => None,
+            Ok((x,)) => Some(format!("Schema version {}, the latest is {}", x, latest)),
+            Err(e) => Some(format!("No schema version: {}", e)),
+        },
+    );
+    Ok(Some(pool))
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    models::{NixosPkg, Platform, StrOrVec},
+    Row,
+};
+
+/// Most packages kept as samples of each warning
+const SAMPLES: usize = 5;
+
+/// Packages with a field shape the tables don't hold, a few of them with their value
+#[derive(Default)]
+struct Warning {
+    count: usize,
+    samples: Vec<Value>,
+}
+
+/// Package fields that are lost or only partly stored, by field and what is wrong with them
+#[derive(Default)]
+pub struct Warnings(BTreeMap<(&'static str, &'static str), Warning>);
+
+impl Warnings {
+    /// Records the fields of package `pkg` that don't fit the tables
+    pub fn check(&mut self, pkg: &str, data: &NixosPkg) {
+        let meta = &data.meta;
+        for license in meta.license.iter().flat_map(|x| x.licenses()) {
+            if license.name().is_none() {
+                self.add(pkg, "meta.license", "license without a name", &license);
+            }
+        }
+        for (field, platforms) in [
+            ("meta.platforms", &meta.platforms),
+            ("meta.badPlatforms", &meta.badplatforms),
+            ("meta.hydraPlatforms", &meta.hydraplatforms),
+        ] {
+            if let Some(Platform::Unknown(x)) = platforms {
+                self.add(pkg, field, "not a list of systems", x);
+            }
+        }
+        match &meta.homepage {
+            Some(StrOrVec::List(x)) if x.is_empty() => {
+                self.add(pkg, "meta.homepage", "empty list", x)
+            }
+            Some(x) => {
+                for homepage in x.all() {
+                    if !homepage.contains("://") {
+                        self.add(pkg, "meta.homepage", "not a URL", homepage);
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+
+    fn add(&mut self, pkg: &str, field: &'static str, kind: &'static str, value: impl Serialize) {
+        let warning = self.0.entry((field, kind)).or_default();
+        warning.count += 1;
+        if warning.samples.len() < SAMPLES {
+            warning
+                .samples
+                .push(json!({ "attribute": pkg, "value": value }));
+        }
+    }
+
+    /// Adds the warnings of `other`, keeping the samples already taken first
+    pub fn merge(&mut self, other: Warnings) {
+        for (key, other) in other.0 {
+            let warning = self.0.entry(key).or_default();
+            warning.count += other.count;
+            let room = SAMPLES - warning.samples.len();
+            warning.samples.extend(other.samples.into_iter().take(room));
+        }
+    }
+
+    /// Packages warned about, once for every warning
+    pub fn count(&self) -> usize {
+        self.0.values().map(|x| x.count).sum()
+    }
+
+    /// Rows of the `warnings` table
+    pub fn rows(&self) -> Result<Vec<Row>> {
+        let mut rows = Vec::new();
+        for ((field, kind), warning) in &self.0 {
+            rows.push(vec![
+                (*field).into(),
+                (*kind).into(),
+                warning.count.into(),
+                serde_json::to_string(&warning.samples)?.into(),
+            ]);
+        }
+        Ok(rows)
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{channel, compress};
+
+/// Body of the request announcing a rebuilt package database
+#[derive(Serialize)]
+pub struct Payload {
+    /// Channel, flake reference or nixpkgs path
+    channel: String,
+    version: String,
+    /// Nixpkgs git revision, when known
+    revision: Option<String>,
+    packages: i64,
+    /// SHA-256 of each generated file by name
+    artifacts: BTreeMap<String, String>,
+}
+
+impl Payload {
+    /// Describes the package database `dbfile` along with `files`, each a path and its name
+    pub async fn load(dbfile: &str, files: &[(String, String)]) -> Result<Self> {
+        let pool = SqlitePool::connect(&format!("sqlite://{}?mode=ro", dbfile)).await?;
+        let info: Option<(String, String, Option<String>, i64)> = sqlx::query_as(
+            r#"SELECT "channel", "version", "revision", "package_count" FROM "generation_info""#,
+        )
+        .fetch_optional(&pool)
+        .await?;
+        pool.close().await;
+        let (channel, version, revision, packages) =
+            info.ok_or_else(|| anyhow!("{} has no generation info", dbfile))?;
+        Ok(Payload {
+            channel,
+            version,
+            revision,
+            packages,
+            artifacts: files
+                .iter()
+                .map(|(file, name)| Ok((name.clone(), compress::sha256(file)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// POSTs `payload` as JSON to `url`
+pub async fn notify(client: &Client, retries: u32, url: &Url, payload: &Payload) -> Result<()> {
+    debug!("Notifying {}", url);
+    let body = serde_json::to_vec(payload)?;
+    channel::retry(&format!("notifying {}", url), retries, || async {
+        client
+            .post(url.clone())
+            .header("content-type", "application/json")
+            .body(body.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    })
+    .await
+}